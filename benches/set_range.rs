@@ -0,0 +1,72 @@
+//! Compares Map::set_range with repeated Map::set for a filled rectangle, run with
+//! `cargo bench`. The crate has no library, the modules of the map are included directly.
+#![allow(dead_code)]
+
+#[path = "../src/chunk.rs"]
+mod chunk;
+#[path = "../src/history.rs"]
+mod history;
+#[path = "../src/image.rs"]
+mod image;
+#[path = "../src/journal.rs"]
+mod journal;
+#[path = "../src/map.rs"]
+mod map;
+#[path = "../src/tile.rs"]
+mod tile;
+
+use std::time::{Duration, Instant};
+
+use image::STONE;
+use map::Map;
+use tile::Tile;
+
+/// side length of the filled square in tiles
+const SIZE: i32 = 512;
+const RUNS: u32 = 5;
+
+type Tiles = [(i32, i32, i32, Tile)];
+
+fn tiles() -> Vec<(i32, i32, i32, Tile)> {
+    let tile = Tile {
+        bg: Some(STONE),
+        fg: None,
+        fg_rotation: 0,
+        fg2: None,
+    };
+    let mut tiles = vec![];
+    for y in -SIZE / 2..SIZE / 2 {
+        for x in -SIZE / 2..SIZE / 2 {
+            tiles.push((x, y, 0, tile));
+        }
+    }
+    tiles
+}
+
+/// the fastest of RUNS runs, each on a new map
+fn measure(tiles: &Tiles, fill: fn(&mut Map, &Tiles)) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let mut map = Map::new();
+            let start = Instant::now();
+            fill(&mut map, tiles);
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let tiles = tiles();
+    let set = measure(&tiles, |map, tiles| {
+        for &(x, y, z, tile) in tiles {
+            map.set(x, y, z, tile);
+        }
+    });
+    let set_range = measure(&tiles, |map, tiles| {
+        map.set_range(tiles);
+    });
+    println!("{} tiles", tiles.len());
+    println!("set:       {:>8.2} ms", set.as_secs_f64() * 1000.);
+    println!("set_range: {:>8.2} ms", set_range.as_secs_f64() * 1000.);
+}
@@ -0,0 +1,86 @@
+use std::error::Error;
+
+use rzdb::{Data, Db};
+
+pub const BOOKMARK_SLOTS: usize = 9;
+
+/// A stored view: the upper left tile of the screen, the z level and the zoom
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    pub dx: i32,
+    pub dy: i32,
+    pub dz: i32,
+    pub scale: f32,
+}
+
+pub struct Bookmarks {
+    pub slots: [Option<Camera>; BOOKMARK_SLOTS],
+}
+impl Bookmarks {
+    pub fn new() -> Self {
+        Bookmarks {
+            slots: [None; BOOKMARK_SLOTS],
+        }
+    }
+
+    /// Store the bookmarks in the database.
+    /// Data format:
+    /// slot,dx,dy,dz,scale - only for slots which are set
+    pub fn store(&self, db: &mut Db, table_name: &str) -> Result<(), Box<dyn Error>> {
+        db.create_or_replace_table(table_name)?;
+        db.create_column(table_name, "slot")?;
+        db.create_column(table_name, "dx")?;
+        db.create_column(table_name, "dy")?;
+        db.create_column(table_name, "dz")?;
+        db.create_column(table_name, "scale")?;
+        for (slot, camera) in self.slots.iter().enumerate() {
+            if let Some(camera) = camera {
+                db.insert_data(
+                    table_name,
+                    vec![
+                        Data::Int(slot as i64),
+                        Data::Int(camera.dx as i64),
+                        Data::Int(camera.dy as i64),
+                        Data::Int(camera.dz as i64),
+                        Data::Float(camera.scale as f64),
+                    ],
+                )?;
+            }
+        }
+        Ok(())
+    }
+    pub fn parse_table(&mut self, db: &mut Db, table_name: &str) -> Result<(), Box<dyn Error>> {
+        let rows = db.select_from(table_name)?;
+        for row in &rows {
+            match (
+                row.select_at(0)?,
+                row.select_at(1)?,
+                row.select_at(2)?,
+                row.select_at(3)?,
+                row.select_at(4)?,
+            ) {
+                (
+                    Data::Int(slot),
+                    Data::Int(dx),
+                    Data::Int(dy),
+                    Data::Int(dz),
+                    Data::Float(scale),
+                ) if (slot as usize) < BOOKMARK_SLOTS => {
+                    self.slots[slot as usize] = Some(Camera {
+                        dx: dx as i32,
+                        dy: dy as i32,
+                        dz: dz as i32,
+                        scale: scale as f32,
+                    });
+                }
+                _ => {
+                    return Err(Box::new(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "invalid bookmark row",
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+}
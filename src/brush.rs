@@ -0,0 +1,74 @@
+use std::error::Error;
+
+use rzdb::{Data, Db};
+
+use crate::image::ImageId;
+
+pub const BRUSH_SLOTS: usize = 9;
+
+/// A brush preset: the selected image (or any image of a multi image) and the cursor size
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Brush {
+    pub image_id: ImageId,
+    pub cursor_size: i32,
+}
+
+pub struct Brushes {
+    pub slots: [Option<Brush>; BRUSH_SLOTS],
+}
+impl Brushes {
+    pub fn new() -> Self {
+        Brushes {
+            slots: [None; BRUSH_SLOTS],
+        }
+    }
+    /// slot index of the first preset equal to `brush`
+    pub fn active(&self, brush: Brush) -> Option<usize> {
+        self.slots.iter().position(|slot| *slot == Some(brush))
+    }
+
+    /// Store the brush presets in the database.
+    /// Data format:
+    /// slot,image_id,cursor_size - only for slots which are set
+    pub fn store(&self, db: &mut Db, table_name: &str) -> Result<(), Box<dyn Error>> {
+        db.create_or_replace_table(table_name)?;
+        db.create_column(table_name, "slot")?;
+        db.create_column(table_name, "image_id")?;
+        db.create_column(table_name, "cursor_size")?;
+        for (slot, brush) in self.slots.iter().enumerate() {
+            if let Some(brush) = brush {
+                db.insert_data(
+                    table_name,
+                    vec![
+                        Data::Int(slot as i64),
+                        Data::Int(brush.image_id as i64),
+                        Data::Int(brush.cursor_size as i64),
+                    ],
+                )?;
+            }
+        }
+        Ok(())
+    }
+    pub fn parse_table(&mut self, db: &mut Db, table_name: &str) -> Result<(), Box<dyn Error>> {
+        let rows = db.select_from(table_name)?;
+        for row in &rows {
+            match (row.select_at(0)?, row.select_at(1)?, row.select_at(2)?) {
+                (Data::Int(slot), Data::Int(image_id), Data::Int(cursor_size))
+                    if (slot as usize) < BRUSH_SLOTS =>
+                {
+                    self.slots[slot as usize] = Some(Brush {
+                        image_id: image_id as ImageId,
+                        cursor_size: cursor_size as i32,
+                    });
+                }
+                _ => {
+                    return Err(Box::new(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "invalid brush row",
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
 use std::error::Error;
 
-use rzdb::{Data, Db, Row};
+use rzdb::{Data, Row};
 
 use crate::tile::Tile;
 
@@ -9,13 +9,17 @@ use crate::tile::Tile;
 pub struct Chunk {
     // Vec<Z>, Z=Vec<Y>, Y=Vec<X>
     pub tiles: Vec<Vec<Vec<Option<Tile>>>>,
+    pub dirty: bool, // changed since it was stored or read
 }
 impl Chunk {
     pub fn chunksize() -> usize {
         16
     }
     pub fn new() -> Self {
-        Chunk { tiles: vec![] }
+        Chunk {
+            tiles: vec![],
+            dirty: false,
+        }
     }
     pub fn has_data(&self) -> bool {
         !self.tiles.is_empty()
@@ -30,6 +34,25 @@ impl Chunk {
     pub fn set(&mut self, x: usize, y: usize, z: usize, tile: Tile) {
         self.expand(x, y, z);
         self.tiles[z][y][x] = Some(tile);
+        self.dirty = true;
+    }
+    /// forget the tile, so it is generated again
+    pub fn clear(&mut self, x: usize, y: usize, z: usize) {
+        if z < self.tiles.len() && y < self.tiles[z].len() && x < self.tiles[z][y].len() {
+            self.tiles[z][y][x] = None;
+        }
+        self.dirty = true;
+    }
+    /// all tiles with information as (x, y, z, tile)
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize, usize, Tile)> + '_ {
+        self.tiles.iter().enumerate().flat_map(|(z, tiles_y)| {
+            tiles_y.iter().enumerate().flat_map(move |(y, tiles_x)| {
+                tiles_x
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(x, tile)| tile.map(|tile| (x, y, z, tile)))
+            })
+        })
     }
     fn expand(&mut self, x: usize, y: usize, z: usize) {
         while self.tiles.len() < z + 1 {
@@ -42,107 +65,260 @@ impl Chunk {
             self.tiles[z][y].push(None);
         }
     }
-    pub fn store(
-        &self,
-        db: &mut Db,
-        table_name: &str,
-        chunk_x: i32,
-        chunk_y: i32,
-        chunk_z: i32,
-    ) -> Result<(), Box<dyn Error>> {
-        for z in 0..Chunk::chunksize() {
-            for y in 0..Chunk::chunksize() {
+    /// The rows of the chunk which have tiles, a row is run-length encoded if that fits into the
+    /// columns.
+    pub fn rows(&self, chunk_x: i32, chunk_y: i32, chunk_z: i32) -> Vec<Vec<Data>> {
+        let cs = Chunk::chunksize();
+        let mut rows = vec![];
+        for z in 0..cs {
+            for y in 0..cs {
+                let tiles: Vec<_> = (0..cs).map(|x| self.get(x, y, z)).collect();
                 // only store the data if the line is not empty
-                if (0..Chunk::chunksize()).any(|x| self.get(x, y, z).is_some()) {
-                    let mut data = vec![
-                        Data::Int(chunk_x as i64),
-                        Data::Int(chunk_y as i64),
-                        Data::Int(chunk_z as i64),
-                        Data::Int(z as i64),
-                        Data::Int(y as i64),
-                    ];
-                    for x in 0..Chunk::chunksize() {
-                        if let Some(tile) = self.get(x, y, z) {
-                            // background
-                            data.push(if let Some(image_id) = tile.bg {
-                                Data::Int(image_id as i64)
-                            } else {
-                                Data::String("-".to_string())
-                            });
-                            // foreground
-                            data.push(if let Some(image_id) = tile.fg {
-                                Data::Int(image_id as i64)
-                            } else {
-                                Data::String("-".to_string())
-                            });
-                        } else {
-                            data.push(Data::Empty);
-                            data.push(Data::Empty);
-                        };
+                if tiles.iter().all(|tile| tile.is_none()) {
+                    continue;
+                }
+                let mut runs: Vec<(usize, Option<Tile>)> = vec![];
+                for tile in tiles.iter().copied() {
+                    match runs.last_mut() {
+                        Some((count, last)) if *last == tile => *count += 1,
+                        _ => runs.push((1, tile)),
                     }
-                    db.insert_data(table_name, data)?;
                 }
+                let mut data = vec![
+                    Data::Int(chunk_x as i64),
+                    Data::Int(chunk_y as i64),
+                    Data::Int(chunk_z as i64),
+                    Data::Int(z as i64),
+                    Data::Int(y as i64),
+                ];
+                if runs.len() * 4 <= 3 * cs {
+                    data.push(Data::Int(ENCODING_RLE));
+                    for (count, tile) in runs {
+                        data.push(Data::Int(count as i64));
+                        data.extend(tile_to_entries(tile));
+                    }
+                    data.resize(6 + 3 * cs, Data::Empty);
+                } else {
+                    data.push(Data::Int(ENCODING_PLAIN));
+                    for tile in tiles {
+                        data.extend(tile_to_entries(tile));
+                    }
+                }
+                rows.push(data);
             }
         }
-        Ok(())
+        rows
+    }
+    /// A row which discards the rows of the chunk stored before it, so a changed chunk can be
+    /// appended to the table instead of rewriting the table.
+    pub fn clear_row(chunk_x: i32, chunk_y: i32, chunk_z: i32) -> Vec<Data> {
+        let mut data = vec![
+            Data::Int(chunk_x as i64),
+            Data::Int(chunk_y as i64),
+            Data::Int(chunk_z as i64),
+            Data::Int(0),
+            Data::Int(0),
+            Data::Int(ENCODING_CLEAR),
+        ];
+        data.resize(6 + 3 * Chunk::chunksize(), Data::Empty);
+        data
+    }
+    /// true for a row made by clear_row()
+    pub fn is_clear_row(row: &Row, columns: usize) -> bool {
+        columns == 6 + 3 * Chunk::chunksize()
+            && matches!(row.select_at(5), Ok(Data::Int(ENCODING_CLEAR)))
     }
     // row format:
-    // chunk_x, chunk_y, chunk_z, z, y, x0, x1, ..., x{chunksize-1}
-    pub fn parse_row(&mut self, row: &Row) -> Result<(), Box<dyn Error>> {
+    // chunk_x, chunk_y, chunk_z, z, y, encoding, x0, x1, ..., x{chunksize-1}
+    // with x = bg, fg, fg2 and fg = image id + (rotation << 16)
+    // or run-length encoded:
+    // chunk_x, chunk_y, chunk_z, z, y, encoding, run0, run1, ... and empty cells after the runs
+    // with run = count, bg, fg, fg2
+    // or a clear row, which discards the tiles of the rows before it, see clear_row():
+    // chunk_x, chunk_y, chunk_z, 0, 0, encoding
+    // Rows of older maps have no encoding column, and before fg2 only bg, fg per x.
+    // `columns` is the number of columns of the table.
+    pub fn parse_row(&mut self, row: &Row, columns: usize) -> Result<(), Box<dyn Error>> {
         fn gen_error(msg: &str) -> Result<(), Box<dyn Error>> {
             Err(Box::new(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
                 msg,
             )))
         }
-        let entry_to_image_id = |entry| {
-            if let Data::Int(image_id) = entry {
-                Some(image_id as u16)
-            } else if let Data::String(s) = entry {
-                if s == "-" {
-                    None
-                } else {
-                    panic!("invalid tile entry: {}", s);
+        let cs = Chunk::chunksize();
+        let (Data::Int(z), Data::Int(y)) = (row.select_at(3)?, row.select_at(4)?) else {
+            return gen_error("invalid chunk data");
+        };
+        let (z, y) = (z as usize, y as usize);
+        let (encoding, first, per_x) = match columns {
+            columns if columns == 5 + 2 * cs => (ENCODING_PLAIN, 5, 2),
+            columns if columns == 5 + 3 * cs => (ENCODING_PLAIN, 5, 3),
+            _ => match row.select_at(5)? {
+                Data::Int(encoding) => (encoding, 6, 3),
+                _ => return gen_error("invalid chunk encoding"),
+            },
+        };
+        if encoding == ENCODING_CLEAR {
+            self.tiles.clear();
+            return Ok(());
+        }
+        self.expand(cs - 1, y, z);
+        let entries = |idx: usize| -> Result<_, Box<dyn Error>> {
+            let fg2 = match per_x {
+                3 => row.select_at(idx + 2)?,
+                _ => Data::Empty,
+            };
+            Ok((row.select_at(idx)?, row.select_at(idx + 1)?, fg2))
+        };
+        match encoding {
+            ENCODING_PLAIN => {
+                for x in 0..cs {
+                    if let Some(tile) = entries_to_tile(entries(first + per_x * x)?)? {
+                        self.set(x, y, z, tile);
+                    }
                 }
-            } else {
-                panic!("invalid tile entry: {}", entry);
             }
-        };
-        if let Data::Int(z) = row.select_at(3)? {
-            if let Data::Int(y) = row.select_at(4)? {
-                self.expand(Chunk::chunksize() - 1, y as usize, z as usize);
-                for x in 0..Chunk::chunksize() {
-                    let bg = row.select_at(5 + 2 * x)?;
-                    let fg = row.select_at(5 + 2 * x + 1)?;
-                    match (bg, fg) {
-                        (Data::Empty, Data::Empty) => {} // no entry exists
-                        (bg, Data::Empty) => self.set(
-                            x,
-                            y as usize,
-                            z as usize,
-                            Tile {
-                                bg: entry_to_image_id(bg),
-                                fg: None,
-                            },
-                        ),
-                        (Data::Empty, _) => unreachable!(),
-                        (bg, fg) => self.set(
-                            x,
-                            y as usize,
-                            z as usize,
-                            Tile {
-                                bg: entry_to_image_id(bg),
-                                fg: entry_to_image_id(fg),
-                            },
-                        ),
+            ENCODING_RLE => {
+                let (mut x, mut idx) = (0, first);
+                while x < cs {
+                    let Data::Int(count) = row.select_at(idx)? else {
+                        return gen_error("invalid run length");
                     };
+                    let end = (x + count.max(1) as usize).min(cs);
+                    if let Some(tile) = entries_to_tile(entries(idx + 1)?)? {
+                        for x in x..end {
+                            self.set(x, y, z, tile);
+                        }
+                    }
+                    (x, idx) = (end, idx + 4);
                 }
-            } else {
-                gen_error("invalid chunk data")?;
             }
-        } else {
-            gen_error("invalid chunk data")?;
+            _ => return gen_error("unknown chunk encoding"),
         }
         Ok(())
     }
 }
+
+/// values of the encoding column, see Chunk::parse_row()
+const ENCODING_PLAIN: i64 = 0;
+const ENCODING_RLE: i64 = 1;
+const ENCODING_CLEAR: i64 = 2;
+
+/// the bg, fg and fg2 entries of a tile, all empty if there is no information about it
+fn tile_to_entries(tile: Option<Tile>) -> [Data; 3] {
+    let Some(tile) = tile else {
+        return [Data::Empty, Data::Empty, Data::Empty];
+    };
+    let entry = |image_id: Option<u16>, extra: i64| match image_id {
+        Some(image_id) => Data::Int(image_id as i64 + extra),
+        None => Data::String("-".to_string()),
+    };
+    [
+        entry(tile.bg, 0),
+        // the rotation is stored above the image id
+        entry(tile.fg, (tile.fg_rotation as i64) << 16),
+        entry(tile.fg2, 0),
+    ]
+}
+
+/// the tile of the bg, fg and fg2 entries, None if there is no information about it
+fn entries_to_tile((bg, fg, fg2): (Data, Data, Data)) -> Result<Option<Tile>, Box<dyn Error>> {
+    let entry_to_image_id = |entry| -> Result<_, Box<dyn Error>> {
+        match entry {
+            // the cast to u16 drops the rotation
+            Data::Int(image_id) => Ok(Some(image_id as u16)),
+            Data::String(s) if s == "-" => Ok(None),
+            entry => Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid tile entry: {}", entry),
+            ))),
+        }
+    };
+    if let Data::Empty = bg {
+        return Ok(None); // no entry exists
+    }
+    let fg_rotation = match fg {
+        Data::Int(value) => (value >> 16).rem_euclid(4) as u8,
+        _ => 0,
+    };
+    Ok(Some(Tile {
+        bg: entry_to_image_id(bg)?,
+        fg: match fg {
+            Data::Empty => None,
+            fg => entry_to_image_id(fg)?,
+        },
+        fg_rotation,
+        fg2: match fg2 {
+            Data::Empty => None,
+            fg2 => entry_to_image_id(fg2)?,
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use rzdb::Db;
+
+    use super::*;
+
+    /// the rows in a table with the columns of the current format
+    fn table_rows(rows: Vec<Vec<Data>>) -> Vec<Row> {
+        let mut db = Db::create("w8_test", &std::env::temp_dir().to_string_lossy()).unwrap();
+        db.create_or_replace_table("map").unwrap();
+        for idx in 0..6 + 3 * Chunk::chunksize() {
+            db.create_column("map", &format!("c{}", idx)).unwrap();
+        }
+        for row in rows {
+            db.insert_data("map", row).unwrap();
+        }
+        db.select_from("map").unwrap()
+    }
+
+    #[test]
+    fn rows_parse_back_to_the_same_tiles() {
+        let mut chunk = Chunk::new();
+        let tile = Tile {
+            bg: Some(3),
+            fg: Some(7),
+            fg_rotation: 2,
+            fg2: None,
+        };
+        chunk.set(0, 1, 2, tile);
+        chunk.set(5, 1, 2, tile);
+        let columns = 6 + 3 * Chunk::chunksize();
+        let mut parsed = Chunk::new();
+        for row in table_rows(chunk.rows(-1, 0, -1)) {
+            parsed.parse_row(&row, columns).unwrap();
+        }
+        assert_eq!(parsed.get(0, 1, 2), Some(tile));
+        assert_eq!(parsed.get(5, 1, 2), Some(tile));
+        assert_eq!(parsed.get(1, 1, 2), chunk.get(1, 1, 2));
+    }
+
+    #[test]
+    fn malformed_row_is_an_error() {
+        let mut chunk = Chunk::new();
+        chunk.set(
+            0,
+            0,
+            0,
+            Tile {
+                bg: Some(3),
+                fg: None,
+                fg_rotation: 0,
+                fg2: None,
+            },
+        );
+        let columns = 6 + 3 * Chunk::chunksize();
+        let mut rows = chunk.rows(0, 0, 0);
+        // a text instead of an image id
+        let bg = rows[0]
+            .iter()
+            .position(|entry| *entry == Data::Int(3))
+            .unwrap();
+        rows[0][bg] = Data::String("stone".to_string());
+        let row = &table_rows(rows)[0];
+        let result = Chunk::new().parse_row(row, columns);
+        assert_eq!(result.unwrap_err().to_string(), "invalid tile entry: stone");
+    }
+}
@@ -0,0 +1,59 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use rzdb::{Data, Db};
+
+/// Settings of a world as name/value pairs. Names which are not stored get a default
+/// value from the caller, so new settings need no changes here.
+pub struct Config {
+    values: BTreeMap<String, i64>, // sorted, so that saving gives the same table
+}
+impl Config {
+    pub fn new() -> Self {
+        Config {
+            values: BTreeMap::new(),
+        }
+    }
+    pub fn get(&self, name: &str, default: i64) -> i64 {
+        self.values.get(name).copied().unwrap_or(default)
+    }
+    pub fn set(&mut self, name: &str, value: i64) {
+        self.values.insert(name.to_string(), value);
+    }
+    /// all names with their values, sorted by name
+    pub fn values(&self) -> impl Iterator<Item = (&str, i64)> {
+        self.values
+            .iter()
+            .map(|(name, value)| (name.as_str(), *value))
+    }
+
+    /// Store the settings in the database.
+    /// Data format:
+    /// name,value
+    pub fn store(&self, db: &mut Db, table_name: &str) -> Result<(), Box<dyn Error>> {
+        db.create_or_replace_table(table_name)?;
+        db.create_column(table_name, "name")?;
+        db.create_column(table_name, "value")?;
+        for (name, value) in &self.values {
+            db.insert_data(
+                table_name,
+                vec![Data::String(name.clone()), Data::Int(*value)],
+            )?;
+        }
+        Ok(())
+    }
+    pub fn parse_table(&mut self, db: &mut Db, table_name: &str) -> Result<(), Box<dyn Error>> {
+        let rows = db.select_from(table_name)?;
+        for row in &rows {
+            if let (Data::String(name), Data::Int(value)) = (row.select_at(0)?, row.select_at(1)?) {
+                self.values.insert(name, value);
+            } else {
+                return Err(Box::new(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "config row is not name,int",
+                )));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+use crate::tile::Tile;
+
+/// default of EditHistory::max_strokes
+pub const HISTORY_MAX_STROKES: usize = 200;
+
+/// A tile set by Map::set(): x, y, z and its modified state before and after. None means the
+/// tile was not modified and shows the generated tile.
+pub type Entry = (i32, i32, i32, Option<Tile>, Option<Tile>);
+
+/// the entries of one stroke in the order they were set
+pub type Stroke = Vec<Entry>;
+
+/// Undo and redo of map edits. Everything set between begin() and end() is one stroke, e.g.
+/// all tiles painted while a mouse button is held, so it is undone at once. Undone strokes
+/// can be redone until the next stroke.
+pub struct EditHistory {
+    strokes: Vec<Stroke>,
+    undone: Vec<Stroke>,
+    current: Option<Stroke>,
+    pub max_strokes: usize, // older strokes are forgotten
+}
+impl EditHistory {
+    pub fn new() -> Self {
+        EditHistory {
+            strokes: vec![],
+            undone: vec![],
+            current: None,
+            max_strokes: HISTORY_MAX_STROKES,
+        }
+    }
+    pub fn begin(&mut self) {
+        self.end();
+        self.current = Some(Stroke::new());
+    }
+    pub fn end(&mut self) {
+        if let Some(stroke) = self.current.take() {
+            if !stroke.is_empty() {
+                self.undone.clear();
+                self.push(stroke);
+            }
+        }
+    }
+    /// remember a changed tile, only between begin() and end()
+    pub fn record(&mut self, x: i32, y: i32, z: i32, old: Option<Tile>, new: Option<Tile>) {
+        if let Some(stroke) = &mut self.current {
+            stroke.push((x, y, z, old, new));
+        }
+    }
+    fn push(&mut self, stroke: Stroke) {
+        self.strokes.push(stroke);
+        if self.strokes.len() > self.max_strokes {
+            self.strokes.remove(0);
+        }
+    }
+    /// The last stroke, including the one in progress. It can be redone afterwards, its old
+    /// tiles have to be set in reverse order, see Map::undo().
+    pub fn undo(&mut self) -> Option<Stroke> {
+        self.end();
+        let stroke = self.strokes.pop()?;
+        self.undone.push(stroke.clone());
+        Some(stroke)
+    }
+    /// The last undone stroke, its new tiles have to be set in order, see Map::redo().
+    pub fn redo(&mut self) -> Option<Stroke> {
+        self.end();
+        let stroke = self.undone.pop()?;
+        self.push(stroke.clone());
+        Some(stroke)
+    }
+    /// true between begin() and end()
+    pub fn is_recording(&self) -> bool {
+        self.current.is_some()
+    }
+    pub fn len(&self) -> usize {
+        self.strokes.len()
+    }
+    pub fn undone_len(&self) -> usize {
+        self.undone.len()
+    }
+}
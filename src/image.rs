@@ -1,3 +1,10 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+
+use sfml::graphics::{Color, Image};
+
 pub const TILESIZE: ImageId = 16;
 pub const IMAGES_X: ImageId = 16;
 pub const IMAGES_Y: ImageId = 16;
@@ -12,17 +19,22 @@ macro_rules! from_grid {
     };
     () => {};
 }
-pub const IS_BACKGROUND: [bool; 96] = [
-    true, false, true, true, true, false, false, false, false, false, false, false, false, false,
-    false, false, // line idx 0: grass, flower, iron, stone, gold
-    false, false, false, true, true, false, false, false, false, false, false, false, false, false,
-    false, false, // line idx 1: tree, tree, tree, water, copper
-    false, false, false, true, true, false, false, false, false, false, false, false, false, false,
-    false, false, // line idx 2: tree, tree, tree, dirt
+/// File with the names and background flags of the atlas images, in the working directory.
+/// Without it resources/tiles.txt is loaded.
+pub const TILE_ATLAS_PATH: &str = "tiles.txt";
+
+/// background flags of the compiled in atlas, see TileAtlas::new()
+const IS_BACKGROUND: [bool; 96] = [
+    true, false, true, true, true, true, false, false, false, false, false, false, false, false,
+    false, false, // line idx 0: grass, flower, iron, stone, gold, sand
+    false, false, false, true, true, true, false, false, false, false, false, false, false, false,
+    false, false, // line idx 1: tree, tree, tree, water, copper, coal
+    false, false, false, true, true, true, false, false, false, false, false, false, false, false,
+    false, false, // line idx 2: tree, tree, tree, dirt, ice, snow
     false, false, false, true, true, false, false, false, false, false, false, false, false, false,
     false, false, // line idx 3: tree, tree, tree, delete symbol
-    false, false, false, true, true, false, false, false, false, false, false, false, false, false,
-    false, false, // line idx 4: tree, flower
+    false, false, false, false, true, false, false, false, false, false, false, false, false,
+    false, false, false, // line idx 4: tree, flower, berry bush, rock
     false, false, false, true, true, false, false, false, false, false, false, false, false, false,
     false, false, // line idx 5: tree, flower
 ];
@@ -31,30 +43,300 @@ pub const STONE: ImageId = from_grid!(3, 0);
 pub const IRON: ImageId = from_grid!(2, 0);
 pub const COPPER: ImageId = from_grid!(4, 1);
 pub const GOLD: ImageId = from_grid!(4, 0);
+pub const COAL: ImageId = from_grid!(5, 1);
 pub const GRASS: ImageId = from_grid!(0, 0);
 pub const DIRT: ImageId = from_grid!(3, 2);
 pub const WATER: ImageId = from_grid!(3, 1);
+pub const ICE: ImageId = from_grid!(4, 2);
+pub const SAND: ImageId = from_grid!(5, 0);
+pub const SNOW: ImageId = from_grid!(5, 2);
 pub const FLOWER1: ImageId = from_grid!(1, 0);
 pub const FLOWER2: ImageId = from_grid!(1, 4);
 pub const FLOWER3: ImageId = from_grid!(1, 5);
-#[allow(dead_code)]
 pub const PINE_3_1: ImageId = from_grid!(0, 3);
-#[allow(dead_code)]
 pub const PINE_3_1_2: ImageId = from_grid!(0, 2);
-#[allow(dead_code)]
 pub const PINE_3_1_3: ImageId = from_grid!(0, 1);
-#[allow(dead_code)]
 pub const PINE_2_1: ImageId = from_grid!(1, 3);
-#[allow(dead_code)]
 pub const PINE_2_1_2: ImageId = from_grid!(1, 2);
 pub const PINE_1_1: ImageId = from_grid!(2, 3);
-#[allow(dead_code)]
 pub const OAK_2_1: ImageId = from_grid!(0, 5);
-#[allow(dead_code)]
 pub const OAK_2_1_2: ImageId = from_grid!(0, 4);
 pub const OAK_1_1: ImageId = from_grid!(2, 2);
 pub const OAK_1_1_RED: ImageId = from_grid!(2, 1);
 pub const OAK_1_1_SMALL: ImageId = from_grid!(1, 1);
+pub const BERRY_BUSH: ImageId = from_grid!(2, 4);
+pub const ROCK: ImageId = from_grid!(3, 4);
+
+/// Foreground images which are drawn additively, so they light up their tile
+pub fn is_glowing(image_id: ImageId) -> bool {
+    matches!(image_id, FLOWER1 | FLOWER2 | FLOWER3)
+}
+
+/// name of the materials generated by the map, None for all other images
+pub fn material_name(image_id: ImageId) -> Option<&'static str> {
+    match image_id {
+        STONE => Some("stone"),
+        DIRT => Some("dirt"),
+        GRASS => Some("grass"),
+        WATER => Some("water"),
+        ICE => Some("ice"),
+        SAND => Some("sand"),
+        SNOW => Some("snow"),
+        IRON => Some("iron"),
+        COPPER => Some("copper"),
+        GOLD => Some("gold"),
+        COAL => Some("coal"),
+        _ => None,
+    }
+}
+
+/// category of every image of the compiled in atlas, None for tools (e.g. the eraser) and
+/// unused palette slots
+fn image_category(image_id: ImageId) -> Option<Category> {
+    match image_id {
+        GRASS | DIRT | STONE | WATER | ICE | SAND | SNOW => Some(Category::Terrain),
+        // rocks are minerals lying on the surface
+        IRON | COPPER | GOLD | COAL | ROCK => Some(Category::Ores),
+        FLOWER1 | FLOWER2 | FLOWER3 | PINE_3_1 | PINE_3_1_2 | PINE_3_1_3 | PINE_2_1
+        | PINE_2_1_2 | PINE_1_1 | OAK_2_1 | OAK_2_1_2 | OAK_1_1 | OAK_1_1_RED | OAK_1_1_SMALL
+        | BERRY_BUSH => Some(Category::Vegetation),
+        _ => None,
+    }
+}
+
+/// name of every image of the compiled in atlas, None for tools and unused palette slots
+fn image_name(image_id: ImageId) -> Option<&'static str> {
+    match image_id {
+        FLOWER1 => Some("flower"),
+        FLOWER2 => Some("flower 2"),
+        FLOWER3 => Some("flower 3"),
+        PINE_3_1 => Some("pine 3 tall, trunk"),
+        PINE_3_1_2 => Some("pine 3 tall, middle"),
+        PINE_3_1_3 => Some("pine 3 tall, top"),
+        PINE_2_1 => Some("pine 2 tall, trunk"),
+        PINE_2_1_2 => Some("pine 2 tall, top"),
+        PINE_1_1 => Some("pine"),
+        OAK_2_1 => Some("oak 2 tall, trunk"),
+        OAK_2_1_2 => Some("oak 2 tall, top"),
+        OAK_1_1 => Some("oak"),
+        OAK_1_1_RED => Some("red oak"),
+        OAK_1_1_SMALL => Some("small oak"),
+        BERRY_BUSH => Some("berry bush"),
+        ROCK => Some("rock"),
+        _ => material_name(image_id),
+    }
+}
+
+/// The names, background flags and palette categories of the images, by grid position, so
+/// new art only needs a line in tiles.txt. The size of the atlas and the images the map
+/// generates (GRASS, STONE, ...) stay compiled in, and so do the rules keyed on those ids:
+/// is_glowing() and material_name().
+/// File format, one image per line, # starts a comment, the name may contain commas:
+/// x,y,name,bg  or  x,y,name,fg, optionally followed by a category, e.g. x,y,name,bg,terrain
+pub struct TileAtlas {
+    names: Vec<Option<String>>, // by ImageId, None for tools and unused palette slots
+    background: Vec<bool>,      // by ImageId
+    categories: Vec<Option<Category>>, // by ImageId, None if no palette filter shows it
+}
+impl TileAtlas {
+    /// the compiled in atlas
+    pub fn new() -> Self {
+        TileAtlas {
+            names: (0..IMAGES_CNT)
+                .map(|image_id| image_name(image_id).map(|name| name.to_string()))
+                .collect(),
+            background: (0..IMAGES_CNT as usize)
+                .map(|idx| IS_BACKGROUND.get(idx).copied().unwrap_or(false))
+                .collect(),
+            categories: (0..IMAGES_CNT).map(image_category).collect(),
+        }
+    }
+    /// The images in the file instead of the compiled in ones. A missing file gives the
+    /// compiled in atlas.
+    pub fn load(path: &str) -> io::Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(TileAtlas::new()),
+            Err(err) => return Err(err),
+        };
+        let mut atlas = TileAtlas {
+            names: vec![None; IMAGES_CNT as usize],
+            background: vec![false; IMAGES_CNT as usize],
+            categories: vec![None; IMAGES_CNT as usize],
+        };
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} line {}: expected x,y,name,bg or x,y,name,fg and a category",
+                        path,
+                        idx + 1
+                    ),
+                )
+            };
+            let Some((fields, last)) = line.rsplit_once(',') else {
+                return Err(invalid());
+            };
+            let (fields, layer, category) = match Category::from_name(last.trim()) {
+                Some(category) => {
+                    let Some((fields, layer)) = fields.rsplit_once(',') else {
+                        return Err(invalid());
+                    };
+                    (fields, layer, Some(category))
+                }
+                None => (fields, last, None),
+            };
+            let fields: Vec<&str> = fields.splitn(3, ',').map(|field| field.trim()).collect();
+            let [x, y, name] = fields[..] else {
+                return Err(invalid());
+            };
+            let (Ok(x), Ok(y)) = (x.parse::<ImageId>(), y.parse::<ImageId>()) else {
+                return Err(invalid());
+            };
+            if x >= IMAGES_X || y >= IMAGES_Y || name.is_empty() {
+                return Err(invalid());
+            }
+            let image_id = from_grid!(x, y) as usize;
+            atlas.names[image_id] = Some(name.to_string());
+            atlas.background[image_id] = match layer.trim() {
+                "bg" => true,
+                "fg" => false,
+                _ => return Err(invalid()),
+            };
+            atlas.categories[image_id] = category;
+        }
+        Ok(atlas)
+    }
+    pub fn is_background(&self, image_id: ImageId) -> bool {
+        self.background
+            .get(image_id as usize)
+            .copied()
+            .unwrap_or(false)
+    }
+    pub fn name(&self, image_id: ImageId) -> Option<&str> {
+        self.names.get(image_id as usize)?.as_deref()
+    }
+    pub fn category(&self, image_id: ImageId) -> Option<Category> {
+        self.categories.get(image_id as usize).copied().flatten()
+    }
+    /// the images of a category in the order of the atlas
+    pub fn image_ids(&self, category: Category) -> Vec<ImageId> {
+        (0..IMAGES_CNT)
+            .filter(|&image_id| self.category(image_id) == Some(category))
+            .collect()
+    }
+}
+
+/// Write a JSON description of the atlas for external tools: its size and every named image
+/// with its grid cell, category and flags
+pub fn export_tileset_meta(
+    path: &str,
+    atlas_path: &str,
+    atlas: &TileAtlas,
+) -> Result<usize, Box<dyn Error>> {
+    let mut tiles = vec![];
+    for image_id in 0..IMAGES_CNT {
+        let Some(name) = atlas.name(image_id) else {
+            continue;
+        };
+        let category = atlas
+            .category(image_id)
+            .map_or("null".to_string(), |category| {
+                format!("\"{}\"", category.name())
+            });
+        tiles.push(format!(
+            "    {{\"id\": {}, \"x\": {}, \"y\": {}, \"name\": \"{}\", \"category\": {}, \"background\": {}, \"glowing\": {}}}",
+            image_id,
+            image_id % IMAGES_X,
+            image_id / IMAGES_X,
+            name,
+            category,
+            atlas.is_background(image_id),
+            is_glowing(image_id)
+        ));
+    }
+    let json = format!(
+        "{{\n  \"image\": \"{}\",\n  \"tile_size\": {},\n  \"columns\": {},\n  \"rows\": {},\n  \"width\": {},\n  \"height\": {},\n  \"tile_count\": {},\n  \"tiles\": [\n{}\n  ]\n}}\n",
+        atlas_path.replace('\\', "\\\\").replace('"', "\\\""),
+        TILESIZE,
+        IMAGES_X,
+        IMAGES_Y,
+        IMAGES_X * TILESIZE,
+        IMAGES_Y * TILESIZE,
+        IMAGES_CNT,
+        tiles.join(",\n")
+    );
+    fs::write(path, json)?;
+    Ok(tiles.len())
+}
+
+/// Visually equivalent images in consecutive atlas cells. The render picks one per position,
+/// stored tiles keep the base image id.
+pub fn variants() -> HashMap<ImageId, Vec<ImageId>> {
+    HashMap::from([
+        (
+            GRASS,
+            vec![GRASS, from_grid!(0, 6), from_grid!(1, 6), from_grid!(2, 6)],
+        ),
+        (
+            STONE,
+            vec![STONE, from_grid!(3, 6), from_grid!(4, 6), from_grid!(5, 6)],
+        ),
+    ])
+}
+
+/// Representative color of every image: the average of its opaque pixels
+pub fn image_colors(atlas: &Image) -> Vec<Color> {
+    (0..IMAGES_CNT)
+        .map(|image_id| {
+            let (tile_x, tile_y) = (
+                (image_id % IMAGES_X * TILESIZE) as u32,
+                (image_id / IMAGES_X * TILESIZE) as u32,
+            );
+            let (mut r, mut g, mut b, mut n) = (0u32, 0u32, 0u32, 0u32);
+            for y in tile_y..tile_y + TILESIZE as u32 {
+                for x in tile_x..tile_x + TILESIZE as u32 {
+                    let pixel = atlas.pixel_at(x, y);
+                    if pixel.a > 0 {
+                        r += pixel.r as u32;
+                        g += pixel.g as u32;
+                        b += pixel.b as u32;
+                        n += 1;
+                    }
+                }
+            }
+            let n = n.max(1);
+            Color::rgb((r / n) as u8, (g / n) as u8, (b / n) as u8)
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Category {
+    Terrain,
+    Vegetation,
+    Ores,
+}
+impl Category {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Terrain => "terrain",
+            Category::Vegetation => "vegetation",
+            Category::Ores => "ores",
+        }
+    }
+    /// the category with that name in tiles.txt
+    pub fn from_name(name: &str) -> Option<Category> {
+        [Category::Terrain, Category::Vegetation, Category::Ores]
+            .into_iter()
+            .find(|category| category.name() == name)
+    }
+}
 
 #[derive(Clone, Copy)]
 pub struct MultiImagePart {
@@ -64,6 +346,7 @@ pub struct MultiImagePart {
 }
 #[derive(Clone)]
 pub struct MultiImage {
+    pub name: String,
     pub image_ids: Vec<ImageId>,
     pub min_x: ImageId,
     pub min_y: ImageId,
@@ -71,7 +354,7 @@ pub struct MultiImage {
     pub size_y: ImageId,
 }
 impl MultiImage {
-    pub fn new(image_ids_xy: Vec<(ImageId, ImageId)>) -> Self {
+    pub fn new(name: &str, image_ids_xy: Vec<(ImageId, ImageId)>) -> Self {
         let mut image_ids = vec![];
         let mut min_x = 0;
         let mut min_y = 0;
@@ -90,6 +373,7 @@ impl MultiImage {
         let size_x = max_x - min_x + 1;
         let size_y = max_y - min_y + 1;
         MultiImage {
+            name: name.to_string(),
             image_ids,
             min_x,
             min_y,
@@ -97,6 +381,50 @@ impl MultiImage {
             size_y,
         }
     }
+    /// the multi-images without resources/multi_images.txt, the file ships the same ones
+    pub fn built_in() -> Vec<MultiImage> {
+        vec![
+            MultiImage::new("pine 3 tall", vec![(0, 1), (0, 2), (0, 3)]),
+            MultiImage::new("pine 2 tall", vec![(1, 2), (1, 3)]),
+            MultiImage::new("oak 2 tall", vec![(0, 4), (0, 5)]),
+        ]
+    }
+    /// Read multi-images from a text file, one per line: a name, "=" and the grid cells,
+    /// e.g. "tall pine = 0,1 0,2 0,3". Empty lines and lines starting with # are skipped.
+    pub fn from_file(path: &str) -> Result<Vec<MultiImage>, Box<dyn Error>> {
+        let invalid = |line_nr: usize, reason: &str| {
+            Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{}:{}: {}", path, line_nr + 1, reason),
+            ))
+        };
+        let mut multi_images = vec![];
+        for (line_nr, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((name, cells)) = line.split_once('=') else {
+                return Err(invalid(line_nr, "expected name = cells"));
+            };
+            let mut image_ids_xy = vec![];
+            for cell in cells.split_whitespace() {
+                let xy = cell.split_once(',').and_then(|(x, y)| {
+                    Some((x.parse::<ImageId>().ok()?, y.parse::<ImageId>().ok()?))
+                });
+                match xy {
+                    Some((x, y)) if x < IMAGES_X && y < IMAGES_Y => image_ids_xy.push((x, y)),
+                    Some(_) => return Err(invalid(line_nr, "cell outside of the palette")),
+                    None => return Err(invalid(line_nr, "expected cells as x,y")),
+                }
+            }
+            if image_ids_xy.is_empty() {
+                return Err(invalid(line_nr, &format!("{} has no cells", name.trim())));
+            }
+            multi_images.push(MultiImage::new(name.trim(), image_ids_xy));
+        }
+        Ok(multi_images)
+    }
     pub fn multi_id_from_image_id(image_id: ImageId, multi_array: &[MultiImage]) -> Option<usize> {
         multi_array
             .iter()
@@ -0,0 +1,124 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+
+use crate::tile::Tile;
+
+/// A change of a tile, None means the tile shows the generated tile again
+pub type JournalEntry = (i32, i32, i32, Option<Tile>);
+
+/// Append-only file of the tile changes since the last save, one stroke per block, so
+/// unsaved strokes can be replayed after a crash.
+/// Data format, one line per tile and an empty line after each stroke:
+/// x,y,z,bg,fg,rotation,fg2  (bg, fg and fg2 are "-" if empty, a single "-" after z if the
+/// edit is removed, older journals lack fg2 or the rotation and fg2)
+pub struct Journal {
+    path: String,
+    pending: Vec<JournalEntry>, // changes of the stroke in progress
+}
+impl Journal {
+    pub fn new(path: &str) -> Self {
+        Journal {
+            path: path.to_string(),
+            pending: vec![],
+        }
+    }
+    pub fn record(&mut self, x: i32, y: i32, z: i32, tile: Option<Tile>) {
+        self.pending.push((x, y, z, tile));
+    }
+    /// Append the recorded changes to the file as one stroke
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let mut text = String::new();
+        for (x, y, z, tile) in self.pending.drain(..) {
+            let id = |image_id: Option<u16>| image_id.map_or("-".to_string(), |id| id.to_string());
+            match tile {
+                Some(tile) => {
+                    text += &format!(
+                        "{},{},{},{},{},{},{}\n",
+                        x,
+                        y,
+                        z,
+                        id(tile.bg),
+                        id(tile.fg),
+                        tile.fg_rotation,
+                        id(tile.fg2)
+                    )
+                }
+                None => text += &format!("{},{},{},-\n", x, y, z),
+            }
+        }
+        text += "\n";
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(text.as_bytes())?;
+        file.sync_data()
+    }
+    /// Forget everything, called after the map was saved
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.pending.clear();
+        match fs::remove_file(&self.path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+    /// The strokes in the file, empty if there is none. A stroke cut off by a crash is dropped.
+    pub fn load(&self) -> io::Result<Vec<Vec<JournalEntry>>> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err),
+        };
+        let invalid = |line: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid journal line: {}", line),
+            )
+        };
+        let mut strokes = vec![];
+        let mut stroke = vec![];
+        for line in text.lines() {
+            if line.is_empty() {
+                strokes.push(std::mem::take(&mut stroke));
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').collect();
+            let int = |field: &str| field.parse::<i32>().map_err(|_| invalid(line));
+            let id = |field: &str| match field {
+                "-" => Ok(None),
+                _ => field.parse::<u16>().map(Some).map_err(|_| invalid(line)),
+            };
+            let (x, y, z) = match fields[..] {
+                [x, y, z, ..] => (int(x)?, int(y)?, int(z)?),
+                _ => return Err(invalid(line)),
+            };
+            let tile = match fields[3..] {
+                ["-"] => None,
+                [bg, fg] => Some(Tile {
+                    bg: id(bg)?,
+                    fg: id(fg)?,
+                    fg_rotation: 0,
+                    fg2: None,
+                }),
+                [bg, fg, rotation] => Some(Tile {
+                    bg: id(bg)?,
+                    fg: id(fg)?,
+                    fg_rotation: rotation.parse::<u8>().map_err(|_| invalid(line))? % 4,
+                    fg2: None,
+                }),
+                [bg, fg, rotation, fg2] => Some(Tile {
+                    bg: id(bg)?,
+                    fg: id(fg)?,
+                    fg_rotation: rotation.parse::<u8>().map_err(|_| invalid(line))? % 4,
+                    fg2: id(fg2)?,
+                }),
+                _ => return Err(invalid(line)),
+            };
+            stroke.push((x, y, z, tile));
+        }
+        Ok(strokes)
+    }
+}
@@ -0,0 +1,203 @@
+use std::fs;
+use std::io;
+
+use sfml::window::Key;
+
+pub const KEYMAP_PATH: &str = "keybindings.txt";
+
+/// What a key does, independent of the key it is bound to
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    PanUp,
+    PanDown,
+    PanLeft,
+    PanRight,
+    ToggleFog,
+    Erase,
+    TogglePaintErase,
+    CursorGrow,
+    CursorShrink,
+    Slope,
+    Label,
+    Measure,
+    ToggleMinimap,
+    ToggleStats,
+    RotateLeft,
+    RotateRight,
+}
+
+/// names of the actions in the file, with their default keys
+const ACTIONS: &[(&str, Action, &[Key])] = &[
+    ("pan_up", Action::PanUp, &[Key::W, Key::UP]),
+    ("pan_down", Action::PanDown, &[Key::S, Key::DOWN]),
+    ("pan_left", Action::PanLeft, &[Key::A, Key::LEFT]),
+    ("pan_right", Action::PanRight, &[Key::D, Key::RIGHT]),
+    ("toggle_fog", Action::ToggleFog, &[Key::V]),
+    ("erase", Action::Erase, &[Key::X, Key::DELETE]),
+    ("toggle_paint_erase", Action::TogglePaintErase, &[Key::F]),
+    ("cursor_grow", Action::CursorGrow, &[Key::EQUAL]),
+    ("cursor_shrink", Action::CursorShrink, &[Key::HYPHEN]),
+    ("slope", Action::Slope, &[Key::R]),
+    ("label", Action::Label, &[Key::L]),
+    ("measure", Action::Measure, &[Key::F12]),
+    ("toggle_minimap", Action::ToggleMinimap, &[Key::M]),
+    ("toggle_stats", Action::ToggleStats, &[Key::T]),
+    ("rotate_left", Action::RotateLeft, &[Key::COMMA]),
+    ("rotate_right", Action::RotateRight, &[Key::PERIOD]),
+];
+
+/// names of the keys in the file, letters and digits are named by themselves
+const KEY_NAMES: &[(&str, Key)] = &[
+    ("up", Key::UP),
+    ("down", Key::DOWN),
+    ("left", Key::LEFT),
+    ("right", Key::RIGHT),
+    ("space", Key::SPACE),
+    ("tab", Key::TAB),
+    ("delete", Key::DELETE),
+    ("insert", Key::INSERT),
+    ("home", Key::HOME),
+    ("end", Key::END),
+    ("equal", Key::EQUAL),
+    ("hyphen", Key::HYPHEN),
+    ("comma", Key::COMMA),
+    ("period", Key::PERIOD),
+    ("semicolon", Key::SEMICOLON),
+    ("slash", Key::SLASH),
+    ("add", Key::ADD),
+    ("subtract", Key::SUBTRACT),
+    ("f1", Key::F1),
+    ("f2", Key::F2),
+    ("f3", Key::F3),
+    ("f4", Key::F4),
+    ("f5", Key::F5),
+    ("f6", Key::F6),
+    ("f7", Key::F7),
+    ("f8", Key::F8),
+    ("f9", Key::F9),
+    ("f10", Key::F10),
+    ("f11", Key::F11),
+    ("f12", Key::F12),
+];
+const LETTERS: [Key; 26] = [
+    Key::A,
+    Key::B,
+    Key::C,
+    Key::D,
+    Key::E,
+    Key::F,
+    Key::G,
+    Key::H,
+    Key::I,
+    Key::J,
+    Key::K,
+    Key::L,
+    Key::M,
+    Key::N,
+    Key::O,
+    Key::P,
+    Key::Q,
+    Key::R,
+    Key::S,
+    Key::T,
+    Key::U,
+    Key::V,
+    Key::W,
+    Key::X,
+    Key::Y,
+    Key::Z,
+];
+const DIGITS: [Key; 10] = [
+    Key::NUM0,
+    Key::NUM1,
+    Key::NUM2,
+    Key::NUM3,
+    Key::NUM4,
+    Key::NUM5,
+    Key::NUM6,
+    Key::NUM7,
+    Key::NUM8,
+    Key::NUM9,
+];
+
+fn parse_key(name: &str) -> Option<Key> {
+    let name = name.to_lowercase();
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c @ 'a'..='z'), None) => Some(LETTERS[c as usize - 'a' as usize]),
+        (Some(c @ '0'..='9'), None) => Some(DIGITS[c as usize - '0' as usize]),
+        _ => KEY_NAMES
+            .iter()
+            .find(|(key_name, _)| *key_name == name)
+            .map(|&(_, key)| key),
+    }
+}
+
+/// The keys of the actions which can be rebound. Keys with ctrl, shift or alt keep their
+/// hardcoded meaning, they are checked before the actions.
+/// File format, one action per line, several keys separated by commas, # starts a comment:
+/// pan_up=W,Up
+pub struct KeyMap {
+    bindings: Vec<(Key, Action)>,
+}
+impl KeyMap {
+    /// the default keys
+    pub fn new() -> Self {
+        KeyMap {
+            bindings: ACTIONS
+                .iter()
+                .flat_map(|&(_, action, keys)| keys.iter().map(move |&key| (key, action)))
+                .collect(),
+        }
+    }
+    /// The default keys with the actions in the file bound to the keys given there. A missing
+    /// file gives the default keys.
+    pub fn load(path: &str) -> io::Result<Self> {
+        let mut keymap = KeyMap::new();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(keymap),
+            Err(err) => return Err(err),
+        };
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |what: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} line {}: {}", path, idx + 1, what),
+                )
+            };
+            let (name, keys) = line
+                .split_once('=')
+                .ok_or_else(|| invalid("expected action=key"))?;
+            let action = ACTIONS
+                .iter()
+                .find(|(action_name, _, _)| *action_name == name.trim())
+                .map(|&(_, action, _)| action)
+                .ok_or_else(|| invalid(&format!("unknown action {}", name.trim())))?;
+            keymap.bindings.retain(|&(_, bound)| bound != action);
+            for key_name in keys.split(',') {
+                let key = parse_key(key_name.trim())
+                    .ok_or_else(|| invalid(&format!("unknown key {}", key_name.trim())))?;
+                keymap.bindings.push((key, action));
+            }
+        }
+        Ok(keymap)
+    }
+    /// the action of a key, the first binding wins if a key is bound twice
+    pub fn action(&self, code: Key) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|&&(key, _)| key == code)
+            .map(|&(_, action)| action)
+    }
+    pub fn keys(&self, action: Action) -> impl Iterator<Item = Key> + '_ {
+        self.bindings
+            .iter()
+            .filter(move |&&(_, bound)| bound == action)
+            .map(|&(key, _)| key)
+    }
+}
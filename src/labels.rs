@@ -0,0 +1,70 @@
+use std::error::Error;
+
+use rzdb::{Data, Db};
+
+/// A named position in the world, e.g. "spawn" or "iron mine"
+pub struct Label {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+    pub name: String,
+}
+
+pub struct Labels {
+    pub labels: Vec<Label>,
+}
+impl Labels {
+    pub fn new() -> Self {
+        Labels { labels: vec![] }
+    }
+
+    /// Store the labels in the database.
+    /// Data format:
+    /// x,y,z,name
+    pub fn store(&self, db: &mut Db, table_name: &str) -> Result<(), Box<dyn Error>> {
+        db.create_or_replace_table(table_name)?;
+        db.create_column(table_name, "x")?;
+        db.create_column(table_name, "y")?;
+        db.create_column(table_name, "z")?;
+        db.create_column(table_name, "name")?;
+        for label in &self.labels {
+            db.insert_data(
+                table_name,
+                vec![
+                    Data::Int(label.x as i64),
+                    Data::Int(label.y as i64),
+                    Data::Int(label.z as i64),
+                    Data::String(label.name.clone()),
+                ],
+            )?;
+        }
+        Ok(())
+    }
+    pub fn parse_table(&mut self, db: &mut Db, table_name: &str) -> Result<(), Box<dyn Error>> {
+        let rows = db.select_from(table_name)?;
+        for row in &rows {
+            match (
+                row.select_at(0)?,
+                row.select_at(1)?,
+                row.select_at(2)?,
+                row.select_at(3)?,
+            ) {
+                (Data::Int(x), Data::Int(y), Data::Int(z), Data::String(name)) => {
+                    self.labels.push(Label {
+                        x: x as i32,
+                        y: y as i32,
+                        z: z as i32,
+                        name,
+                    });
+                }
+                _ => {
+                    return Err(Box::new(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "invalid label row",
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+}
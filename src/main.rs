@@ -1,32 +1,112 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt::Write;
+use std::time::Duration;
 
 use sfml::{
     graphics::{
-        Color, Font, PrimitiveType, Rect, RenderStates, RenderTarget, RenderWindow, Text, Texture,
-        Transform, Vertex, View,
+        BlendMode, Color, Font, PrimitiveType, Rect, RenderStates, RenderTarget, RenderWindow,
+        Text, Texture, Transform, Transformable, Vertex, View,
     },
-    system::{Clock, Vector2, Vector2f, Vector2i},
+    system::{sleep, Clock, Time, Vector2, Vector2f, Vector2i},
     window::{
         mouse::{Button, Wheel},
         ContextSettings, Event, Key, Style, VideoMode,
     },
+    SfBox,
 };
 
 use rzdb::Db;
 
+mod bookmark;
+mod brush;
 mod chunk;
+mod config;
+mod history;
 mod image;
+mod journal;
+mod keymap;
+mod labels;
 mod map;
+mod minimap;
+mod pattern;
+mod saver;
+mod slots;
+mod stats;
 mod tile;
 
+use bookmark::{Bookmarks, Camera};
+use brush::{Brush, Brushes};
+use chunk::Chunk;
+use config::Config;
+use history::HISTORY_MAX_STROKES;
 use image::{
-    ImageId, MultiImage, GRASS, IMAGES_USED_X, IMAGES_USED_Y, IS_BACKGROUND, TILESIZE, WATER,
+    export_tileset_meta, image_colors, is_glowing, material_name, variants, Category, ImageId,
+    MultiImage, TileAtlas, DIRT, GRASS, ICE, IMAGES_USED_X, IMAGES_USED_Y, TILESIZE,
+    TILE_ATLAS_PATH, WATER,
 };
-use map::Map;
+use journal::Journal;
+use keymap::{Action, KeyMap, KEYMAP_PATH};
+use labels::{Label, Labels};
+use map::{
+    tile_hash, Map, NoiseParam, VegetationMode, EDIT_AGE_MAX, FLOOD_FILL_MAX_TILES, JOURNAL_PATH,
+    MAP_FORMAT, MAX_RESIDENT_CHUNKS, WORLD_LIMIT,
+};
+use minimap::Minimap;
+use pattern::Pattern;
+use saver::Saver;
+use slots::{SlotInfo, DEFAULT_WORLD, SLOT_COUNT};
+use stats::Stats;
 use tile::Tile;
 
 use crate::image::{IMAGES_CNT, IMAGES_X};
 
+/// colors for columns without any tile in the rendered depth, black shows the void
+const SKY_COLORS: [Color; 3] = [
+    Color::rgb(135, 206, 235),
+    Color::rgb(255, 180, 120),
+    Color::BLACK,
+];
+
+/// pixels the texture coordinates are moved inwards with the inset option, against seams
+const TEX_INSET: f32 = 0.5;
+
+/// tiles around the origin shown by the seed preview, one pixel per SEED_PREVIEW_STEP tiles
+const SEED_PREVIEW_SIZE: (i32, i32) = (1024, 1024);
+const SEED_PREVIEW_STEP: i32 = 8;
+/// screen pixels per pixel of the seed preview
+const SEED_PREVIEW_PIXELS: f32 = 3.0;
+
+/// how far the nearest water is searched, in tiles from the center of the window
+const WATER_SEARCH_RADIUS: i32 = 100;
+
+/// smooth camera: acceleration in tiles per second² at scale 1, and the part of the speed
+/// which is kept after a second
+const FLY_ACCELERATION: f32 = 400.0;
+const FLY_DAMPING: f32 = 0.02;
+/// adaptive quality: fewest z levels drawn, and how often the drawn depth changes by one
+const ADAPTIVE_MIN_DEPTH: i32 = 3;
+const ADAPTIVE_INTERVAL_MS: i32 = 250;
+/// z-slices are exported to and imported from this file in the working directory
+const CSV_SLICE_PATH: &str = "w8_slice.csv";
+/// the sea level can be moved this far up or down, the terrain is between -8 and 16
+const SEA_LEVEL_MAX: i16 = 32;
+/// freshly generated chunks are highlighted this long
+const GENERATION_HIGHLIGHT_MS: u64 = 1000;
+/// how often the window title may change
+const TITLE_UPDATE_MS: i32 = 500;
+/// scatter painting: objects are placed on one of this many cells of the brush
+const SCATTER_DENSITY: u32 = 4;
+const SCATTER_SEED: i32 = 2;
+/// how long the picked tile is highlighted after an alt-click
+const PICK_HIGHLIGHT_MS: i32 = 300;
+/// the column tooltip lists this many z levels above and below the current one
+const COLUMN_TOOLTIP_RANGE: i32 = 10;
+
+/// z levels between contour lines to cycle through
+const CONTOUR_INTERVALS: [i32; 4] = [1, 2, 4, 8];
+/// terrace heights in z levels to cycle through, 0 = smooth terrain
+const TERRACE_STEPS: [i16; 4] = [0, 2, 3, 4];
+
 macro_rules! example_res {
     ($path:literal) => {
         concat!(env!("CARGO_MANIFEST_DIR"), "/resources/", $path)
@@ -35,6 +115,39 @@ macro_rules! example_res {
 enum Mode {
     Paint,
     Erase,
+    RectFill, // press at a corner, drag to the opposite one and release to fill
+}
+/// placing a label: wait for a click on the map, then type its name
+enum LabelInput {
+    Off,
+    Position,
+    Name(i32, i32, i32, String),
+}
+/// what happens with a region once both corners are clicked
+#[derive(Clone, Copy)]
+enum RegionAction {
+    Export,      // save it as a new world
+    Measure,     // count the solid tiles, e.g. for excavations
+    PatternPick, // take the visible tiles as the pattern
+    PatternFill, // repeat the pattern, starting at the first corner
+    ExportCsv,   // the backgrounds at the current z as CSV
+}
+impl RegionAction {
+    fn name(&self) -> &'static str {
+        match self {
+            RegionAction::Export => "export",
+            RegionAction::Measure => "measure",
+            RegionAction::PatternPick => "pick pattern",
+            RegionAction::PatternFill => "fill with pattern",
+            RegionAction::ExportCsv => "export CSV",
+        }
+    }
+}
+/// selecting a region: wait for two clicks on opposite corners
+enum RegionInput {
+    Off,
+    First(RegionAction),
+    Second(RegionAction, i32, i32),
 }
 struct Object {
     position: Vector2i,
@@ -45,6 +158,113 @@ enum MouseObject {
     ImageId(ImageId),
     MultiImage(MultiImage),
 }
+impl MouseObject {
+    /// images which are part of a multi image select the whole multi image
+    fn from_image_id(image_id: ImageId, multi_objects: &[MultiImage]) -> Self {
+        if let Some(multi_idx) = MultiImage::multi_id_from_image_id(image_id, multi_objects) {
+            MouseObject::MultiImage(multi_objects[multi_idx].clone())
+        } else {
+            MouseObject::ImageId(image_id)
+        }
+    }
+    fn image_id(&self) -> ImageId {
+        match self {
+            MouseObject::ImageId(image_id) => *image_id,
+            MouseObject::MultiImage(multi_image) => multi_image.image_ids[0],
+        }
+    }
+}
+
+/// upper left and lower right window corner of the tiles between two view positions
+fn region_corners(corner_a: Vector2i, corner_b: Vector2i, scale: f32) -> (Vector2f, Vector2f) {
+    let min = Vector2i::new(corner_a.x.min(corner_b.x), corner_a.y.min(corner_b.y));
+    let max = Vector2i::new(corner_a.x.max(corner_b.x), corner_a.y.max(corner_b.y));
+    (
+        grid_to_win(min, scale),
+        grid_to_win(max + Vector2i::new(1, 1), scale),
+    )
+}
+
+/// The tile of Mode::RectFill: backgrounds and objects on grass like painting, an empty tile
+/// for the eraser and None for multi-images
+fn rect_fill_tile(selection: &MouseObject, eraser: ImageId, atlas: &TileAtlas) -> Option<Tile> {
+    match *selection {
+        MouseObject::ImageId(image_id) if image_id == eraser => Some(Tile {
+            bg: None,
+            fg: None,
+            fg_rotation: 0,
+            fg2: None,
+        }),
+        MouseObject::ImageId(image_id) => {
+            let is_bg = atlas.is_background(image_id);
+            Some(Tile {
+                bg: Some(if is_bg { image_id } else { GRASS }),
+                fg: (!is_bg).then_some(image_id),
+                fg_rotation: 0,
+                fg2: None,
+            })
+        }
+        MouseObject::MultiImage(_) => None,
+    }
+}
+
+/// value of a command line option given as `--name value` or `--name=value`
+fn cli_value(name: &str) -> Option<i64> {
+    cli_string(name)?.parse().ok()
+}
+/// text of a command line option, see cli_value()
+fn cli_string(name: &str) -> Option<String> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let option = format!("--{}", name);
+    args.iter().enumerate().find_map(|(i, arg)| {
+        if *arg == option {
+            args.get(i + 1).cloned()
+        } else {
+            Some(arg.strip_prefix(&option)?.strip_prefix('=')?.to_string())
+        }
+    })
+}
+
+/// Screen grid positions to world positions and back. The view is rotated by `rotation`
+/// quarter turns around the middle of the screen, the stored world is unaffected.
+#[derive(Clone, Copy)]
+struct ViewGrid {
+    origin: Vector2i, // dx, dy: world position at the upper left corner without rotation
+    center: Vector2i, // middle of the screen grid
+    rotation: u8,
+}
+impl ViewGrid {
+    fn new(dx: i32, dy: i32, grid_size: Vector2i, rotation: u8) -> Self {
+        ViewGrid {
+            origin: Vector2i::new(dx, dy),
+            center: grid_size / 2,
+            rotation,
+        }
+    }
+    fn to_world(self, view_pos: Vector2i) -> Vector2i {
+        self.origin + self.center + rotate_quarter(view_pos - self.center, self.rotation)
+    }
+    fn to_view(self, world_pos: Vector2i) -> Vector2i {
+        let turns_back = (4 - self.rotation) % 4;
+        self.center + rotate_quarter(world_pos - self.origin - self.center, turns_back)
+    }
+}
+/// Rotate a screen offset into the world by quarter turns: with one turn, screen right is
+/// world up
+fn rotate_quarter<T: Copy + std::ops::Neg<Output = T>>(v: Vector2<T>, turns: u8) -> Vector2<T> {
+    match turns % 4 {
+        0 => v,
+        1 => Vector2::new(v.y, -v.x),
+        2 => Vector2::new(-v.x, -v.y),
+        _ => Vector2::new(-v.y, v.x),
+    }
+}
+
+/// Pixels the map is moved to the upper left by the sub-tile part of the smooth camera
+fn camera_shift(camera: Vector2f, rotation: u8, scale: f32) -> Vector2f {
+    let fraction = Vector2f::new(camera.x - camera.x.floor(), camera.y - camera.y.floor());
+    rotate_quarter(fraction, (4 - rotation) % 4) * (TILESIZE as f32 * scale)
+}
 
 fn grid_to_win(grid_pos: Vector2i, scale: f32) -> Vector2f {
     Vector2f {
@@ -77,19 +297,136 @@ fn vu2f(v: Vector2<u32>) -> Vector2f {
 }
 
 fn main() {
+    // without --slot the slot of the last session is loaded and the slots are listed
+    let mut slot = cli_value("slot").map(|slot| slot.clamp(1, SLOT_COUNT as i64) as u8);
+    if let Err(e) = cli_string("map").map_or(Ok(()), |world| slots::check_world_name(&world)) {
+        println!("{}", e);
+        return;
+    }
+    while let Some(next_slot) = run(slot) {
+        slot = Some(next_slot);
+    }
+}
+
+/// Edit the world in a save slot until the window is closed, or another slot is chosen.
+/// Returns the chosen slot.
+fn run(slot: Option<u8>) -> Option<u8> {
     let mut map = Map::new();
     let db_name = "w8";
     let db_dir = "~/.local/rzdb";
-    let table_map = "generated_map";
-    let mut db = if let Ok(mut db) = Db::load(db_name, db_dir) {
+    let (mut db, db_loaded) = match Db::load(db_name, db_dir) {
+        Ok(db) => (db, true),
+        Err(_) => (Db::create(db_name, db_dir).unwrap(), false),
+    };
+    let mut show_slots = slot.is_none();
+    // the database can hold several worlds, each with its own slots
+    let world = &cli_string("map").unwrap_or_else(|| DEFAULT_WORLD.to_string());
+    let slot = slot.unwrap_or_else(|| slots::last_slot(&mut db, world));
+    let mut slot_infos: Vec<SlotInfo> = vec![]; // listed on the slot screen
+    let mut worlds: Vec<String> = vec![]; // listed on the slot screen
+    let mut next_slot = None; // switched to after the next save
+    let table_map = &slots::table_name(world, "map", slot);
+    let table_stats = &slots::table_name(world, "stats", slot);
+    let table_brushes = &slots::table_name(world, "brushes", slot);
+    let table_bookmarks = &slots::table_name(world, "bookmarks", slot);
+    let table_labels = &slots::table_name(world, "labels", slot);
+    let table_config = &slots::table_name(world, "config", slot);
+    let table_noise = &slots::table_name(world, "map_meta", slot);
+    map.journal = Journal::new(&slots::file_path(world, JOURNAL_PATH, slot));
+    let keymap = KeyMap::load(KEYMAP_PATH).unwrap_or_else(|e| {
+        println!("{}", e);
+        KeyMap::new()
+    });
+    let atlas_path = if std::path::Path::new(TILE_ATLAS_PATH).exists() {
+        TILE_ATLAS_PATH
+    } else {
+        example_res!("tiles.txt")
+    };
+    let atlas = TileAtlas::load(atlas_path).unwrap_or_else(|e| {
+        println!("{}", e);
+        TileAtlas::new()
+    });
+    let mut stats = Stats::new();
+    let mut brushes = Brushes::new();
+    let mut bookmarks = Bookmarks::new();
+    let mut labels = Labels::new();
+    let mut config = Config::new();
+    // a new slot has no tables yet
+    let world_saved = db_loaded && db.select_from(table_map).is_ok();
+    if world_saved {
+        if let Err(e) = config.parse_table(&mut db, table_config) {
+            println!("{}", e);
+        }
+        // quit instead of saving over a world which was not read
+        let map_format = config.get("map_format", MAP_FORMAT);
+        if let Err(e) = Map::check_schema(&mut db, table_map, map_format) {
+            println!("{}", e);
+            return None;
+        }
         if let Err(e) = map.parse_table(&mut db, table_map) {
             println!("{}", e);
         }
-        db
-    } else {
-        Db::create(db_name, db_dir).unwrap()
+        if let Err(e) = stats.parse_table(&mut db, table_stats) {
+            println!("{}", e);
+        }
+        if let Err(e) = brushes.parse_table(&mut db, table_brushes) {
+            println!("{}", e);
+        }
+        if let Err(e) = bookmarks.parse_table(&mut db, table_bookmarks) {
+            println!("{}", e);
+        }
+        if let Err(e) = labels.parse_table(&mut db, table_labels) {
+            println!("{}", e);
+        }
+        if let Err(e) = map.parse_noise_params(&mut db, table_noise) {
+            println!("{}", e);
+        }
+    }
+    // saves the database on another thread from here on
+    let mut saver = Saver::new(db);
+    stats.sessions += 1;
+    // strokes which were not saved before the last session ended
+    let mut recovery = match map.journal.load() {
+        Ok(strokes) if !strokes.is_empty() => Some(strokes),
+        Ok(_) => None,
+        Err(e) => {
+            println!("{}", e);
+            None
+        }
+    };
+    map.vegetation_mode = match config.get("vegetation_mode", 0) {
+        0 => VegetationMode::Noise,
+        _ => VegetationMode::Hash,
+    };
+    map.vegetation_density = config.get("vegetation_density", 40) as u32;
+    map.terrace_step = config.get("terrace_step", 0) as i16;
+    map.sea_level = config.get("sea_level", 0) as i16;
+    // a saved world keeps the seed it was generated with, --seed is for new worlds
+    map.seed = match cli_value("seed") {
+        Some(seed) if !world_saved => seed as i32,
+        Some(_) => {
+            println!(
+                "--seed ignored, the world in slot {} has a seed already",
+                slot
+            );
+            config.get("seed", 0) as i32
+        }
+        None => config.get("seed", 0) as i32,
     };
+    map.resource_density = config.get("resource_density", 5) as u32;
+    map.river_width = config.get("river_width", 8).max(0) as i16;
+    map.history.max_strokes = config
+        .get("history_max_strokes", HISTORY_MAX_STROKES as i64)
+        .max(1) as usize;
+    map.max_resident_chunks = config
+        .get("max_resident_chunks", MAX_RESIDENT_CHUNKS as i64)
+        .max(0) as usize;
+    map.world_limit = config
+        .get("world_limit", WORLD_LIMIT as i64)
+        .clamp(0, i32::MAX as i64) as i32;
+    let mut show_stats = false;
     let mut map_modified = false;
+    let mut saved_settings = None; // the settings of the last save, see config
     let mut save_clock = Clock::start();
 
     let native_mode = VideoMode::desktop_mode();
@@ -98,27 +435,80 @@ fn main() {
     window.set_vertical_sync_enabled(true);
     let font = Font::from_file(example_res!("Qaz/Qaz.ttf")).unwrap();
     let texture = Texture::from_file(example_res!("palette.png")).unwrap();
+    let image_colors = image_colors(&texture.copy_to_image().unwrap());
+    let mut sampled_color = None;
+    let mut show_debug_sample = false;
+    let mut inspector: Option<(i32, i32, i32)> = None; // inspected column and its selected z
+    let mut show_edit_age = false;
+    let variants = variants();
+    let mut show_variants = true;
+    let mut jitter_fg = false;
+    let mut dim_map = false;
+    let mut minimap = Minimap::new();
 
-    let multi_objects = vec![
-        MultiImage::new(vec![(0, 1), (0, 2), (0, 3)]),
-        MultiImage::new(vec![(1, 2), (1, 3)]),
-        MultiImage::new(vec![(0, 4), (0, 5)]),
-    ];
-    #[allow(unused_variables)]
+    let multi_objects = match MultiImage::from_file(example_res!("multi_images.txt")) {
+        Ok(multi_objects) => multi_objects,
+        Err(e) => {
+            // without the file the built-in multi-images are used silently
+            let kind = e.downcast_ref::<std::io::Error>().map(|e| e.kind());
+            if kind != Some(std::io::ErrorKind::NotFound) {
+                println!("{}", e);
+            }
+            MultiImage::built_in()
+        }
+    };
     let multi_ids = MultiImage::generate_multi_reverse_map(&multi_objects);
     let eraser = 3 * IMAGES_X + 3;
 
     let mut mode = Mode::Paint;
+    let mut rect_corner: Option<Vector2i> = None; // first corner of Mode::RectFill, in the world
 
-    let estimated_dpi = if window.size().y > 4000 { 400 } else { 300 };
-    let mut scale = (estimated_dpi as f32 / 400.1 * 6.0).floor();
+    // --dpi or the config override the guess from the window height, 0 = guess
+    let dpi = match cli_value("dpi").unwrap_or_else(|| config.get("dpi", 0)) {
+        dpi if dpi > 0 => dpi,
+        _ if window.size().y > 4000 => 400,
+        _ => 300,
+    };
+    let mut scale = (dpi as f32 / 400.1 * 6.0).floor().max(1.0);
 
     let mut text_object = Text::new("", &font, 9 * scale as u32);
     // scale = 1.0;
     text_object.set_outline_color(Color::BLACK);
     text_object.set_outline_thickness(1.0);
+    let mut label_text = Text::new("", &font, 9 * scale as u32);
+    label_text.set_outline_color(Color::BLACK);
+    label_text.set_outline_thickness(1.0);
+    let mut label_input = LabelInput::Off;
+    let mut goto_input: Option<String> = None; // coordinates typed after ':'
+    let mut noise_panel: Option<usize> = None; // selected line of the terrain noise panel
+    let mut region_input = RegionInput::Off;
+    let mut label_idx = 0;
+    let mut compass_text = Text::new("", &font, 9 * scale as u32);
+    compass_text.set_outline_color(Color::BLACK);
+    compass_text.set_outline_thickness(1.0);
+    let mut show_compass = true;
+    let mut inspector_text = Text::new("", &font, 9 * scale as u32);
+    inspector_text.set_outline_color(Color::BLACK);
+    inspector_text.set_outline_thickness(1.0);
+    let mut show_crosshair = false;
+    let mut show_contours = false;
+    let mut contour_interval = config.get("contour_interval", 2).max(1) as i32;
+    let mut accumulate_ores = false; // sum up the generated ores instead of counting per frame
+    let mut last_pick = None; // position of the last alt-click pick, for a short highlight
     let mut rs = RenderStates::default();
     let mut buf = Vec::new();
+    let mut sky_buf = Vec::new();
+    let mut sky_color_idx = (config.get("sky_color", 0) as usize).min(SKY_COLORS.len() - 1);
+    let mut overlay_buf = Vec::new();
+    let mut glow_buf = Vec::new(); // drawn additively after the map, see image::is_glowing()
+    let mut glow = true;
+    let mut seed_preview: Option<(i32, SfBox<Texture>)> = None; // candidate seed and its terrain
+    let mut max_vertices = config.get("max_vertices", 400_000) as usize;
+    let mut show_provenance = false;
+    let title_coordinates = config.get("title_coordinates", 1) != 0;
+    let mut title = String::new(); // last window title, it is only set when it changes
+    let mut title_clock = Clock::start();
+    let mut show_generation = false; // tint freshly generated chunks, count the prefetch queue
     let mut current_frames_rendered = 0;
     let mut fps_clock = Clock::start();
     let mut frame_timer = Clock::start();
@@ -131,18 +521,75 @@ fn main() {
     let mut dx = 94;
     let mut dy = -44;
     let mut dz = -30;
+    let mut smooth_camera = false; // glide with momentum instead of stepping tile by tile
+    let mut camera = Vector2f::new(0., 0.); // dx, dy with the sub-tile part in smooth mode
+    let mut camera_velocity = Vector2f::new(0., 0.); // tiles per second
     let grid_size = win_to_grid(vu2f(window.size()), scale);
     let mut cursor_size = 1;
     let middle = grid_size / 2;
+    // start at the edits of a loaded world, at the top of them
+    if let Some((min, max)) = map.edit_bounds() {
+        dx = (min.0 + max.0) / 2 - middle.x;
+        dy = (min.1 + max.1) / 2 - middle.y;
+        dz = max.2;
+    }
     while map.get(middle.x + dx, middle.y + dy, dz).bg.is_some() {
         dz += 1;
     }
-    let mut fog = true;
+    let mut day_night = config.get("day_night", 0) != 0;
+    let mut day_night_paused = false;
+    let day_length = config.get("day_length_s", 600).max(1) as f32; // seconds per day
+    let mut time_of_day: f32 = 0.5; // 0: midnight, 0.5: noon
+    let mut fog = config.get("fog", 1) != 0;
+    let mut tex_inset = config.get("tex_inset", 0) != 0;
+    // only tiles sharing a face reveal, not diagonal ones
+    let mut fog_cardinal = config.get("fog_cardinal", 0) != 0;
+    // z levels searched downwards for the surface
+    let mut render_depth = config.get("render_depth", 20) as i32;
+    // adaptive quality: draw fewer z levels than render_depth while frames are slow
+    let mut adaptive_depth = config.get("adaptive_depth", 0) != 0;
+    let adaptive_target_ms = config.get("adaptive_target_ms", 20).max(1) as f32;
+    let mut draw_depth = render_depth;
+    // foreground objects are not drawn below this scale, they are too small to see anyway
+    let mut hide_fg_zoomed_out = config.get("hide_fg_zoomed_out", 1) != 0;
+    let fg_min_scale = config.get("fg_min_scale_percent", 50) as f32 / 100.0;
+    let mut frame_time_avg = 0.0; // smoothed, so single slow frames don't change the depth
+    let mut adaptive_clock = Clock::start();
 
     let mut clock_dx = Clock::start();
     let mut clock_dy = Clock::start();
 
-    let (mut matrix, mut matrix_offset_y) = make_matrix(scale);
+    // tiles per frame, smoothed, to prefetch chunks in the direction of travel
+    let mut velocity = Vector2f::new(0., 0.);
+    let mut last_dxy = Vector2i::new(dx, dy);
+
+    let mut palette_filter = None;
+    let mut command_message = String::new();
+    let mut pending_confirm = None;
+
+    // slope brush: while dragging, the paint z-level changes by `slope` every `slope_ratio` tiles
+    let mut slope = 0; // 0: off, 1: up, -1: down
+    let mut slope_ratio = 4;
+    let mut slope_stroke: Option<(Vector2i, i32)> = None; // last position, distance since start
+    let mut paint_last: Option<Vector2i> = None; // world position painted in the last frame
+    let mut surface_paint = false; // paint on the surface of each column instead of at dz
+    let mut surface_columns = HashMap::new(); // surface z of the columns painted in this stroke
+    let mut protect_fg = true; // multi-images are not placed over other objects
+    let mut view_rotation = config.get("view_rotation", 0).rem_euclid(4) as u8; // quarter turns
+    let mut show_preview = true; // the brush under the cursor, hidden to see the map there
+    let mut height_paint = false; // the mouse wheel raises and lowers the terrain under the brush
+    let mut raise_fill = false; // fill the columns below painted tiles up from the terrain
+    let mut scatter_rotate = false; // objects only on some cells of the brush, randomly rotated
+    let mut paint_rotation: u8 = 0; // quarter turns clockwise of painted objects
+    let mut erase_objects = false; // erase only foregrounds, multi-images as a whole
+    let mut flood_fill_pending = false; // the next click fills the area under it
+    let mut pattern = None; // picked with F1, repeated over a region with shift+F1
+
+    // erasing with larger brushes needs ctrl held, 0 = never
+    let erase_confirm_size = config.get("erase_confirm_size", 0) as i32;
+    let mut palette_scroll = 0; // hidden rows at the top of the palette
+    let (mut matrix, mut matrix_offset_y) =
+        make_matrix(scale, &atlas, palette_filter, palette_scroll);
 
     while window.is_open() {
         // frame time for deciding if zoom can be decreased
@@ -150,50 +597,1190 @@ fn main() {
         frame_timer.restart();
 
         let mouse_pos = win_to_grid(vi2f(window.mouse_position()), scale);
+        let view = ViewGrid::new(
+            dx,
+            dy,
+            win_to_grid(vu2f(window.size()), scale),
+            view_rotation,
+        );
+        let map_shift = if smooth_camera {
+            camera_shift(camera, view_rotation, scale)
+        } else {
+            Vector2f::new(0., 0.)
+        };
+        let map_mouse_pos = win_to_grid(vi2f(window.mouse_position()) + map_shift, scale);
         while let Some(event) = window.poll_event() {
+            // corners are taken on release, so the click does not paint afterwards
+            match (&region_input, event) {
+                (RegionInput::Off, _) => {}
+                (
+                    _,
+                    Event::KeyPressed {
+                        code: Key::ESCAPE, ..
+                    },
+                ) => {
+                    region_input = RegionInput::Off;
+                    continue;
+                }
+                (
+                    &RegionInput::First(action),
+                    Event::MouseButtonReleased {
+                        button: Button::LEFT,
+                        x,
+                        y,
+                    },
+                ) => {
+                    let pos =
+                        view.to_world(win_to_grid(vi2f(Vector2i::new(x, y)) + map_shift, scale));
+                    region_input = RegionInput::Second(action, pos.x, pos.y);
+                    continue;
+                }
+                (
+                    &RegionInput::Second(action, first_x, first_y),
+                    Event::MouseButtonReleased {
+                        button: Button::LEFT,
+                        x,
+                        y,
+                    },
+                ) => {
+                    let pos =
+                        view.to_world(win_to_grid(vi2f(Vector2i::new(x, y)) + map_shift, scale));
+                    let (pos_x, pos_y) = (pos.x, pos.y);
+                    let min = (
+                        first_x.min(pos_x),
+                        first_y.min(pos_y),
+                        dz - render_depth + 1,
+                    );
+                    let max = (first_x.max(pos_x), first_y.max(pos_y), dz + 1);
+                    command_message = match action {
+                        RegionAction::Export => {
+                            let export_name = format!("{}_export_{}_{}", db_name, min.0, min.1);
+                            match map.export_region_as_world(
+                                &export_name,
+                                db_dir,
+                                table_map,
+                                (min, max),
+                            ) {
+                                Ok(count) => format!(
+                                    "export: {} tiles of {},{} to {},{} saved as {}",
+                                    count, min.0, min.1, max.0, max.1, export_name
+                                ),
+                                Err(err) => format!("export: {}", err),
+                            }
+                        }
+                        RegionAction::Measure => {
+                            let mut counts: Vec<_> =
+                                map.count_tiles((min, max)).into_iter().collect();
+                            counts.sort_unstable_by_key(|&(image_id, count)| {
+                                (std::cmp::Reverse(count), image_id)
+                            });
+                            let total: usize = counts.iter().map(|(_, count)| count).sum();
+                            let materials: Vec<_> = counts
+                                .iter()
+                                .map(|&(image_id, count)| match material_name(image_id) {
+                                    Some(name) => format!("{}: {}", name, count),
+                                    None => format!("img:{}: {}", image_id, count),
+                                })
+                                .collect();
+                            format!(
+                                "volume: {} solid tiles in {}x{}x{}\n{}",
+                                total,
+                                max.0 - min.0 + 1,
+                                max.1 - min.1 + 1,
+                                max.2 - min.2 + 1,
+                                materials.join(", ")
+                            )
+                        }
+                        RegionAction::PatternPick => {
+                            let (width, height) = (max.0 - min.0 + 1, max.1 - min.1 + 1);
+                            let mut tiles = vec![];
+                            for y in min.1..=max.1 {
+                                for x in min.0..=max.0 {
+                                    // the topmost tile, like picking with alt
+                                    let tile = (min.2..=dz)
+                                        .rev()
+                                        .map(|z| map.get(x, y, z))
+                                        .find(|tile| tile.bg.is_some() || tile.fg.is_some());
+                                    tiles.push(tile);
+                                }
+                            }
+                            pattern = Some(Pattern::new(width, height, tiles));
+                            format!("pattern: {}x{} tiles (shift+F1: fill)", width, height)
+                        }
+                        RegionAction::PatternFill => match &pattern {
+                            Some(pattern) => {
+                                let tiles = pattern.fill(
+                                    (min.0, min.1),
+                                    (max.0, max.1),
+                                    dz,
+                                    (first_x, first_y),
+                                );
+                                map.history.begin();
+                                stats.tiles_painted += map.set_range(&tiles) as i64;
+                                map.history.end();
+                                save_clock.restart();
+                                map_modified = true;
+                                format!("pattern: filled {} tiles", tiles.len())
+                            }
+                            None => "pattern: pick one first (F1)".to_string(),
+                        },
+                        RegionAction::ExportCsv => {
+                            match map.export_csv_slice(
+                                CSV_SLICE_PATH,
+                                (min.0, min.1),
+                                (max.0, max.1),
+                                dz,
+                            ) {
+                                Ok(count) => format!(
+                                    "CSV: {} tiles of z {} saved to {}",
+                                    count, dz, CSV_SLICE_PATH
+                                ),
+                                Err(err) => format!("CSV: {}", err),
+                            }
+                        }
+                    };
+                    region_input = RegionInput::Off;
+                    continue;
+                }
+                _ => {}
+            }
+            // while placing a label, the keyboard is used for its name
+            match (&mut label_input, event) {
+                (
+                    LabelInput::Position | LabelInput::Name(..),
+                    Event::KeyPressed {
+                        code: Key::ESCAPE, ..
+                    },
+                ) => {
+                    label_input = LabelInput::Off;
+                    continue;
+                }
+                (
+                    LabelInput::Position,
+                    Event::MouseButtonPressed {
+                        button: Button::LEFT,
+                        x,
+                        y,
+                    },
+                ) => {
+                    let pos =
+                        view.to_world(win_to_grid(vi2f(Vector2i::new(x, y)) + map_shift, scale));
+                    label_input = LabelInput::Name(pos.x, pos.y, dz, String::new());
+                    continue;
+                }
+                (LabelInput::Name(x, y, z, name), event) => {
+                    match event {
+                        Event::KeyPressed {
+                            code: Key::ENTER, ..
+                        } => {
+                            if !name.is_empty() {
+                                labels.labels.push(Label {
+                                    x: *x,
+                                    y: *y,
+                                    z: *z,
+                                    name: name.clone(),
+                                });
+                                save_clock.restart();
+                                map_modified = true;
+                            }
+                            label_input = LabelInput::Off;
+                        }
+                        Event::KeyPressed {
+                            code: Key::BACKSPACE,
+                            ..
+                        } => {
+                            name.pop();
+                        }
+                        Event::TextEntered { unicode } if !unicode.is_control() => {
+                            name.push(unicode);
+                        }
+                        Event::Closed => window.close(),
+                        _ => {}
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            // while typing coordinates to go to, the keyboard is used for them
+            if let Some(text) = &mut goto_input {
+                match event {
+                    Event::KeyPressed {
+                        code: Key::ESCAPE, ..
+                    } => goto_input = None,
+                    Event::KeyPressed {
+                        code: Key::ENTER, ..
+                    } => {
+                        command_message = match parse_coordinates(text) {
+                            Some((x, y, z)) => {
+                                let grid_size = win_to_grid(vu2f(window.size()), scale);
+                                dx = x - grid_size.x / 2;
+                                dy = y - grid_size.y / 2;
+                                dz = z.unwrap_or(dz);
+                                // the smooth camera would glide on from there
+                                camera_velocity = Vector2f::new(0., 0.);
+                                format!("went to {},{},{}", x, y, dz)
+                            }
+                            None => format!("go to: expected x,y or x,y,z instead of '{}'", text),
+                        };
+                        goto_input = None;
+                    }
+                    Event::KeyPressed {
+                        code: Key::BACKSPACE,
+                        ..
+                    } => {
+                        text.pop();
+                    }
+                    Event::TextEntered { unicode }
+                        if unicode.is_ascii_digit() || matches!(unicode, '-' | ',' | ' ') =>
+                    {
+                        text.push(unicode);
+                    }
+                    Event::Closed => window.close(),
+                    _ => {}
+                }
+                continue;
+            }
+            // while the noise panel is open, the arrow keys change the terrain noise
+            if let Some(selected) = &mut noise_panel {
+                let count = NoiseParam::ALL.len();
+                let handled = match event {
+                    Event::KeyPressed {
+                        code: Key::ESCAPE, ..
+                    } => {
+                        noise_panel = None;
+                        true
+                    }
+                    Event::KeyPressed { code: Key::UP, .. } => {
+                        *selected = (*selected + count - 1) % count;
+                        true
+                    }
+                    Event::KeyPressed {
+                        code: Key::DOWN, ..
+                    } => {
+                        *selected = (*selected + 1) % count;
+                        true
+                    }
+                    Event::KeyPressed {
+                        code: code @ (Key::LEFT | Key::RIGHT),
+                        shift,
+                        ..
+                    } => {
+                        let steps = if shift { 5 } else { 1 };
+                        let steps = if code == Key::LEFT { -steps } else { steps };
+                        map.nudge_terrain_noise(NoiseParam::ALL[*selected], steps);
+                        save_clock.restart();
+                        map_modified = true;
+                        true
+                    }
+                    _ => false,
+                };
+                if handled {
+                    continue;
+                }
+            }
+            // the rebindable keys, see keymap.rs
+            let action = match event {
+                Event::KeyPressed {
+                    code,
+                    ctrl: false,
+                    shift: false,
+                    alt: false,
+                    ..
+                } => keymap.action(code),
+                _ => None,
+            };
             match event {
+                Event::KeyPressed {
+                    code: Key::ESCAPE, ..
+                } if show_slots => show_slots = false,
+                Event::KeyPressed { code, .. }
+                    if show_slots
+                        && number_key(code)
+                            .is_some_and(|n| (1..=SLOT_COUNT as usize).contains(&n)) =>
+                {
+                    // the current slot is saved first
+                    match number_key(code).unwrap() as u8 {
+                        chosen if chosen == slot => show_slots = false,
+                        chosen => next_slot = Some(chosen),
+                    }
+                }
+                Event::KeyPressed {
+                    code: Key::O,
+                    ctrl: true,
+                    ..
+                } => {
+                    show_slots = !show_slots;
+                    if show_slots {
+                        slot_infos = slots::list(saver.db(), world);
+                    }
+                }
+                Event::KeyPressed {
+                    code: Key::T,
+                    ctrl: true,
+                    ..
+                } => {
+                    noise_panel = match noise_panel {
+                        Some(_) => None,
+                        None => Some(0),
+                    };
+                }
                 Event::Closed
                 | Event::KeyPressed {
                     code: Key::ESCAPE, ..
                 } => window.close(),
-                Event::KeyPressed { code: Key::X, .. }
-                | Event::KeyPressed {
-                    code: Key::DELETE, ..
+                _ if action == Some(Action::Erase) => {
+                    mode = Mode::Erase;
+                    mouse_selection = MouseObject::ImageId(eraser);
+                }
+                _ if action == Some(Action::TogglePaintErase) => {
+                    // unlike erase, the selected image stays for switching back
+                    mode = match mode {
+                        Mode::Paint => Mode::Erase,
+                        Mode::Erase | Mode::RectFill => Mode::Paint,
+                    };
+                }
+                _ if action == Some(Action::ToggleFog) => {
+                    fog = !fog;
+                }
+                _ if action == Some(Action::CursorGrow) => {
+                    cursor_size_increase(&mut cursor_size);
+                }
+                _ if action == Some(Action::CursorShrink) => {
+                    cursor_size_decrease(&mut cursor_size);
+                }
+                _ if action == Some(Action::Slope) => {
+                    slope = match slope {
+                        0 => 1,
+                        1 => -1,
+                        _ => 0,
+                    };
+                }
+                _ if action == Some(Action::Label) => {
+                    label_input = LabelInput::Position;
+                }
+                // the typed character, ':' is on another key on many keyboard layouts
+                Event::TextEntered { unicode: ':' } => {
+                    goto_input = Some(String::new());
+                }
+                _ if action == Some(Action::Measure) => {
+                    region_input = RegionInput::First(RegionAction::Measure);
+                }
+                _ if action == Some(Action::ToggleMinimap) => {
+                    minimap.visible = !minimap.visible;
+                }
+                _ if action == Some(Action::ToggleStats) => {
+                    show_stats = !show_stats;
+                }
+                _ if action == Some(Action::RotateLeft) => {
+                    paint_rotation = (paint_rotation + 3) % 4;
+                }
+                _ if action == Some(Action::RotateRight) => {
+                    paint_rotation = (paint_rotation + 1) % 4;
+                }
+                Event::KeyPressed {
+                    code: Key::X,
+                    shift: true,
+                    ..
+                } => {
+                    erase_objects = !erase_objects;
+                    mode = Mode::Erase;
+                    mouse_selection = MouseObject::ImageId(eraser);
+                }
+                Event::KeyPressed {
+                    code: Key::V,
+                    shift: true,
+                    ..
+                } => {
+                    fog_cardinal = !fog_cardinal;
+                }
+                Event::KeyPressed {
+                    code, ctrl: true, ..
+                } if matches!(number_key(code), Some(0..=3)) => {
+                    palette_filter = match number_key(code) {
+                        Some(1) => Some(Category::Terrain),
+                        Some(2) => Some(Category::Vegetation),
+                        Some(3) => Some(Category::Ores),
+                        _ => None,
+                    };
+                    palette_scroll = 0;
+                    (matrix, matrix_offset_y) =
+                        make_matrix(scale, &atlas, palette_filter, palette_scroll);
+                }
+                Event::KeyPressed {
+                    code, alt: true, ..
+                } if matches!(number_key(code), Some(1..=6)) => {
+                    // cursor size presets
+                    cursor_size = [1, 3, 5, 9, 17, 31][number_key(code).unwrap() - 1];
+                }
+                Event::KeyPressed { code, shift, .. }
+                    if matches!(number_key(code), Some(1..=9)) =>
+                {
+                    // shift+1..9 stores the current brush, 1..9 recalls it
+                    let slot = number_key(code).unwrap() - 1;
+                    if shift {
+                        brushes.slots[slot] = Some(Brush {
+                            image_id: mouse_selection.image_id(),
+                            cursor_size,
+                        });
+                        save_clock.restart();
+                        map_modified = true;
+                    } else if let Some(brush) = brushes.slots[slot] {
+                        mouse_selection =
+                            MouseObject::from_image_id(brush.image_id, &multi_objects);
+                        cursor_size = brush.cursor_size;
+                        mode = if brush.image_id == eraser {
+                            Mode::Erase
+                        } else {
+                            Mode::Paint
+                        };
+                    }
+                }
+                Event::KeyPressed { code, ctrl, .. } if numpad_key(code).is_some() => {
+                    // ctrl+numpad 1..9 stores the camera, numpad 1..9 jumps to it
+                    let Some(slot) = numpad_key(code).filter(|&n| n > 0).map(|n| n - 1) else {
+                        continue;
+                    };
+                    if ctrl {
+                        bookmarks.slots[slot] = Some(Camera { dx, dy, dz, scale });
+                        save_clock.restart();
+                        map_modified = true;
+                        command_message = format!("saved bookmark {}", slot + 1);
+                    } else if let Some(camera) = bookmarks.slots[slot] {
+                        (dx, dy, dz) = (camera.dx, camera.dy, camera.dz);
+                        if scale != camera.scale {
+                            scale = camera.scale;
+                            (matrix, matrix_offset_y) =
+                                make_matrix(scale, &atlas, palette_filter, palette_scroll);
+                        }
+                        command_message = format!("bookmark {}", slot + 1);
+                    } else {
+                        command_message =
+                            format!("bookmark {} is empty (ctrl+numpad: save)", slot + 1);
+                    }
+                }
+                Event::KeyPressed {
+                    code: Key::G,
+                    alt: true,
+                    ..
+                } => {
+                    flood_fill_pending = !flood_fill_pending;
+                }
+                Event::KeyPressed {
+                    code: Key::G,
+                    shift,
+                    ..
+                } => {
+                    // debug: regenerate the chunk under the cursor, shift+G twice also discards edits
+                    let Vector2i { x: pos_x, y: pos_y } = view.to_world(map_mouse_pos);
+                    let (chunk_x, chunk_y, chunk_z) = Map::chunk_coords(pos_x, pos_y, dz);
+                    if shift && !confirm(&mut pending_confirm, Key::G, shift) {
+                        command_message = format!(
+                            "press shift+G again to discard edits in chunk {},{},{}",
+                            chunk_x, chunk_y, chunk_z
+                        );
+                    } else {
+                        map.history.begin();
+                        map.regenerate_chunk(pos_x, pos_y, dz, shift);
+                        map.history.end();
+                        if shift {
+                            save_clock.restart();
+                            map_modified = true;
+                        }
+                        command_message = format!(
+                            "regenerated chunk {},{},{}{}",
+                            chunk_x,
+                            chunk_y,
+                            chunk_z,
+                            if shift { " (edits discarded)" } else { "" }
+                        );
+                    }
+                }
+                Event::KeyPressed {
+                    code: Key::F2,
+                    shift,
+                    ..
+                } => {
+                    // winter: water becomes ice, shift+F2 also bakes the generated chunks in memory
+                    if confirm(&mut pending_confirm, Key::F2, shift) {
+                        map.history.begin();
+                        let count = map.replace_all(WATER, ICE, shift);
+                        map.history.end();
+                        if count > 0 {
+                            save_clock.restart();
+                            map_modified = true;
+                        }
+                        command_message = format!("replaced {} water tiles with ice", count);
+                    } else {
+                        command_message = format!(
+                            "press {}F2 again to replace water with ice{}",
+                            if shift { "shift+" } else { "" },
+                            if shift { " (including generated)" } else { "" }
+                        );
+                    }
+                }
+                Event::MouseButtonPressed {
+                    button: Button::MIDDLE,
+                    ..
+                } => {
+                    middle_button_start_window_xy = Some(window.mouse_position());
+                    middle_button_start_grid_xy = Some(Vector2i { x: dx, y: dy });
+                }
+                Event::MouseButtonReleased {
+                    button: Button::MIDDLE,
+                    ..
+                } => {
+                    middle_button_start_window_xy = None;
+                    middle_button_start_grid_xy = None;
+                }
+                Event::MouseButtonReleased {
+                    button: Button::LEFT,
+                    x,
+                    y,
+                } if flood_fill_pending => {
+                    flood_fill_pending = false;
+                    let pos =
+                        view.to_world(win_to_grid(vi2f(Vector2i::new(x, y)) + map_shift, scale));
+                    command_message = match mouse_selection.clone() {
+                        MouseObject::ImageId(image_id) if image_id != eraser => {
+                            let filled = map.flood_fill(
+                                pos.x,
+                                pos.y,
+                                dz,
+                                image_id,
+                                atlas.is_background(image_id),
+                            );
+                            match filled {
+                                Some(count) => {
+                                    stats.tiles_painted += count as i64;
+                                    save_clock.restart();
+                                    map_modified = true;
+                                    format!("flood fill: {} tiles", count)
+                                }
+                                None => format!(
+                                    "flood fill: more than {} tiles, nothing filled",
+                                    FLOOD_FILL_MAX_TILES
+                                ),
+                            }
+                        }
+                        _ => "flood fill: select a single image first".to_string(),
+                    };
+                }
+                Event::MouseButtonPressed {
+                    button: Button::LEFT,
+                    x,
+                    y,
+                } if minimap.world_pos(vi2f(Vector2i::new(x, y))).is_some() => {
+                    // center the camera on the clicked position
+                    if let Some((pos_x, pos_y)) = minimap.world_pos(vi2f(Vector2i::new(x, y))) {
+                        let grid_size = win_to_grid(vu2f(window.size()), scale);
+                        dx = pos_x - grid_size.x / 2;
+                        dy = pos_y - grid_size.y / 2;
+                    }
+                }
+                Event::MouseButtonPressed {
+                    button: Button::LEFT,
+                    x,
+                    y,
+                } => {
+                    // everything painted until the button is released is one undo step
+                    map.history.begin();
+                    paint_last = None;
+                    surface_columns.clear();
+                    let grid_pos = win_to_grid(vi2f(Vector2i::new(x, y)), scale);
+                    let over_palette = grid_pos.x < IMAGES_USED_X as i32
+                        && grid_pos.y >= matrix_offset_y
+                        && grid_pos.y < IMAGES_USED_Y as i32 + matrix_offset_y;
+                    let alt = Key::is_pressed(Key::LALT) || Key::is_pressed(Key::RALT);
+                    if matches!(mode, Mode::RectFill) && !over_palette && !alt {
+                        let pos = win_to_grid(vi2f(Vector2i::new(x, y)) + map_shift, scale);
+                        rect_corner = Some(view.to_world(pos));
+                    }
+                }
+                Event::MouseButtonReleased {
+                    button: Button::LEFT,
+                    x,
+                    y,
+                } => {
+                    slope_stroke = None;
+                    paint_last = None;
+                    surface_columns.clear();
+                    if let Some(first) = rect_corner.take() {
+                        let second = view
+                            .to_world(win_to_grid(vi2f(Vector2i::new(x, y)) + map_shift, scale));
+                        command_message = match rect_fill_tile(&mouse_selection, eraser, &atlas) {
+                            Some(tile) => {
+                                let mut tiles = vec![];
+                                for y in first.y.min(second.y)..=first.y.max(second.y) {
+                                    for x in first.x.min(second.x)..=first.x.max(second.x) {
+                                        tiles.push((x, y, dz, tile));
+                                    }
+                                }
+                                let changed = map.set_range(&tiles) as i64;
+                                if tile.bg.is_none() {
+                                    stats.tiles_erased += changed;
+                                } else {
+                                    stats.tiles_painted += changed;
+                                }
+                                save_clock.restart();
+                                map_modified = true;
+                                format!("fill: {} tiles", tiles.len())
+                            }
+                            None => "fill: multi-images can not fill a rectangle".to_string(),
+                        };
+                    }
+                    map.history.end();
+                }
+                Event::KeyPressed {
+                    code: Key::Z,
+                    ctrl: true,
+                    shift: true,
+                    ..
+                } => {
+                    if map.redo() {
+                        save_clock.restart();
+                        map_modified = true;
+                    }
+                }
+                Event::KeyPressed {
+                    code: Key::Z,
+                    ctrl: true,
+                    ..
+                } => {
+                    if map.undo() {
+                        save_clock.restart();
+                        map_modified = true;
+                    }
+                }
+                Event::KeyPressed {
+                    code: Key::PAGEUP, ..
+                } => {
+                    max_vertices *= 2;
+                }
+                Event::KeyPressed {
+                    code: Key::PAGEDOWN,
+                    ..
+                } => {
+                    max_vertices = (max_vertices / 2).max(10_000);
+                }
+                Event::KeyPressed {
+                    code: Key::ENTER, ..
+                } if recovery.is_some() => {
+                    let strokes = recovery.take().unwrap_or_default();
+                    // the replay records the strokes again
+                    if let Err(e) = map.journal.clear() {
+                        println!("{}", e);
+                    }
+                    map.replay(&strokes);
+                    save_clock.restart();
+                    map_modified = true;
+                    command_message = format!("journal: replayed {} strokes", strokes.len());
+                }
+                Event::KeyPressed {
+                    code: Key::BACKSPACE,
+                    ..
+                } if recovery.is_some() => {
+                    if let Err(e) = map.journal.clear() {
+                        println!("{}", e);
+                    }
+                    let strokes = recovery.take().unwrap_or_default();
+                    command_message = format!("journal: discarded {} strokes", strokes.len());
+                }
+                Event::KeyPressed { code: Key::F8, .. } => {
+                    map.scratch_begin();
+                }
+                Event::KeyPressed {
+                    code: Key::ENTER, ..
+                } if map.scratch_len().is_some() => {
+                    let count = map.scratch_commit();
+                    save_clock.restart();
+                    map_modified = true;
+                    command_message = format!("scratch: committed {} tiles", count);
+                }
+                Event::KeyPressed {
+                    code: Key::BACKSPACE,
+                    ..
+                } if map.scratch_len().is_some() => {
+                    let count = map.scratch_discard();
+                    command_message = format!("scratch: discarded {} tiles", count);
+                }
+                Event::KeyPressed { code: Key::F7, .. } => {
+                    show_edit_age = !show_edit_age;
+                }
+                Event::KeyPressed { code: Key::F6, .. } => {
+                    show_debug_sample = !show_debug_sample;
+                }
+                Event::KeyPressed { code: Key::F5, .. } => {
+                    let issues = map.validate();
+                    for issue in &issues {
+                        println!("{}", issue);
+                    }
+                    command_message = match issues.first() {
+                        None => "validate: no issues".to_string(),
+                        Some(issue) => format!("validate: {} issues, e.g. {}", issues.len(), issue),
+                    };
+                }
+                Event::KeyPressed { code: Key::F3, .. } => {
+                    // average color of the visible surface, e.g. for palette studies. The view
+                    // cells are turned into world tiles like in the render loop.
+                    let grid_size = win_to_grid(vu2f(window.size()), scale);
+                    let (mut r, mut g, mut b, mut n) = (0u64, 0u64, 0u64, 0u64);
+                    for view_y in 0..=grid_size.y {
+                        for view_x in 0..=grid_size.x {
+                            let Vector2i { x: pos_x, y: pos_y } =
+                                view.to_world(Vector2i::new(view_x, view_y));
+                            for pos_z in (dz - render_depth + 1..=dz).rev() {
+                                let tile = map.get(pos_x, pos_y, pos_z);
+                                if let Some(image_id) = tile.fg.or(tile.bg) {
+                                    let color = image_colors[image_id as usize];
+                                    r += color.r as u64;
+                                    g += color.g as u64;
+                                    b += color.b as u64;
+                                    n += 1;
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    let n = n.max(1);
+                    sampled_color = Some(Color::rgb((r / n) as u8, (g / n) as u8, (b / n) as u8));
+                }
+                Event::KeyPressed {
+                    code: Key::F9,
+                    shift: true,
+                    ..
+                } => {
+                    // description of the atlas for external tools
+                    let path = "w8_tileset.json";
+                    command_message =
+                        match export_tileset_meta(path, example_res!("palette.png"), &atlas) {
+                            Ok(count) => format!("tileset: {} images saved to {}", count, path),
+                            Err(err) => format!("tileset: {}", err),
+                        };
+                }
+                Event::KeyPressed { code: Key::F9, .. } => {
+                    // overview of the whole stored world as an image, one pixel per tile
+                    let path = "w8_overview.png";
+                    command_message =
+                        match map.render_overview(saver.db(), table_map, &image_colors) {
+                            Ok((image, (min_x, min_y))) if image.save_to_file(path) => {
+                                let size = image.size();
+                                format!(
+                                    "overview: {}x{} tiles from {},{} saved to {}",
+                                    size.x, size.y, min_x, min_y, path
+                                )
+                            }
+                            Ok(_) => format!("overview: could not save {}", path),
+                            Err(err) => format!("overview: {}", err),
+                        };
+                }
+                Event::KeyPressed {
+                    code: Key::F11,
+                    ctrl: true,
+                    ..
+                } => {
+                    // the upper left corner of the CSV is put under the cursor
+                    let Vector2i { x: pos_x, y: pos_y } = view.to_world(map_mouse_pos);
+                    command_message = match map.import_csv_slice(CSV_SLICE_PATH, pos_x, pos_y, dz) {
+                        Ok(count) => {
+                            save_clock.restart();
+                            map_modified = true;
+                            format!("CSV: {} tiles imported from {}", count, CSV_SLICE_PATH)
+                        }
+                        Err(err) => format!("CSV: {}", err),
+                    };
+                }
+                Event::KeyPressed { code: Key::F11, .. } => {
+                    // nearest surface water, searched in growing squares around the center
+                    let grid_size = win_to_grid(vu2f(window.size()), scale);
+                    let center = (dx + grid_size.x / 2, dy + grid_size.y / 2);
+                    let mut found = None;
+                    'search: for radius in 0..=WATER_SEARCH_RADIUS {
+                        for iy in -radius..=radius {
+                            for ix in -radius..=radius {
+                                if ix.abs() != radius && iy.abs() != radius {
+                                    continue;
+                                }
+                                let (pos_x, pos_y) = (center.0 + ix, center.1 + iy);
+                                if let Some(pos_z) =
+                                    map.surface_height(pos_x, pos_y, dz, render_depth)
+                                {
+                                    if map.get(pos_x, pos_y, pos_z).bg == Some(WATER) {
+                                        found = Some((pos_x, pos_y, radius));
+                                        break 'search;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    command_message = match found {
+                        Some((pos_x, pos_y, distance)) => {
+                            dx = pos_x - grid_size.x / 2;
+                            dy = pos_y - grid_size.y / 2;
+                            format!("water at {},{}, {} tiles away", pos_x, pos_y, distance)
+                        }
+                        None => "no water found nearby".to_string(),
+                    };
+                }
+                Event::KeyPressed {
+                    code: Key::F10,
+                    ctrl: true,
+                    ..
                 } => {
-                    mode = Mode::Erase;
-                    mouse_selection = MouseObject::ImageId(eraser);
+                    region_input = RegionInput::First(RegionAction::ExportCsv);
                 }
-                Event::KeyPressed { code: Key::V, .. } => {
-                    fog = !fog;
+                Event::KeyPressed {
+                    code: Key::F10,
+                    shift: true,
+                    ..
+                } => {
+                    command_message = match map.compact(saver.db(), table_map) {
+                        Ok((before, after)) => {
+                            // written to disk with the next save
+                            save_clock.restart();
+                            map_modified = true;
+                            format!("compact: {} rows before, {} after", before, after)
+                        }
+                        Err(err) => format!("compact: {}", err),
+                    };
+                }
+                Event::KeyPressed { code: Key::F10, .. } => {
+                    region_input = RegionInput::First(RegionAction::Export);
                 }
                 Event::KeyPressed {
-                    code: Key::EQUAL, ..
+                    code: Key::F1,
+                    shift,
+                    ..
                 } => {
-                    cursor_size_increase(&mut cursor_size);
+                    region_input = RegionInput::First(if shift {
+                        RegionAction::PatternFill
+                    } else {
+                        RegionAction::PatternPick
+                    });
+                }
+                Event::KeyPressed { code: Key::TAB, .. } => {
+                    // jump to the next label
+                    if !labels.labels.is_empty() {
+                        label_idx = (label_idx + 1) % labels.labels.len();
+                        let label = &labels.labels[label_idx];
+                        let grid_size = win_to_grid(vu2f(window.size()), scale);
+                        dx = label.x - grid_size.x / 2;
+                        dy = label.y - grid_size.y / 2;
+                        dz = label.z;
+                        command_message = format!(
+                            "label {}/{}: {}",
+                            label_idx + 1,
+                            labels.labels.len(),
+                            label.name
+                        );
+                    }
+                }
+                Event::KeyPressed {
+                    code: Key::O,
+                    shift: true,
+                    ..
+                } => {
+                    map.iron_ore_count = 0;
+                    map.copper_ore_count = 0;
+                    map.gold_ore_count = 0;
+                    map.coal_ore_count = 0;
                 }
                 Event::KeyPressed {
-                    code: Key::HYPHEN, ..
+                    code: Key::U,
+                    ctrl: true,
+                    ..
                 } => {
-                    cursor_size_decrease(&mut cursor_size);
+                    time_of_day = (time_of_day + 1.0 / 24.0).fract();
                 }
-                Event::MouseButtonPressed {
-                    button: Button::MIDDLE,
+                Event::KeyPressed {
+                    code: Key::U,
+                    shift: true,
                     ..
                 } => {
-                    middle_button_start_window_xy = Some(window.mouse_position());
-                    middle_button_start_grid_xy = Some(Vector2i { x: dx, y: dy });
+                    day_night_paused = !day_night_paused;
                 }
-                Event::MouseButtonReleased {
-                    button: Button::MIDDLE,
+                Event::KeyPressed { code: Key::U, .. } => {
+                    day_night = !day_night;
+                }
+                Event::KeyPressed {
+                    code: Key::I,
+                    ctrl: true,
                     ..
                 } => {
-                    middle_button_start_window_xy = None;
-                    middle_button_start_grid_xy = None;
+                    // the column under the mouse, starting at the current z
+                    inspector = match inspector {
+                        Some(_) => None,
+                        None => {
+                            let column = view.to_world(map_mouse_pos);
+                            Some((column.x, column.y, dz))
+                        }
+                    };
+                }
+                Event::KeyPressed { code: Key::UP, .. } if inspector.is_some() => {
+                    if let Some((_, _, selected_z)) = &mut inspector {
+                        *selected_z = (*selected_z + 1).min(dz);
+                    }
+                }
+                Event::KeyPressed {
+                    code: Key::DOWN, ..
+                } if inspector.is_some() => {
+                    if let Some((_, _, selected_z)) = &mut inspector {
+                        *selected_z = (*selected_z - 1).max(dz - render_depth + 1);
+                    }
+                }
+                Event::KeyPressed {
+                    code: Key::I,
+                    alt: true,
+                    ..
+                } => {
+                    // the column tooltip is shown while alt+I is held, see below
+                }
+                Event::KeyPressed { code: Key::I, .. } => {
+                    tex_inset = !tex_inset;
+                    command_message = format!("texture inset: {}", tex_inset);
+                }
+                Event::KeyPressed { code: Key::O, .. } => {
+                    accumulate_ores = !accumulate_ores;
+                }
+                Event::KeyPressed {
+                    code: Key::M,
+                    shift: true,
+                    ..
+                } => {
+                    protect_fg = !protect_fg;
+                    command_message = if protect_fg {
+                        "multi-images: not placed over other objects".to_string()
+                    } else {
+                        "multi-images: overwrite other objects".to_string()
+                    };
+                }
+                Event::KeyPressed {
+                    code: Key::M,
+                    ctrl: true,
+                    ..
+                } => {
+                    smooth_camera = !smooth_camera;
+                    camera = Vector2f::new(dx as f32, dy as f32);
+                    camera_velocity = Vector2f::new(0., 0.);
+                    command_message = if smooth_camera {
+                        "camera: smooth".to_string()
+                    } else {
+                        "camera: stepped".to_string()
+                    };
+                }
+                Event::KeyPressed { code: Key::B, .. } => {
+                    dim_map = !dim_map;
+                }
+                Event::KeyPressed {
+                    code: Key::Y,
+                    ctrl: true,
+                    ..
+                } => {
+                    seed_preview = None;
+                }
+                Event::KeyPressed {
+                    code: Key::Y,
+                    shift: true,
+                    ..
+                } => {
+                    if let Some((seed, _)) = seed_preview.take() {
+                        map.seed = seed;
+                        map.clear_generated();
+                        command_message = format!("seed: {}", seed);
+                        save_clock.restart();
+                        map_modified = true;
+                    }
+                }
+                Event::KeyPressed { code: Key::Y, .. } => {
+                    let seed = seed_preview
+                        .as_ref()
+                        .map_or(map.seed, |(seed, _)| *seed)
+                        .wrapping_add(1);
+                    let image =
+                        map.preview_seed(seed, SEED_PREVIEW_SIZE, SEED_PREVIEW_STEP, &image_colors);
+                    seed_preview = Texture::from_image(&image).map(|texture| (seed, texture));
+                }
+                Event::KeyPressed { code: Key::Q, .. } => {
+                    glow = !glow;
+                    command_message = format!("glowing images: {}", glow);
+                }
+                Event::KeyPressed {
+                    code: Key::J,
+                    shift: true,
+                    ..
+                } => {
+                    scatter_rotate = !scatter_rotate;
+                }
+                Event::KeyPressed {
+                    code: Key::J,
+                    ctrl: true,
+                    ..
+                } => {
+                    hide_fg_zoomed_out = !hide_fg_zoomed_out;
+                }
+                Event::KeyPressed { code: Key::J, .. } => {
+                    jitter_fg = !jitter_fg;
+                }
+                Event::KeyPressed { code: Key::N, .. } => {
+                    show_variants = !show_variants;
+                }
+                Event::KeyPressed {
+                    code: Key::H,
+                    ctrl: true,
+                    ..
+                } => {
+                    height_paint = !height_paint;
+                }
+                Event::KeyPressed { code: Key::H, .. } => {
+                    map.vegetation_mode = match map.vegetation_mode {
+                        VegetationMode::Noise => VegetationMode::Hash,
+                        VegetationMode::Hash => VegetationMode::Noise,
+                    };
+                    map.clear_generated();
+                }
+                Event::KeyPressed {
+                    code: Key::F,
+                    shift: true,
+                    ..
+                } => {
+                    surface_paint = !surface_paint;
+                }
+                Event::KeyPressed {
+                    code: Key::E,
+                    shift: true,
+                    ..
+                } => {
+                    map.sea_level = (map.sea_level + 1).min(SEA_LEVEL_MAX);
+                    map.clear_generated();
+                }
+                Event::KeyPressed {
+                    code: Key::E,
+                    ctrl: true,
+                    ..
+                } => {
+                    map.sea_level = (map.sea_level - 1).max(-SEA_LEVEL_MAX);
+                    map.clear_generated();
+                }
+                Event::KeyPressed { code: Key::E, .. } => {
+                    let idx = TERRACE_STEPS
+                        .iter()
+                        .position(|&step| step == map.terrace_step)
+                        .map_or(0, |idx| (idx + 1) % TERRACE_STEPS.len());
+                    map.terrace_step = TERRACE_STEPS[idx];
+                    map.clear_generated();
+                }
+                Event::KeyPressed {
+                    code: Key::P,
+                    ctrl: true,
+                    ..
+                } => {
+                    show_generation = !show_generation;
+                }
+                Event::KeyPressed {
+                    code: Key::P,
+                    shift: true,
+                    ..
+                } => {
+                    show_preview = !show_preview;
+                }
+                Event::KeyPressed { code: Key::P, .. } => {
+                    show_provenance = !show_provenance;
+                }
+                Event::KeyPressed { code: Key::C, .. } => {
+                    show_compass = !show_compass;
+                }
+                Event::KeyPressed { code: Key::F4, .. } => {
+                    show_crosshair = !show_crosshair;
+                }
+                Event::KeyPressed {
+                    code: Key::K,
+                    ctrl: true,
+                    ..
+                } => {
+                    show_contours = !show_contours;
+                }
+                Event::KeyPressed {
+                    code: Key::K,
+                    shift: true,
+                    ..
+                } => {
+                    let idx = CONTOUR_INTERVALS
+                        .iter()
+                        .position(|&interval| interval == contour_interval)
+                        .map_or(0, |idx| (idx + 1) % CONTOUR_INTERVALS.len());
+                    contour_interval = CONTOUR_INTERVALS[idx];
+                    show_contours = true;
+                }
+                Event::KeyPressed { code: Key::K, .. } => {
+                    sky_color_idx = (sky_color_idx + 1) % SKY_COLORS.len();
+                }
+                Event::KeyPressed {
+                    code: Key::R,
+                    shift: true,
+                    ..
+                } => {
+                    raise_fill = !raise_fill;
+                }
+                Event::KeyPressed {
+                    code: Key::R,
+                    ctrl: true,
+                    ..
+                } => {
+                    view_rotation = (view_rotation + 1) % 4;
+                    command_message =
+                        format!("view rotated by {} degrees", view_rotation as u32 * 90);
+                }
+                Event::KeyPressed {
+                    code: Key::R,
+                    alt: true,
+                    ..
+                } => {
+                    mode = Mode::RectFill;
+                }
+                Event::KeyPressed {
+                    code: Key::BACKSLASH,
+                    ..
+                } => {
+                    adaptive_depth = !adaptive_depth;
+                }
+                Event::KeyPressed {
+                    code: Key::LBRACKET,
+                    ctrl: true,
+                    ..
+                } => {
+                    render_depth = (render_depth - 5).max(5);
+                }
+                Event::KeyPressed {
+                    code: Key::RBRACKET,
+                    ctrl: true,
+                    ..
+                } => {
+                    render_depth += 5;
+                }
+                Event::KeyPressed {
+                    code: Key::LBRACKET,
+                    ..
+                } => {
+                    slope_ratio = (slope_ratio - 1).max(1);
+                }
+                Event::KeyPressed {
+                    code: Key::RBRACKET,
+                    ..
+                } => {
+                    slope_ratio += 1;
                 }
                 #[allow(unused_variables)]
                 Event::MouseWheelScrolled { wheel, delta, x, y } => {
+                    let grid_pos = win_to_grid(vi2f(Vector2i::new(x, y)), scale);
+                    let rows = palette_rows(&atlas, palette_filter);
+                    let over_palette = grid_pos.x < IMAGES_USED_X as i32
+                        && grid_pos.y >= matrix_offset_y
+                        && grid_pos.y < matrix_offset_y + rows - palette_scroll;
                     if wheel == Wheel::Vertical {
-                        if Key::is_pressed(Key::LALT) || Key::is_pressed(Key::RALT) {
+                        if over_palette {
+                            palette_scroll = (palette_scroll - delta as i32).clamp(0, rows - 1);
+                            (matrix, matrix_offset_y) =
+                                make_matrix(scale, &atlas, palette_filter, palette_scroll);
+                        } else if Key::is_pressed(Key::LALT) || Key::is_pressed(Key::RALT) {
                             if delta > 0.0 {
                                 cursor_size_increase(&mut cursor_size);
                             } else {
@@ -217,7 +1804,8 @@ fn main() {
                                     scale = (1.1 + scale).floor()
                                 }
                             }
-                            (matrix, matrix_offset_y) = make_matrix(scale);
+                            (matrix, matrix_offset_y) =
+                                make_matrix(scale, &atlas, palette_filter, palette_scroll);
 
                             // when scale is changed, we need to update the map position
                             let device_pixels_per_tile = TILESIZE as f32 * scale;
@@ -240,6 +1828,36 @@ fn main() {
                                 dx -= delta_tiles_relative.x;
                                 dy -= delta_tiles_relative.y;
                             }
+                        } else if height_paint {
+                            // raised columns get the selected background, or dirt
+                            let material = match mouse_selection.image_id() {
+                                image_id if atlas.is_background(image_id) => image_id,
+                                _ => DIRT,
+                            };
+                            let pos = view.to_world(win_to_grid(
+                                vi2f(Vector2i::new(x, y)) + map_shift,
+                                scale,
+                            ));
+                            let plus_half = cursor_size / 2;
+                            let minus_half = cursor_size - plus_half - 1;
+                            let mut columns = vec![];
+                            for iy in -minus_half..=plus_half {
+                                for ix in -minus_half..=plus_half {
+                                    columns.push((pos.x + ix, pos.y + iy));
+                                }
+                            }
+                            map.history.begin();
+                            let count = map.sculpt(
+                                &columns,
+                                dz,
+                                render_depth,
+                                (delta > 0.).then_some(material),
+                            );
+                            map.history.end();
+                            if count > 0 {
+                                save_clock.restart();
+                                map_modified = true;
+                            }
                         } else {
                             dz -= delta as i32;
                         }
@@ -259,52 +1877,110 @@ fn main() {
             }
         }
 
-        if window.has_focus() {
+        if window.has_focus()
+            && !matches!(label_input, LabelInput::Name(..))
+            && goto_input.is_none()
+        {
             const F: f32 = 6.0;
+            // the keys move along the screen, which is rotated against the world
+            let step = (F / scale).max(1.0) as i32;
+            // up and down move the z cursor of the column inspector instead, the arrow keys
+            // change the noise panel
+            let pressed = |action| {
+                keymap.keys(action).any(|key| {
+                    Key::is_pressed(key)
+                        && !(inspector.is_some() && (key == Key::UP || key == Key::DOWN))
+                        && !(noise_panel.is_some()
+                            && matches!(key, Key::UP | Key::DOWN | Key::LEFT | Key::RIGHT))
+                })
+            };
+            let mut pan = Vector2i::new(0, 0);
             if clock_dy.elapsed_time().as_milliseconds() > 30 {
-                if Key::is_pressed(Key::S) || Key::is_pressed(Key::DOWN) {
-                    dy += (F / scale).max(1.0) as i32;
+                if pressed(Action::PanDown) {
+                    pan.y += step;
                     clock_dy.restart();
-                } else if Key::is_pressed(Key::W) || Key::is_pressed(Key::UP) {
-                    dy -= (F / scale).max(1.0) as i32;
+                } else if pressed(Action::PanUp) {
+                    pan.y -= step;
                     clock_dy.restart();
                 }
             }
             if clock_dx.elapsed_time().as_milliseconds() > 30 {
-                if Key::is_pressed(Key::D) || Key::is_pressed(Key::RIGHT) {
-                    dx += (F / scale).max(1.0) as i32;
+                if pressed(Action::PanRight) {
+                    pan.x += step;
                     clock_dx.restart();
-                } else if Key::is_pressed(Key::A) || Key::is_pressed(Key::LEFT) {
-                    dx -= (F / scale).max(1.0) as i32;
+                } else if pressed(Action::PanLeft) {
+                    pan.x -= step;
                     clock_dx.restart();
                 }
             }
+            if smooth_camera {
+                // dx, dy were moved by something else, e.g. a jump
+                if (camera.x.floor() as i32, camera.y.floor() as i32) != (dx, dy) {
+                    camera = Vector2f::new(dx as f32, dy as f32);
+                }
+                let mut direction = Vector2f::new(0., 0.);
+                if pressed(Action::PanDown) {
+                    direction.y += 1.;
+                } else if pressed(Action::PanUp) {
+                    direction.y -= 1.;
+                }
+                if pressed(Action::PanRight) {
+                    direction.x += 1.;
+                } else if pressed(Action::PanLeft) {
+                    direction.x -= 1.;
+                }
+                let dt = frame_time as f32 / 1000.0;
+                let acceleration = rotate_quarter(direction, view_rotation) * FLY_ACCELERATION;
+                camera_velocity =
+                    (camera_velocity + acceleration * (dt / scale)) * FLY_DAMPING.powf(dt);
+                camera += camera_velocity * dt;
+                dx = camera.x.floor() as i32;
+                dy = camera.y.floor() as i32;
+            } else {
+                let pan = rotate_quarter(pan, view_rotation);
+                dx += pan.x;
+                dy += pan.y;
+            }
+            // the camera may have moved since the start of the frame
+            let map_shift = if smooth_camera {
+                camera_shift(camera, view_rotation, scale)
+            } else {
+                Vector2f::new(0., 0.)
+            };
+            let map_mouse_pos = win_to_grid(vi2f(window.mouse_position()) + map_shift, scale);
 
-            if Button::LEFT.is_pressed() {
+            if Button::LEFT.is_pressed()
+                && matches!(label_input, LabelInput::Off)
+                && matches!(region_input, RegionInput::Off)
+                && goto_input.is_none()
+                && !flood_fill_pending
+                && minimap.world_pos(vi2f(window.mouse_position())).is_none()
+            {
                 // pick image_id from matrix
                 // if mouse_pos.x < IMAGES_X as i32
                 if mouse_pos.x < IMAGES_USED_X as i32
                     && mouse_pos.y >= matrix_offset_y
                     && mouse_pos.y < IMAGES_USED_Y as i32 + matrix_offset_y
                 {
-                    let image_id: ImageId =
-                        (mouse_pos.y - matrix_offset_y) as u16 * IMAGES_X + mouse_pos.x as u16;
-                    mode = if image_id == eraser {
-                        Mode::Erase
-                    } else {
-                        Mode::Paint
-                    };
-                    if let Some(multi_idx) =
-                        MultiImage::multi_id_from_image_id(image_id, &multi_objects)
-                    {
-                        mouse_selection = MouseObject::MultiImage(multi_objects[multi_idx].clone());
-                    } else {
-                        mouse_selection = MouseObject::ImageId(image_id);
+                    // the filtered palette has holes, clicking them selects nothing
+                    if let Some(obj) = matrix.iter().find(|obj| obj.position == mouse_pos) {
+                        let image_id = obj.image_id;
+                        mode = match mode {
+                            _ if image_id == eraser => Mode::Erase,
+                            Mode::RectFill => Mode::RectFill,
+                            _ => Mode::Paint,
+                        };
+                        mouse_selection = MouseObject::from_image_id(image_id, &multi_objects);
                     }
                 } else {
                     // place image_id on map or pick from map
-                    let pos_x = mouse_pos.x + dx;
-                    let pos_y = mouse_pos.y + dy;
+                    let view = ViewGrid::new(
+                        dx,
+                        dy,
+                        win_to_grid(vu2f(window.size()), scale),
+                        view_rotation,
+                    );
+                    let Vector2i { x: pos_x, y: pos_y } = view.to_world(map_mouse_pos);
                     let pos_z = dz;
 
                     if Key::is_pressed(Key::LALT) || Key::is_pressed(Key::RALT) {
@@ -312,73 +1988,260 @@ fn main() {
                         for dz in 0..10 {
                             let dz = -dz;
                             let tile = map.get(pos_x, pos_y, pos_z + dz);
-                            let old_image_id = if tile.fg.is_some() { tile.fg } else { tile.bg };
+                            let old_image_id = tile.fg2.or(tile.fg).or(tile.bg);
                             if let Some(old_image_id) = old_image_id {
-                                let old_image = if let Some(multi_idx) =
-                                    MultiImage::multi_id_from_image_id(old_image_id, &multi_objects)
-                                {
-                                    MouseObject::MultiImage(multi_objects[multi_idx].clone())
-                                } else {
-                                    MouseObject::ImageId(old_image_id)
-                                };
-                                mouse_selection = old_image;
+                                mouse_selection =
+                                    MouseObject::from_image_id(old_image_id, &multi_objects);
+                                last_pick = Some((pos_x, pos_y, Clock::start()));
                                 break;
                             }
                         }
-                        mode = Mode::Paint;
-                    } else {
-                        // place image or multi-image on map
-                        match mode {
-                            Mode::Paint => {
-                                // place image_id on map
-                                match mouse_selection.clone() {
-                                    MouseObject::ImageId(image_id) => {
-                                        let plus_half = cursor_size / 2;
-                                        let minus_half = cursor_size - plus_half - 1;
-                                        for y in -minus_half..=plus_half {
-                                            for x in -minus_half..=plus_half {
-                                                let is_bg = IS_BACKGROUND[image_id as usize];
-                                                map.set(
-                                                    pos_x + x,
-                                                    pos_y + y,
+                        if !matches!(mode, Mode::RectFill) {
+                            mode = Mode::Paint;
+                        }
+                    } else if !matches!(mode, Mode::RectFill) {
+                        // place image or multi-image on map, along the path since the last
+                        // frame, so neither fast mouse moves nor panning leave gaps
+                        let pos = Vector2i::new(pos_x, pos_y);
+                        let points = match paint_last {
+                            Some(last) if last != pos => line_points(last, pos),
+                            _ => vec![pos],
+                        };
+                        paint_last = Some(pos);
+                        for Vector2i { x: pos_x, y: pos_y } in points {
+                            match mode {
+                                Mode::Paint => {
+                                    let pos_z = if slope != 0 {
+                                        let pos = Vector2i { x: pos_x, y: pos_y };
+                                        let (last_pos, distance) =
+                                            slope_stroke.get_or_insert((pos, 0));
+                                        *distance += (pos.x - last_pos.x)
+                                            .abs()
+                                            .max((pos.y - last_pos.y).abs());
+                                        *last_pos = pos;
+                                        pos_z + slope * (*distance / slope_ratio)
+                                    } else {
+                                        pos_z
+                                    };
+                                    // place image_id on map
+                                    match mouse_selection.clone() {
+                                        MouseObject::ImageId(image_id) => {
+                                            let plus_half = cursor_size / 2;
+                                            let minus_half = cursor_size - plus_half - 1;
+                                            // ctrl puts objects on the second foreground layer
+                                            let to_fg2 = Key::is_pressed(Key::LCONTROL)
+                                                || Key::is_pressed(Key::RCONTROL);
+                                            let mut tiles = vec![];
+                                            for y in -minus_half..=plus_half {
+                                                for x in -minus_half..=plus_half {
+                                                    let is_bg = atlas.is_background(image_id);
+                                                    // the same cells and rotations for every
+                                                    // stroke, so repainting changes nothing
+                                                    let hash = tile_hash(
+                                                        pos_x + x,
+                                                        pos_y + y,
+                                                        SCATTER_SEED,
+                                                    );
+                                                    if scatter_rotate
+                                                        && !is_bg
+                                                        && !hash.is_multiple_of(SCATTER_DENSITY)
+                                                    {
+                                                        continue;
+                                                    }
+                                                    let Some(pos_z) = paint_z(
+                                                        &mut map,
+                                                        surface_paint
+                                                            .then_some(&mut surface_columns),
+                                                        pos_x + x,
+                                                        pos_y + y,
+                                                        pos_z,
+                                                        render_depth,
+                                                    ) else {
+                                                        continue;
+                                                    };
+                                                    if raise_fill {
+                                                        // solid material from the terrain up
+                                                        // to the painted tile
+                                                        let floor =
+                                                            map.surface_height(
+                                                                pos_x + x,
+                                                                pos_y + y,
+                                                                pos_z - 1,
+                                                                render_depth,
+                                                            )
+                                                            .map_or(pos_z - render_depth + 1, |z| {
+                                                                z + 1
+                                                            });
+                                                        let bg =
+                                                            if is_bg { image_id } else { DIRT };
+                                                        for fill_z in floor..pos_z {
+                                                            tiles.push((
+                                                                pos_x + x,
+                                                                pos_y + y,
+                                                                fill_z,
+                                                                Tile {
+                                                                    bg: Some(bg),
+                                                                    fg: None,
+                                                                    fg_rotation: 0,
+                                                                    fg2: None,
+                                                                },
+                                                            ));
+                                                        }
+                                                    }
+                                                    let tile = if to_fg2 && !is_bg {
+                                                        // above the foreground which is there
+                                                        let old =
+                                                            map.get(pos_x + x, pos_y + y, pos_z);
+                                                        Tile {
+                                                            bg: old.bg.or(Some(GRASS)),
+                                                            fg2: Some(image_id),
+                                                            ..old
+                                                        }
+                                                    } else {
+                                                        Tile {
+                                                            bg: if is_bg {
+                                                                Some(image_id)
+                                                            } else {
+                                                                Some(GRASS)
+                                                            },
+                                                            fg: if is_bg {
+                                                                None
+                                                            } else {
+                                                                Some(image_id)
+                                                            },
+                                                            fg_rotation: if is_bg {
+                                                                0
+                                                            } else if scatter_rotate {
+                                                                ((hash >> 8) as u8)
+                                                                    .wrapping_add(paint_rotation)
+                                                                    % 4
+                                                            } else {
+                                                                paint_rotation
+                                                            },
+                                                            fg2: None,
+                                                        }
+                                                    };
+                                                    tiles.push((pos_x + x, pos_y + y, pos_z, tile));
+                                                }
+                                            }
+                                            // the tiles are set again every frame while the
+                                            // button is held, only changes count
+                                            stats.tiles_painted += map.set_range(&tiles) as i64;
+                                        }
+                                        MouseObject::MultiImage(multi_image) => {
+                                            if let Some(pos_z) = paint_z(
+                                                &mut map,
+                                                surface_paint.then_some(&mut surface_columns),
+                                                pos_x,
+                                                pos_y,
+                                                pos_z,
+                                                render_depth,
+                                            ) {
+                                                if protect_fg
+                                                    && map.multi_fg_blocked(
+                                                        pos_x,
+                                                        pos_y,
+                                                        pos_z,
+                                                        &multi_image,
+                                                    )
+                                                {
+                                                    command_message = format!(
+                                                        "blocked: object at {},{},{} (shift+M: overwrite)",
+                                                        pos_x, pos_y, pos_z
+                                                    );
+                                                    continue;
+                                                }
+                                                let changed = map.set_multi_fg(
+                                                    pos_x,
+                                                    pos_y,
                                                     pos_z,
-                                                    Tile {
-                                                        bg: if is_bg {
-                                                            Some(image_id)
-                                                        } else {
-                                                            Some(GRASS)
-                                                        },
-                                                        fg: if is_bg {
-                                                            None
-                                                        } else {
-                                                            Some(image_id)
-                                                        },
-                                                    },
+                                                    multi_image,
                                                 );
+                                                stats.tiles_painted += changed as i64;
                                             }
                                         }
                                     }
-                                    MouseObject::MultiImage(multi_image) => {
-                                        map.set_multi_fg(pos_x, pos_y, pos_z, multi_image);
-                                    }
                                 }
-                            }
-                            Mode::Erase => {
-                                // erase image_id from map
-                                let plus_half = cursor_size / 2;
-                                let minus_half = cursor_size - plus_half - 1;
-                                for y in -minus_half..=plus_half {
-                                    for x in -minus_half..=plus_half {
-                                        map.set(
-                                            pos_x + x,
-                                            pos_y + y,
-                                            pos_z,
-                                            Tile { bg: None, fg: None },
+                                Mode::Erase => {
+                                    if erase_confirm_size > 0
+                                        && cursor_size > erase_confirm_size
+                                        && !Key::is_pressed(Key::LCONTROL)
+                                        && !Key::is_pressed(Key::RCONTROL)
+                                    {
+                                        command_message = format!(
+                                            "hold ctrl to erase with brushes above {}",
+                                            erase_confirm_size
                                         );
+                                        continue;
                                     }
+                                    // erase image_id from map
+                                    let plus_half = cursor_size / 2;
+                                    let minus_half = cursor_size - plus_half - 1;
+                                    let mut tiles = vec![];
+                                    let mut erased_objects = HashSet::new();
+                                    for y in -minus_half..=plus_half {
+                                        for x in -minus_half..=plus_half {
+                                            let Some(pos_z) = paint_z(
+                                                &mut map,
+                                                surface_paint.then_some(&mut surface_columns),
+                                                pos_x + x,
+                                                pos_y + y,
+                                                pos_z,
+                                                render_depth,
+                                            ) else {
+                                                continue;
+                                            };
+                                            if erase_objects {
+                                                let (x, y) = (pos_x + x, pos_y + y);
+                                                let mut cells = map.multi_fg_footprint(
+                                                    x,
+                                                    y,
+                                                    pos_z,
+                                                    &multi_ids,
+                                                    &multi_objects,
+                                                );
+                                                if cells.is_empty()
+                                                    && map.get(x, y, pos_z).fg.is_some()
+                                                {
+                                                    cells.push((x, y, pos_z));
+                                                }
+                                                for (x, y, z) in cells {
+                                                    if erased_objects.insert((x, y, z)) {
+                                                        let tile = map.get(x, y, z);
+                                                        tiles.push((
+                                                            x,
+                                                            y,
+                                                            z,
+                                                            Tile {
+                                                                fg: None,
+                                                                fg_rotation: 0,
+                                                                fg2: None,
+                                                                ..tile
+                                                            },
+                                                        ));
+                                                    }
+                                                }
+                                                continue;
+                                            }
+                                            tiles.push((
+                                                pos_x + x,
+                                                pos_y + y,
+                                                pos_z,
+                                                Tile {
+                                                    bg: None,
+                                                    fg: None,
+                                                    fg_rotation: 0,
+                                                    fg2: None,
+                                                },
+                                            ));
+                                        }
+                                    }
+                                    stats.tiles_erased += map.set_range(&tiles) as i64;
                                 }
+                                Mode::RectFill => {} // filled on release, see rect_corner
                             }
                         }
+                        stats.edit_time_ms += frame_time as i64;
                         save_clock.restart();
                         map_modified = true;
                     }
@@ -393,12 +2256,11 @@ fn main() {
                     // mouse moves to 300,300
                     // dx,dy = 3,3+(300-200,300-200)/tilesize =
                     let mouse_pos_window = window.mouse_position();
-                    let window_dx = mouse_pos_window - start_window_xy;
+                    let window_dx =
+                        rotate_quarter(vi2f(mouse_pos_window - start_window_xy), view_rotation);
                     let device_pixels_per_tile = TILESIZE as f32 * (scale + 0.001);
-                    dx = (start_grid_xy.x as f32 - window_dx.x as f32 / device_pixels_per_tile)
-                        as i32;
-                    dy = (start_grid_xy.y as f32 - window_dx.y as f32 / device_pixels_per_tile)
-                        as i32;
+                    dx = (start_grid_xy.x as f32 - window_dx.x / device_pixels_per_tile) as i32;
+                    dy = (start_grid_xy.y as f32 - window_dx.y / device_pixels_per_tile) as i32;
                 }
             }
         }
@@ -412,22 +2274,81 @@ fn main() {
             y: window_size.y as f32,
         };
         let grid_size = win_to_grid(window_vec, scale);
-        let tile_min_pos = Vector2i { x: dx, y: dy };
+        let mouse_over_palette = mouse_pos.x < IMAGES_USED_X as i32
+            && mouse_pos.y >= matrix_offset_y
+            && mouse_pos.y < IMAGES_USED_Y as i32 + matrix_offset_y;
+        if dim_map && matches!(mode, Mode::Paint) && !mouse_over_palette {
+            // the overlay is drawn between the map and the brush preview
+            let color = Color::rgba(0, 0, 0, 100);
+            let (width, height) = (window_vec.x, window_vec.y);
+            for (x, y) in [(0., 0.), (width, 0.), (width, height), (0., height)] {
+                overlay_buf.push(Vertex::with_pos_color(Vector2f::new(x, y), color));
+            }
+        }
+        minimap.update(
+            &mut map,
+            (dx + grid_size.x / 2, dy + grid_size.y / 2, dz),
+            render_depth,
+            &image_colors,
+            window_vec,
+        );
+        let view = ViewGrid::new(dx, dy, grid_size, view_rotation);
+        let map_shift = if smooth_camera {
+            camera_shift(camera, view_rotation, scale)
+        } else {
+            Vector2f::new(0., 0.)
+        };
+        let map_mouse_pos = win_to_grid(vi2f(window.mouse_position()) + map_shift, scale);
+        let fg_hidden = hide_fg_zoomed_out && scale < fg_min_scale;
+        // world area on the screen, turned by the rotation
+        let (corner_a, corner_b) = (view.to_world(Vector2i::new(0, 0)), view.to_world(grid_size));
+        let tile_min_pos = Vector2i {
+            x: corner_a.x.min(corner_b.x),
+            y: corner_a.y.min(corner_b.y),
+        };
         let tile_max_pos = Vector2i {
-            x: dx + grid_size.x,
-            y: dy + grid_size.y,
+            x: corner_a.x.max(corner_b.x),
+            y: corner_a.y.max(corner_b.y),
         };
 
+        if adaptive_depth {
+            frame_time_avg = frame_time_avg * 0.9 + frame_time as f32 * 0.1;
+            if adaptive_clock.elapsed_time().as_milliseconds() > ADAPTIVE_INTERVAL_MS {
+                // grow only with clear headroom, so the depth does not swing around the target
+                if frame_time_avg > adaptive_target_ms {
+                    draw_depth -= 1;
+                } else if frame_time_avg < adaptive_target_ms * 0.7 {
+                    draw_depth += 1;
+                }
+                adaptive_clock.restart();
+            }
+            draw_depth = draw_depth.clamp(ADAPTIVE_MIN_DEPTH.min(render_depth), render_depth);
+        } else {
+            draw_depth = render_depth;
+        }
+
         // calculate object positions and texture coordinates
         let mut images_used = vec![];
-        for pos_y in tile_min_pos.y..=tile_max_pos.y {
-            for pos_x in tile_min_pos.x..=tile_max_pos.x {
+        let mut vertex_cap_reached = false;
+        'render: for view_y in 0..=grid_size.y {
+            for view_x in 0..=grid_size.x {
+                let Vector2i { x: pos_x, y: pos_y } = view.to_world(Vector2i::new(view_x, view_y));
+                // up to two quads per tile, stop with a partial view instead of ballooning
+                if buf.len() + 8 > max_vertices {
+                    vertex_cap_reached = true;
+                    break 'render;
+                }
                 let mut visible = true;
                 if fog {
                     visible = false;
                     for iz in -0..=1 {
                         for iy in -1..=1 {
                             for ix in -1..=1 {
+                                if fog_cardinal
+                                    && [ix, iy, iz].iter().filter(|&&i| i != 0).count() > 1
+                                {
+                                    continue;
+                                }
                                 let image_id = map.get(pos_x + ix, pos_y + iy, dz + iz).bg;
                                 if image_id.is_none() || image_id == Some(WATER) {
                                     visible = true;
@@ -441,7 +2362,8 @@ fn main() {
                     let mut alpha = 1.0;
                     let mut image_id_bg = None;
                     let mut old_image_id_bg;
-                    for pos_z_pos in 0..20 {
+                    let mut found = false;
+                    for pos_z_pos in 0..draw_depth {
                         let pos_z_neg = -pos_z_pos;
                         old_image_id_bg = image_id_bg;
                         image_id_bg = map.get(pos_x, pos_y, pos_z_neg + dz).bg;
@@ -457,23 +2379,76 @@ fn main() {
                             } else {
                                 image_id_bg.unwrap()
                             };
-                            let color = Color::rgba(255, 255, 255, (alpha * 255.0) as u8);
+                            let mut color = Color::rgba(255, 255, 255, (alpha * 255.0) as u8);
+                            if map.is_scratch(pos_x, pos_y, pos_z_neg + dz) {
+                                color.r = 160;
+                            }
+                            if show_edit_age {
+                                // recent edits are red, fading to neutral within EDIT_AGE_MAX
+                                let pos_z = pos_z_neg + dz;
+                                if let Some(age) = map.edit_age(pos_x, pos_y, pos_z) {
+                                    let fade =
+                                        (age.as_secs_f32() / EDIT_AGE_MAX.as_secs_f32()).min(1.0);
+                                    color.g = (80.0 + 175.0 * fade) as u8;
+                                    color.b = color.g;
+                                }
+                            }
+                            let image_id_drawn = if show_variants {
+                                pick_variant(&variants, image_id_bg, pos_x, pos_y)
+                            } else {
+                                image_id_bg
+                            };
                             push_texture_coordinates(
-                                image_id_bg,
-                                pos_x - dx,
-                                pos_y - dy,
+                                image_id_drawn,
+                                view_x,
+                                view_y,
                                 scale,
                                 color,
                                 &mut buf,
                             );
-                            if let Some(image_id_fg) = map.get(pos_x, pos_y, pos_z_neg + dz).fg {
+                            let tile = map.get(pos_x, pos_y, pos_z_neg + dz);
+                            if let Some(image_id_fg) = tile.fg.filter(|_| !fg_hidden) {
+                                let batch = if glow && is_glowing(image_id_fg) {
+                                    &mut glow_buf
+                                } else {
+                                    &mut buf
+                                };
+                                if jitter_fg {
+                                    push_jittered_texture_coordinates(
+                                        image_id_fg,
+                                        view_x,
+                                        view_y,
+                                        tile_hash(pos_x, pos_y, 1),
+                                        scale,
+                                        color,
+                                        batch,
+                                    );
+                                } else {
+                                    push_texture_coordinates(
+                                        image_id_fg,
+                                        view_x,
+                                        view_y,
+                                        scale,
+                                        color,
+                                        batch,
+                                    );
+                                }
+                                let len = batch.len();
+                                rotate_texture_coordinates(&mut batch[len - 4..], tile.fg_rotation);
+                            }
+                            if let Some(image_id_fg2) = tile.fg2.filter(|_| !fg_hidden) {
+                                let batch = if glow && is_glowing(image_id_fg2) {
+                                    &mut glow_buf
+                                } else {
+                                    &mut buf
+                                };
                                 push_texture_coordinates(
-                                    image_id_fg,
-                                    pos_x - dx,
-                                    pos_y - dy,
+                                    image_id_fg2,
+                                    view_x,
+                                    view_y,
                                     scale,
                                     color,
-                                    &mut buf,
+                                    batch,
                                 );
                             }
                             num_sprites += 1;
@@ -481,9 +2456,122 @@ fn main() {
                                 images_used.push(0);
                             }
                             images_used[image_id_bg as usize] += 1;
+                            found = true;
                             break;
                         }
                     }
+                    if !found {
+                        push_color_coordinates(
+                            view_x,
+                            view_y,
+                            1,
+                            scale,
+                            SKY_COLORS[sky_color_idx],
+                            &mut sky_buf,
+                        );
+                    }
+                }
+            }
+        }
+
+        // overlays are drawn between the map and the palette
+        let map_vertex_count = buf.len();
+
+        // chunks generated in the last second: green if prefetched in time, red if the frame
+        // had to wait for them
+        let cs = Chunk::chunksize() as i32;
+        for generated in map.recently_generated(Duration::from_millis(GENERATION_HIGHLIGHT_MS)) {
+            let (chunk_x, chunk_y, chunk_z) = generated.chunk;
+            if !show_generation || chunk_z * cs > dz || (chunk_z + 1) * cs <= dz - render_depth {
+                continue;
+            }
+            let fade =
+                1.0 - generated.time.elapsed().as_millis() as f32 / GENERATION_HIGHLIGHT_MS as f32;
+            let alpha = (fade.max(0.0) * 60.0) as u8;
+            let color = if generated.prefetched {
+                Color::rgba(0, 255, 0, alpha)
+            } else {
+                Color::rgba(255, 0, 0, alpha)
+            };
+            let (corner_a, corner_b) = (
+                view.to_view(Vector2i::new(chunk_x * cs, chunk_y * cs)),
+                view.to_view(Vector2i::new(chunk_x * cs + cs - 1, chunk_y * cs + cs - 1)),
+            );
+            push_color_coordinates(
+                corner_a.x.min(corner_b.x),
+                corner_a.y.min(corner_b.y),
+                cs,
+                scale,
+                color,
+                &mut overlay_buf,
+            );
+        }
+
+        // chunks with edits vs. purely generated chunks
+        if show_provenance {
+            let (min_x, min_y, min_z) =
+                Map::chunk_coords(tile_min_pos.x, tile_min_pos.y, dz - render_depth + 1);
+            let (max_x, max_y, max_z) = Map::chunk_coords(tile_max_pos.x, tile_max_pos.y, dz + 1);
+            for chunk_y in min_y..=max_y {
+                for chunk_x in min_x..=max_x {
+                    let modified = (min_z..=max_z)
+                        .any(|chunk_z| map.is_chunk_modified(chunk_x, chunk_y, chunk_z));
+                    let color = if modified {
+                        Color::rgba(255, 128, 0, 80)
+                    } else {
+                        Color::rgba(0, 128, 255, 40)
+                    };
+                    // upper left corner on the screen, depending on the rotation
+                    let (corner_a, corner_b) = (
+                        view.to_view(Vector2i::new(chunk_x * cs, chunk_y * cs)),
+                        view.to_view(Vector2i::new(chunk_x * cs + cs - 1, chunk_y * cs + cs - 1)),
+                    );
+                    push_color_coordinates(
+                        corner_a.x.min(corner_b.x),
+                        corner_a.y.min(corner_b.y),
+                        cs,
+                        scale,
+                        color,
+                        &mut overlay_buf,
+                    );
+                }
+            }
+        }
+
+        // the region selected so far, from the first corner to the mouse
+        if let RegionInput::Second(_, first_x, first_y) = region_input {
+            let (min, max) = region_corners(
+                view.to_view(Vector2i::new(first_x, first_y)),
+                map_mouse_pos,
+                scale,
+            );
+            let (min, max) = (min - map_shift, max - map_shift);
+            for (x, y) in [
+                (min.x, min.y),
+                (min.x, max.y),
+                (max.x, max.y),
+                (max.x, min.y),
+            ] {
+                overlay_buf.push(Vertex::with_pos_color(
+                    Vector2f::new(x, y),
+                    Color::rgba(255, 255, 0, 60),
+                ));
+            }
+        }
+        // the outline of the rectangle to fill, until the button is released
+        if let Some(first) = rect_corner {
+            let (min, max) = region_corners(view.to_view(first), map_mouse_pos, scale);
+            let (min, max) = (min - map_shift, max - map_shift);
+            let width = scale;
+            let color = Color::rgba(255, 255, 0, 200);
+            for (a, b) in [
+                (min, Vector2f::new(max.x, min.y + width)),
+                (Vector2f::new(min.x, max.y - width), max),
+                (min, Vector2f::new(min.x + width, max.y)),
+                (Vector2f::new(max.x - width, min.y), max),
+            ] {
+                for (x, y) in [(a.x, a.y), (a.x, b.y), (b.x, b.y), (b.x, a.y)] {
+                    overlay_buf.push(Vertex::with_pos_color(Vector2f::new(x, y), color));
                 }
             }
         }
@@ -496,17 +2584,74 @@ fn main() {
             push_texture_coordinates(image_id, pos_x, pos_y, scale, Color::WHITE, &mut buf);
         }
 
-        // mouse
-        match mouse_selection.clone() {
-            MouseObject::ImageId(image_id) => {
-                let plus_half = cursor_size / 2;
-                let minus_half = cursor_size - plus_half - 1;
-                for y in -minus_half..=plus_half {
-                    for x in -minus_half..=plus_half {
+        // side view while shift is held: x around the cursor (horizontal) and z (vertical)
+        if window.has_focus() && (Key::is_pressed(Key::LSHIFT) || Key::is_pressed(Key::RSHIFT)) {
+            const HALF_WIDTH: i32 = 4;
+            const Z_ABOVE: i32 = 8;
+            const Z_BELOW: i32 = 12;
+            let strip_x = grid_size.x - 2 * HALF_WIDTH - 2;
+            let Vector2i {
+                x: cursor_x,
+                y: cursor_y,
+            } = view.to_world(map_mouse_pos);
+            for iz in -Z_BELOW..=Z_ABOVE {
+                // the current z level is highlighted
+                let color = if iz == 0 {
+                    Color::WHITE
+                } else {
+                    Color::rgb(130, 130, 130)
+                };
+                for ix in -HALF_WIDTH..=HALF_WIDTH {
+                    let tile = map.get(cursor_x + ix, cursor_y, dz + iz);
+                    let (x, y) = (strip_x + HALF_WIDTH + ix, 1 + Z_ABOVE - iz);
+                    for image_id in [tile.bg, tile.fg, tile.fg2].into_iter().flatten() {
+                        push_texture_coordinates(image_id, x, y, scale, color, &mut buf);
+                        num_sprites += 1;
+                    }
+                }
+            }
+        }
+
+        // mouse, the selection is kept while erasing
+        let preview_start = buf.len();
+        let mouse_preview = match mode {
+            Mode::Paint | Mode::RectFill => mouse_selection.clone(),
+            Mode::Erase => MouseObject::ImageId(eraser),
+        };
+        if show_preview {
+            match mouse_preview {
+                MouseObject::ImageId(image_id) => {
+                    let plus_half = cursor_size / 2;
+                    let minus_half = cursor_size - plus_half - 1;
+                    for y in -minus_half..=plus_half {
+                        for x in -minus_half..=plus_half {
+                            push_texture_coordinates(
+                                image_id,
+                                map_mouse_pos.x + x,
+                                map_mouse_pos.y + y,
+                                scale,
+                                Color::WHITE,
+                                &mut buf,
+                            );
+                            num_sprites += 1;
+                        }
+                    }
+                }
+                MouseObject::MultiImage(multi_image) => {
+                    // the parts are placed in the world, so they follow the rotation
+                    let mouse_world = view.to_world(map_mouse_pos);
+                    let (dx, dy) = (multi_image.size_x as i32 / 2, multi_image.size_y as i32 / 2);
+                    for image_id in multi_image.image_ids {
+                        let (image_x, image_y) = (image_id % IMAGES_X, image_id / IMAGES_X);
+                        let Vector2i { x, y } = view.to_view(Vector2i::new(
+                            mouse_world.x - dx + image_x as i32 - multi_image.min_x as i32,
+                            mouse_world.y - dy + image_y as i32 - multi_image.min_y as i32,
+                        ));
+
                         push_texture_coordinates(
                             image_id,
-                            mouse_pos.x + x,
-                            mouse_pos.y + y,
+                            x as i32,
+                            y as i32,
                             scale,
                             Color::WHITE,
                             &mut buf,
@@ -515,32 +2660,63 @@ fn main() {
                     }
                 }
             }
-            MouseObject::MultiImage(multi_image) => {
-                let (dx, dy) = (multi_image.size_x as i32 / 2, multi_image.size_y as i32 / 2);
-                for image_id in multi_image.image_ids {
-                    let (image_x, image_y) = (image_id % IMAGES_X, image_id / IMAGES_X);
-                    let (x, y) = (
-                        mouse_pos.x - dx + image_x as i32 - multi_image.min_x as i32,
-                        mouse_pos.y - dy + image_y as i32 - multi_image.min_y as i32,
-                    );
+        }
+
+        if smooth_camera {
+            // the sub-tile part of the camera moves everything placed in the world
+            let (before_preview, preview) = buf.split_at_mut(preview_start);
+            for vertex in sky_buf
+                .iter_mut()
+                .chain(&mut before_preview[..map_vertex_count])
+                .chain(&mut glow_buf)
+                .chain(preview)
+            {
+                vertex.position -= map_shift;
+            }
+        }
+
+        if tex_inset {
+            inset_texture_coordinates(&mut buf, TEX_INSET);
+            inset_texture_coordinates(&mut glow_buf, TEX_INSET);
+        }
 
-                    push_texture_coordinates(
-                        image_id,
-                        x as i32,
-                        y as i32,
-                        scale,
-                        Color::WHITE,
-                        &mut buf,
-                    );
-                    num_sprites += 1;
-                }
+        let mut clear_color = Color::BLACK;
+        if day_night {
+            if !day_night_paused {
+                time_of_day = (time_of_day + frame_time as f32 / 1000.0 / day_length).fract();
+            }
+            let tint = day_tint(time_of_day);
+            clear_color = clear_color * tint;
+            for vertex in sky_buf.iter_mut().chain(&mut buf[..map_vertex_count]) {
+                vertex.color = vertex.color * tint;
             }
         }
 
         // draw objects
-        window.clear(Color::BLACK);
+        window.clear(clear_color);
+        window.draw_primitives(&sky_buf, PrimitiveType::QUADS, &rs);
+        rs.set_texture(Some(&texture));
+        window.draw_primitives(&buf[..map_vertex_count], PrimitiveType::QUADS, &rs);
+        // glowing images are not tinted by the day/night cycle
+        rs.blend_mode = BlendMode::ADD;
+        window.draw_primitives(&glow_buf, PrimitiveType::QUADS, &rs);
+        rs.blend_mode = BlendMode::ALPHA;
+        rs.set_texture(None);
+        window.draw_primitives(&overlay_buf, PrimitiveType::QUADS, &rs);
+        if show_contours {
+            let lines = contour_lines(
+                &mut map,
+                view,
+                grid_size,
+                (dz, draw_depth),
+                contour_interval,
+                scale,
+                map_shift,
+            );
+            window.draw_primitives(&lines, PrimitiveType::LINES, &rs);
+        }
         rs.set_texture(Some(&texture));
-        window.draw_primitives(&buf, PrimitiveType::QUADS, &rs);
+        window.draw_primitives(&buf[map_vertex_count..], PrimitiveType::QUADS, &rs);
         rs.set_texture(None);
 
         let selection_message = match mouse_selection.clone() {
@@ -548,7 +2724,7 @@ fn main() {
                 format!("img:{} ", image_id)
             }
             MouseObject::MultiImage(multi_image) => {
-                let mut message = "multi:".to_string();
+                let mut message = format!("multi:{} ", multi_image.name);
                 for image_id in multi_image.image_ids.iter() {
                     _ = write!(message, "{},", image_id);
                 }
@@ -562,16 +2738,61 @@ fn main() {
             }
         }
         let ore_message = format!(
-            "iron ore: {}, copper ore: {}, gold ore: {}",
-            map.iron_ore_count, map.copper_ore_count, map.gold_ore_count
+            "iron ore: {}, copper ore: {}, gold ore: {}, coal: {}{}",
+            map.iron_ore_count,
+            map.copper_ore_count,
+            map.gold_ore_count,
+            map.coal_ore_count,
+            if accumulate_ores {
+                " (accumulating, shift+O: reset)"
+            } else {
+                ""
+            }
         );
-        map.iron_ore_count = 0;
-        map.copper_ore_count = 0;
-        map.gold_ore_count = 0;
+        if !accumulate_ores {
+            map.iron_ore_count = 0;
+            map.copper_ore_count = 0;
+            map.gold_ore_count = 0;
+            map.coal_ore_count = 0;
+        }
 
-        let mouse_pos = win_to_grid(vi2f(window.mouse_position()), scale);
-        let mouse_message = format!("mouse:{},{}", mouse_pos.x + dx, mouse_pos.y + dy);
-        let message = format!(
+        let brush_message = match brushes.active(Brush {
+            image_id: mouse_selection.image_id(),
+            cursor_size,
+        }) {
+            Some(slot) => format!("brush: {}", slot + 1),
+            None => "brush: -".to_string(),
+        } + if show_preview {
+            ", preview: on (shift+P)"
+        } else {
+            ", preview: off (shift+P)"
+        };
+        let vertex_message = if vertex_cap_reached {
+            format!("WARNING: vertex cap {} reached, partial view", max_vertices)
+        } else {
+            format!("vertices: {}/{}", map_vertex_count, max_vertices)
+        };
+        let undo_message = format!(
+            "undo steps: {} (ctrl+Z), redo: {} (ctrl+shift+Z)",
+            map.history.len(),
+            map.history.undone_len()
+        );
+        let slope_message = match slope {
+            0 => "slope: off".to_string(),
+            1 => format!("slope: up 1 every {} tiles", slope_ratio),
+            _ => format!("slope: down 1 every {} tiles", slope_ratio),
+        };
+        let palette_message = match palette_filter {
+            Some(category) if atlas.image_ids(category).is_empty() => {
+                format!("palette: {} (empty, showing all)", category.name())
+            }
+            Some(category) => format!("palette: {}", category.name()),
+            None => "palette: all".to_string(),
+        };
+
+        let mouse_world = view.to_world(map_mouse_pos);
+        let mouse_message = format!("mouse:{},{}", mouse_world.x, mouse_world.y);
+        let mut message = format!(
             "{} sprites\n{} fps ({} ms per frame)\nscale: {}\nZ: {}\n{}\nfog: {}\n{}\n{}\n{}\ncursor size: {}",
             num_sprites,
             fps,
@@ -579,33 +2800,488 @@ fn main() {
             scale,
             dz,
             selection_message,
-            fog,
+            if fog && fog_cardinal {
+                "true (cardinal)".to_string()
+            } else {
+                fog.to_string()
+            },
             image_message,
             ore_message,
             mouse_message,
             cursor_size
         );
+        let scratch_message = match map.scratch_len() {
+            Some(len) => format!("scratch: {} tiles (enter: commit, backspace: discard)", len),
+            None => "scratch: off (F8)".to_string(),
+        };
+        let mode_message = format!(
+            "mode: {} (F: {}), {} (shift+F), raise terrain: {} (shift+R), scatter: {} (shift+J), rotation: {} degrees (comma, period), hold ctrl: second foreground{}",
+            match mode {
+                Mode::Paint => "paint",
+                Mode::Erase if erase_objects => "erase objects (shift+X)",
+                Mode::Erase => "erase",
+                Mode::RectFill => "fill rectangle (alt+R)",
+            },
+            match mode {
+                Mode::Paint => "erase",
+                Mode::Erase | Mode::RectFill => "paint",
+            },
+            if surface_paint {
+                "on the surface"
+            } else {
+                "at Z"
+            },
+            if raise_fill { "on" } else { "off" },
+            if scatter_rotate { "on" } else { "off" },
+            paint_rotation as u32 * 90,
+            if height_paint {
+                "\nmouse wheel: raises and lowers the terrain (ctrl+H)"
+            } else {
+                ""
+            }
+        );
+        let vegetation_message = format!("vegetation: {:?}", map.vegetation_mode);
+        let day_night_message = if day_night {
+            let minutes = (time_of_day * 24.0 * 60.0) as i32;
+            format!(
+                "time: {:02}:{:02}{} (U, shift+U: pause, ctrl+U: +1h)",
+                minutes / 60,
+                minutes % 60,
+                if day_night_paused { " paused" } else { "" }
+            )
+        } else {
+            "day/night: off (U)".to_string()
+        };
+        let terrace_message = match map.terrace_step {
+            0 => "terraces: off (E)".to_string(),
+            step => format!("terraces: every {} z levels (E)", step),
+        } + &format!(
+            ", sea level: {} (shift+E ctrl+E), seed: {} (Y)",
+            map.sea_level, map.seed
+        );
+        let render_depth_message = if adaptive_depth {
+            format!(
+                "render depth: {} of {} (ctrl+[ ctrl+]), adaptive for {} ms per frame (\\)",
+                draw_depth, render_depth, adaptive_target_ms
+            )
+        } else {
+            format!(
+                "render depth: {} (ctrl+[ ctrl+], \\: adaptive)",
+                render_depth
+            )
+        };
+        let fg_message = match (hide_fg_zoomed_out, fg_hidden) {
+            (false, _) => "foreground: always drawn (ctrl+J)".to_string(),
+            (true, false) => format!("foreground: hidden below scale {} (ctrl+J)", fg_min_scale),
+            (true, true) => format!(
+                "foreground: hidden, scale is below {} (ctrl+J: show)",
+                fg_min_scale
+            ),
+        };
+        for line in [
+            mode_message,
+            render_depth_message,
+            fg_message,
+            scratch_message,
+            vegetation_message,
+            terrace_message,
+            day_night_message,
+            brush_message,
+            undo_message,
+            vertex_message,
+            slope_message,
+            palette_message,
+            command_message.clone(),
+        ] {
+            _ = write!(message, "\n{}", line);
+        }
+        if let Some(strokes) = &recovery {
+            _ = write!(
+                message,
+                "\nunsaved strokes of the last session: {} (Enter: replay, Backspace: discard)",
+                strokes.len()
+            );
+        }
+        if show_contours {
+            _ = write!(
+                message,
+                "\ncontour lines: every {} z levels (ctrl+K, shift+K: interval)",
+                contour_interval
+            );
+        }
+        if show_generation {
+            _ = write!(
+                message,
+                "\ngeneration: {} chunks waiting for prefetch (ctrl+P)",
+                map.prefetch_pending
+            );
+        }
+        if show_stats {
+            _ = write!(message, "\n{}", stats.message());
+        }
+        if show_crosshair {
+            let grid_size = win_to_grid(vu2f(window.size()), scale);
+            _ = write!(
+                message,
+                "\ncenter:{},{}",
+                dx + grid_size.x / 2,
+                dy + grid_size.y / 2
+            );
+        }
+        if show_debug_sample {
+            let sample = map.debug_sample(mouse_world.x, mouse_world.y, dz);
+            _ = write!(
+                message,
+                "\nterrain height: {}, soil: {}, vegetation: {}, biome: {}\nore depth iron: {}, copper: {}, gold: {}, coal: {}, ore region: {}",
+                sample.terrain_height,
+                sample.soil_thickness,
+                sample.vegetation,
+                sample.biome.name(),
+                sample.iron_ore_depth,
+                sample.copper_ore_depth,
+                sample.gold_ore_depth,
+                sample.coal_ore_depth,
+                sample.ore_region
+            );
+        }
+        // the tiles under the mouse around the current z, the top first
+        if Key::is_pressed(Key::I) && (Key::is_pressed(Key::LALT) || Key::is_pressed(Key::RALT)) {
+            let id = |image_id: Option<u16>| image_id.map_or("-".to_string(), |id| id.to_string());
+            _ = write!(
+                message,
+                "\ncolumn {},{} (bg / fg):",
+                mouse_world.x, mouse_world.y
+            );
+            for z in (dz - COLUMN_TOOLTIP_RANGE..=dz + COLUMN_TOOLTIP_RANGE).rev() {
+                let tile = map.get(mouse_world.x, mouse_world.y, z);
+                _ = write!(
+                    message,
+                    "\n{} {}: {} / {}",
+                    if z == dz { ">" } else { " " },
+                    z,
+                    id(tile.bg),
+                    id(tile.fg)
+                );
+            }
+        }
+        if let Some(color) = sampled_color {
+            _ = write!(
+                message,
+                "\naverage color: #{:02x}{:02x}{:02x} ({},{},{})",
+                color.r, color.g, color.b, color.r, color.g, color.b
+            );
+        }
+        match &label_input {
+            LabelInput::Off => {
+                let names: Vec<_> = labels
+                    .labels
+                    .iter()
+                    .map(|label| label.name.as_str())
+                    .collect();
+                _ = write!(message, "\nlabels (tab): {}", names.join(", "));
+            }
+            LabelInput::Position => _ = write!(message, "\nlabel: click a position"),
+            LabelInput::Name(_, _, _, name) => _ = write!(message, "\nlabel name: {}_", name),
+        }
+        if let Some(text) = &goto_input {
+            _ = write!(
+                message,
+                "\ngo to x,y or x,y,z: {}_ (Enter: go, Escape: cancel)",
+                text
+            );
+        }
+        if let Some(selected) = noise_panel {
+            _ = write!(
+                message,
+                "\nterrain noise (up/down: select, left/right: change, shift: faster, ctrl+T: close)"
+            );
+            for (idx, param) in NoiseParam::ALL.iter().enumerate() {
+                _ = write!(
+                    message,
+                    "\n{} {}: {}",
+                    if idx == selected { ">" } else { " " },
+                    param.name(),
+                    map.noise().terrain_text(*param)
+                );
+            }
+        }
+        if let Some((seed, _)) = &seed_preview {
+            _ = write!(
+                message,
+                "\nseed preview: {} (Y: next, shift+Y: use it, ctrl+Y: cancel)",
+                seed
+            );
+        }
+        if flood_fill_pending {
+            _ = write!(message, "\nflood fill: click an area (alt+G: cancel)");
+        }
+        match region_input {
+            RegionInput::Off => {}
+            RegionInput::First(action) => {
+                _ = write!(message, "\n{}: click the first corner", action.name())
+            }
+            RegionInput::Second(action, x, y) => {
+                _ = write!(
+                    message,
+                    "\n{}: {},{} - click the second corner",
+                    action.name(),
+                    x,
+                    y
+                )
+            }
+        }
         text_object.set_string(&message);
         window.draw_text(&text_object, &rs);
+        for label in &labels.labels {
+            if label.z <= dz + 1
+                && label.z > dz - render_depth
+                && label.x >= tile_min_pos.x
+                && label.x <= tile_max_pos.x
+                && label.y >= tile_min_pos.y
+                && label.y <= tile_max_pos.y
+            {
+                label_text.set_string(&label.name);
+                label_text.set_position(
+                    grid_to_win(view.to_view(Vector2i::new(label.x, label.y)), scale) - map_shift,
+                );
+                window.draw_text(&label_text, &rs);
+            }
+        }
+        if let Some(color) = sampled_color {
+            // lower left corner, 2x2 tiles
+            let mut swatch = vec![];
+            let grid_size = win_to_grid(vu2f(window.size()), scale);
+            push_color_coordinates(1, grid_size.y - 3, 2, scale, color, &mut swatch);
+            window.draw_primitives(&swatch, PrimitiveType::QUADS, &rs);
+        }
+        if show_compass {
+            draw_compass(&mut window, &mut compass_text, &rs, scale);
+        }
+        if show_crosshair {
+            draw_crosshair(&mut window, &rs);
+        }
+        minimap.draw(&mut window);
+        if let Some((_, preview_texture)) = &seed_preview {
+            draw_seed_preview(&mut window, preview_texture);
+        }
+        if show_slots {
+            if slot_infos.is_empty() {
+                slot_infos = slots::list(saver.db(), world);
+                worlds = slots::worlds(saver.db());
+            }
+            draw_slots(
+                &mut window,
+                &mut inspector_text,
+                &slot_infos,
+                slot,
+                (world, &worlds),
+            );
+        }
+        if let Some((x, y, clock)) = &last_pick {
+            let age = clock.elapsed_time().as_milliseconds();
+            if age < PICK_HIGHLIGHT_MS {
+                let alpha = 255 - 255 * age / PICK_HIGHLIGHT_MS;
+                draw_outline(
+                    &mut window,
+                    &rs,
+                    view.to_view(Vector2i::new(*x, *y)),
+                    scale,
+                    Color::rgba(255, 255, 0, alpha as u8),
+                );
+            } else {
+                last_pick = None;
+            }
+        }
+        if let Some((x, y, selected_z)) = &mut inspector {
+            // z may have changed since the level was selected
+            *selected_z = (*selected_z).clamp(dz - render_depth + 1, dz);
+            draw_outline(
+                &mut window,
+                &rs,
+                view.to_view(Vector2i::new(*x, *y)),
+                scale,
+                Color::CYAN,
+            );
+            draw_column_inspector(
+                &mut window,
+                &mut inspector_text,
+                &rs,
+                &mut map,
+                (*x, *y, *selected_z),
+                (dz, render_depth),
+                &atlas,
+            );
+        }
         window.display();
         buf.clear();
+        sky_buf.clear();
+        overlay_buf.clear();
+        glow_buf.clear();
+
+        // the world and, rounded to chunks, the camera position in the title
+        if title_clock.elapsed_time().as_milliseconds() > TITLE_UPDATE_MS {
+            let mut new_title = format!("w8 - {} {}", db_name, table_map);
+            if title_coordinates {
+                let cs = Chunk::chunksize() as i32;
+                let center = view.to_world(grid_size / 2);
+                _ = write!(
+                    new_title,
+                    " - {},{},{}",
+                    center.x.div_euclid(cs) * cs,
+                    center.y.div_euclid(cs) * cs,
+                    dz
+                );
+            }
+            if new_title != title {
+                window.set_title(&new_title);
+                title = new_title;
+            }
+            title_clock.restart();
+        }
+
+        // append finished strokes to the journal, so they survive a crash before the next save
+        if !map.history.is_recording() {
+            if let Err(err) = map.journal.flush() {
+                println!("journal: {}", err);
+            }
+        }
+
+        if saver.finished() {
+            println!("{:.4} Done.", save_clock.elapsed_time().as_seconds());
+            // edits during the save are only in the journal until the next save
+            if !map_modified {
+                if let Err(err) = map.journal.clear() {
+                    println!("journal: {}", err);
+                }
+            }
+        }
+
+        // the settings stored in the config, a change is saved like an edit of the map
+        let settings = vec![
+            ("fog", fog as i64),
+            ("fog_cardinal", fog_cardinal as i64),
+            ("tex_inset", tex_inset as i64),
+            ("day_night", day_night as i64),
+            ("day_length_s", day_length as i64),
+            ("render_depth", render_depth as i64),
+            ("adaptive_depth", adaptive_depth as i64),
+            ("hide_fg_zoomed_out", hide_fg_zoomed_out as i64),
+            ("max_vertices", max_vertices as i64),
+            ("sky_color", sky_color_idx as i64),
+            (
+                "vegetation_mode",
+                (map.vegetation_mode == VegetationMode::Hash) as i64,
+            ),
+            ("vegetation_density", map.vegetation_density as i64),
+            ("terrace_step", map.terrace_step as i64),
+            ("sea_level", map.sea_level as i64),
+            ("world_limit", map.world_limit as i64),
+            ("seed", map.seed as i64),
+            ("resource_density", map.resource_density as i64),
+            ("river_width", map.river_width as i64),
+            ("view_rotation", view_rotation as i64),
+            ("contour_interval", contour_interval as i64),
+        ];
+        let settings_modified = *saved_settings.get_or_insert_with(|| settings.clone()) != settings;
+
+        // the window was closed this frame, the last edits are saved before leaving the loop
+        let closing = !window.is_open();
+        if closing {
+            // wait for the save in flight
+            _ = saver.db();
+        }
 
-        // save map if modified and enough time has passed
-        if map_modified && save_clock.elapsed_time().as_seconds() >= 0.5 {
+        // the thumbnail renders the view, it is written when leaving the slot and not on autosaves
+        if closing || (next_slot.is_some() && !saver.is_saving()) {
+            let center = view.to_world(grid_size / 2);
+            slots::save_thumbnail(
+                &mut map,
+                (center.x, center.y, dz),
+                render_depth,
+                &image_colors,
+                world,
+                slot,
+            );
+        }
+
+        // save map or settings if modified and enough time has passed, or before switching the
+        // slot or closing. The map rows are taken here and written by the saving thread, no new
+        // save is started while one is in flight.
+        if (((map_modified || settings_modified)
+            && (save_clock.elapsed_time().as_seconds() >= 0.5 || closing))
+            || next_slot.is_some())
+            && !saver.is_saving()
+        {
             println!(
                 "{:.4} Saving map...",
                 save_clock.elapsed_time().as_seconds()
             );
-            if let Err(err) = map.store(&mut db, table_map) {
+            let map_rows = map.take_rows(table_map);
+            if let Err(err) = map.store_noise_params(saver.db(), table_noise) {
                 panic!(" {}", err);
             }
-            if let Err(err) = db.save() {
+            if let Err(err) = stats.store(saver.db(), table_stats) {
                 panic!(" {}", err);
             }
-            println!("{:.4} Done.", save_clock.elapsed_time().as_seconds());
+            if let Err(err) = brushes.store(saver.db(), table_brushes) {
+                panic!(" {}", err);
+            }
+            if let Err(err) = bookmarks.store(saver.db(), table_bookmarks) {
+                panic!(" {}", err);
+            }
+            if let Err(err) = labels.store(saver.db(), table_labels) {
+                panic!(" {}", err);
+            }
+            for &(name, value) in settings.iter().chain(&[
+                ("saved_at", slots::now() as i64),
+                ("map_format", MAP_FORMAT),
+            ]) {
+                config.set(name, value);
+            }
+            if let Err(err) = config.store(saver.db(), table_config) {
+                panic!(" {}", err);
+            }
+            if let Err(err) = slots::store_last_slot(saver.db(), world, next_slot.unwrap_or(slot)) {
+                panic!(" {}", err);
+            }
+            saver.save(map_rows);
             save_clock.restart();
             map_modified = false;
+            saved_settings = Some(settings);
+            if next_slot.is_some() {
+                // wait for the save, the next slot loads the database again
+                _ = saver.db();
+                if let Err(err) = map.journal.clear() {
+                    println!("journal: {}", err);
+                }
+                return next_slot;
+            }
+        }
+
+        // prefetch chunks in the direction of travel
+        velocity = velocity * 0.8 + vi2f(Vector2i::new(dx, dy) - last_dxy) * 0.2;
+        last_dxy = Vector2i::new(dx, dy);
+        let sign = |v: f32| {
+            if v > 0.1 {
+                1
+            } else if v < -0.1 {
+                -1
+            } else {
+                0
+            }
+        };
+        let direction = (sign(velocity.x), sign(velocity.y));
+        if direction != (0, 0) && window.has_focus() {
+            map.prefetch(
+                (tile_min_pos.x, tile_min_pos.y, dz - render_depth + 1),
+                (tile_max_pos.x, tile_max_pos.y, dz + 1),
+                direction,
+                2,
+                Duration::from_millis(4),
+            );
         }
+        let center = view.to_world(grid_size / 2);
+        map.evict_chunks((center.x, center.y, dz));
 
         // calculate fps
         current_frames_rendered += 1;
@@ -614,9 +3290,467 @@ fn main() {
             fps_clock.restart();
             current_frames_rendered = 0;
         }
+
+        // in the background, throttle to about 5 fps but keep pumping events
+        if !window.has_focus() {
+            sleep(Time::milliseconds(200));
+        }
+    }
+    // dropping the saver finishes the final save, only then the journal is not needed anymore
+    drop(saver);
+    if !map_modified {
+        if let Err(err) = map.journal.clear() {
+            println!("journal: {}", err);
+        }
+    }
+    None
+}
+
+/// N/S/E/W of the world axes (north = -y) in the lower right corner
+fn draw_compass(window: &mut RenderWindow, text: &mut Text, rs: &RenderStates, scale: f32) {
+    let radius = TILESIZE as f32 * scale;
+    let center = vu2f(window.size()) - Vector2f::new(radius * 2.0, radius * 2.0);
+    let mut lines = vec![];
+    for (label, direction, color) in [
+        ("N", Vector2f::new(0., -1.), Color::RED),
+        ("S", Vector2f::new(0., 1.), Color::WHITE),
+        ("E", Vector2f::new(1., 0.), Color::WHITE),
+        ("W", Vector2f::new(-1., 0.), Color::WHITE),
+    ] {
+        lines.push(Vertex::with_pos_color(center, color));
+        lines.push(Vertex::with_pos_color(center + direction * radius, color));
+        text.set_string(label);
+        let bounds = text.global_bounds();
+        text.set_position(
+            center + direction * (radius * 1.4)
+                - Vector2f::new(bounds.width / 2.0, bounds.height / 2.0),
+        );
+        window.draw_text(text, rs);
+    }
+    window.draw_primitives(&lines, PrimitiveType::LINES, rs);
+}
+
+/// Color multiplied onto the map at a time of day, 0: midnight, 0.5: noon
+fn day_tint(time_of_day: f32) -> Color {
+    const KEYS: [(f32, (f32, f32, f32)); 6] = [
+        (0.0, (40., 50., 110.)),
+        (0.25, (255., 170., 120.)),
+        (0.35, (255., 255., 255.)),
+        (0.65, (255., 255., 255.)),
+        (0.75, (255., 150., 110.)),
+        (1.0, (40., 50., 110.)),
+    ];
+    let idx = KEYS
+        .iter()
+        .position(|&(time, _)| time > time_of_day)
+        .unwrap_or(KEYS.len() - 1)
+        .max(1);
+    let ((time0, color0), (time1, color1)) = (KEYS[idx - 1], KEYS[idx]);
+    let t = ((time_of_day - time0) / (time1 - time0)).clamp(0., 1.);
+    let mix = |a: f32, b: f32| (a + (b - a) * t) as u8;
+    Color::rgb(
+        mix(color0.0, color1.0),
+        mix(color0.1, color1.1),
+        mix(color0.2, color1.2),
+    )
+}
+
+/// The save slots with their thumbnails and the time of their last save in the middle of the
+/// window, the current slot highlighted. Below them the current world and the other saved worlds.
+fn draw_slots(
+    window: &mut RenderWindow,
+    text: &mut Text,
+    slot_infos: &[SlotInfo],
+    slot: u8,
+    (world, worlds): (&str, &[String]),
+) {
+    const THUMBNAIL_PIXELS: f32 = 128.0;
+    let margin = THUMBNAIL_PIXELS / 8.0;
+    let size = Vector2f::new(
+        THUMBNAIL_PIXELS * 4.0,
+        (THUMBNAIL_PIXELS + margin) * slot_infos.len() as f32 + margin * 5.0,
+    );
+    let corner = (vu2f(window.size()) - size) / 2.0;
+    let rs = RenderStates::default();
+    let background: Vec<_> = [(0., 0.), (1., 0.), (1., 1.), (0., 1.)]
+        .iter()
+        .map(|&(x, y)| {
+            Vertex::with_pos_color(
+                corner + Vector2f::new(x * size.x, y * size.y),
+                Color::rgba(0, 0, 0, 200),
+            )
+        })
+        .collect();
+    window.draw_primitives(&background, PrimitiveType::QUADS, &rs);
+    text.set_string("save slots (1-5: load, escape: close)");
+    text.set_position(corner + Vector2f::new(margin, margin / 2.0));
+    window.draw_text(text, &rs);
+    let now = slots::now();
+    for (idx, info) in slot_infos.iter().enumerate() {
+        let top_left = corner
+            + Vector2f::new(
+                margin,
+                margin * 2.5 + idx as f32 * (THUMBNAIL_PIXELS + margin),
+            );
+        if let Some(thumbnail) = &info.thumbnail {
+            let texture_size = vu2f(thumbnail.size());
+            let quad: Vec<_> = [(0., 0.), (1., 0.), (1., 1.), (0., 1.)]
+                .iter()
+                .map(|&(x, y)| Vertex {
+                    position: top_left + Vector2f::new(x, y) * THUMBNAIL_PIXELS,
+                    color: Color::WHITE,
+                    tex_coords: Vector2f::new(x * texture_size.x, y * texture_size.y),
+                })
+                .collect();
+            let mut rs = RenderStates::default();
+            rs.set_texture(Some(thumbnail));
+            window.draw_primitives(&quad, PrimitiveType::QUADS, &rs);
+        }
+        let saved = match info.saved_at {
+            None => "empty".to_string(),
+            Some(0) => "saved".to_string(),
+            Some(saved_at) => match now.saturating_sub(saved_at) {
+                seconds if seconds < 120 => "saved just now".to_string(),
+                seconds if seconds < 2 * 3600 => format!("saved {} minutes ago", seconds / 60),
+                seconds if seconds < 2 * 86400 => format!("saved {} hours ago", seconds / 3600),
+                seconds => format!("saved {} days ago", seconds / 86400),
+            },
+        };
+        text.set_string(&format!(
+            "{}slot {}: {}",
+            if info.slot == slot { "> " } else { "" },
+            info.slot,
+            saved
+        ));
+        text.set_position(top_left + Vector2f::new(THUMBNAIL_PIXELS + margin, 0.));
+        window.draw_text(text, &rs);
+    }
+    let others: Vec<_> = worlds
+        .iter()
+        .filter(|name| *name != world)
+        .cloned()
+        .collect();
+    text.set_string(&format!(
+        "world: {} (--map NAME)\nother worlds: {}",
+        world,
+        if others.is_empty() {
+            "none".to_string()
+        } else {
+            others.join(", ")
+        }
+    ));
+    text.set_position(corner + Vector2f::new(margin, size.y - margin * 2.5));
+    window.draw_text(text, &rs);
+}
+
+/// the terrain preview of a seed in the middle of the window, see Map::preview_seed()
+fn draw_seed_preview(window: &mut RenderWindow, texture: &Texture) {
+    let size = vu2f(texture.size());
+    let screen_size = size * SEED_PREVIEW_PIXELS;
+    let corner = (vu2f(window.size()) - screen_size) / 2.0;
+    let quad: Vec<_> = [(0., 0.), (1., 0.), (1., 1.), (0., 1.)]
+        .iter()
+        .map(|&(x, y)| Vertex {
+            position: corner + Vector2f::new(x * screen_size.x, y * screen_size.y),
+            color: Color::WHITE,
+            tex_coords: Vector2f::new(x * size.x, y * size.y),
+        })
+        .collect();
+    let mut rs = RenderStates::default();
+    rs.set_texture(Some(texture));
+    window.draw_primitives(&quad, PrimitiveType::QUADS, &rs);
+}
+
+/// z where a column is painted: `z` itself, or with `surface_columns` the surface between z
+/// and z-depth+1. The surface is remembered for the stroke, so holding the button does not
+/// dig down. None if the column has no surface.
+fn paint_z(
+    map: &mut Map,
+    surface_columns: Option<&mut HashMap<(i32, i32), i32>>,
+    x: i32,
+    y: i32,
+    z: i32,
+    depth: i32,
+) -> Option<i32> {
+    match surface_columns {
+        None => Some(z),
+        Some(columns) => {
+            if let Some(&surface_z) = columns.get(&(x, y)) {
+                return Some(surface_z);
+            }
+            let surface_z = map.surface_height(x, y, z, depth)?;
+            columns.insert((x, y), surface_z);
+            Some(surface_z)
+        }
+    }
+}
+
+/// the tiles on the line from `from` (excluded) to `to` (included)
+fn line_points(from: Vector2i, to: Vector2i) -> Vec<Vector2i> {
+    let delta = to - from;
+    let steps = delta.x.abs().max(delta.y.abs());
+    (1..=steps)
+        .map(|step| {
+            let t = step as f32 / steps as f32;
+            Vector2i::new(
+                from.x + (delta.x as f32 * t).round() as i32,
+                from.y + (delta.y as f32 * t).round() as i32,
+            )
+        })
+        .collect()
+}
+
+/// Lines between neighboring columns on the screen whose surface heights are in different
+/// steps of `interval`. The surface is searched between z and z-depth+1.
+fn contour_lines(
+    map: &mut Map,
+    view: ViewGrid,
+    grid_size: Vector2i,
+    (z, depth): (i32, i32),
+    interval: i32,
+    scale: f32,
+    shift: Vector2f,
+) -> Vec<Vertex> {
+    let width = (grid_size.x + 1) as usize;
+    let mut levels = vec![None; width * (grid_size.y + 1) as usize];
+    for view_y in 0..=grid_size.y {
+        for view_x in 0..=grid_size.x {
+            let pos = view.to_world(Vector2i::new(view_x, view_y));
+            levels[view_x as usize + view_y as usize * width] = map
+                .surface_height(pos.x, pos.y, z, depth)
+                .map(|height| height.div_euclid(interval));
+        }
+    }
+    let color = Color::rgba(90, 50, 20, 220);
+    let mut lines = vec![];
+    let mut segment = |from: (i32, i32), to: (i32, i32)| {
+        for (x, y) in [from, to] {
+            lines.push(Vertex::with_pos_color(
+                grid_to_win(Vector2i::new(x, y), scale) - shift,
+                color,
+            ));
+        }
+    };
+    for view_y in 0..=grid_size.y {
+        for view_x in 0..=grid_size.x {
+            let idx = view_x as usize + view_y as usize * width;
+            let Some(level) = levels[idx] else {
+                continue;
+            };
+            // the edge shared with the right and the lower neighbor
+            if view_x < grid_size.x && levels[idx + 1].is_some_and(|right| right != level) {
+                segment((view_x + 1, view_y), (view_x + 1, view_y + 1));
+            }
+            if view_y < grid_size.y && levels[idx + width].is_some_and(|below| below != level) {
+                segment((view_x, view_y + 1), (view_x + 1, view_y + 1));
+            }
+        }
+    }
+    lines
+}
+
+/// a rectangle around the tile at the grid position
+fn draw_outline(
+    window: &mut RenderWindow,
+    rs: &RenderStates,
+    grid_pos: Vector2i,
+    scale: f32,
+    color: Color,
+) {
+    let corner = grid_to_win(grid_pos, scale);
+    let size = TILESIZE as f32 * scale;
+    let corners = [
+        corner,
+        corner + Vector2f::new(size, 0.),
+        corner + Vector2f::new(size, size),
+        corner + Vector2f::new(0., size),
+    ];
+    let mut lines = vec![];
+    for i in 0..corners.len() {
+        lines.push(Vertex::with_pos_color(corners[i], color));
+        lines.push(Vertex::with_pos_color(
+            corners[(i + 1) % corners.len()],
+            color,
+        ));
+    }
+    window.draw_primitives(&lines, PrimitiveType::LINES, rs);
+}
+
+/// The levels between z and z-depth+1 of a column in the upper right corner, the selected
+/// level highlighted and its tile, source and noise values beside the list
+fn draw_column_inspector(
+    window: &mut RenderWindow,
+    text: &mut Text,
+    rs: &RenderStates,
+    map: &mut Map,
+    (x, y, selected_z): (i32, i32, i32),
+    (z, depth): (i32, i32),
+    atlas: &TileAtlas,
+) {
+    let name = |image_id: Option<ImageId>| match image_id {
+        Some(id) => atlas
+            .name(id)
+            .map_or(id.to_string(), |name| name.to_string()),
+        None => "-".to_string(),
+    };
+    let mut list = format!("column {},{} (ctrl+I: close)", x, y);
+    // first character of the selected line, for Text and as byte index into list
+    let (mut selected_char, mut selected_byte) = (0, 0);
+    for level in (z - depth + 1..=z).rev() {
+        let tile = map.get(x, y, level);
+        list += "\n";
+        if level == selected_z {
+            selected_char = list.chars().count();
+            selected_byte = list.len();
+        }
+        _ = write!(list, "{}: {} / {}", level, name(tile.bg), name(tile.fg));
+        if tile.fg2.is_some() {
+            _ = write!(list, " / {}", name(tile.fg2));
+        }
+    }
+    let tile = map.get(x, y, selected_z);
+    let sample = map.debug_sample(x, y, selected_z);
+    let detail = format!(
+        "z: {} (up/down)\nbackground: {}\nforeground: {}, rotation: {}\nsecond foreground: {}\n\
+         source: {}\n\
+         terrain height: {}, soil: {}\nvegetation: {}, biome: {}\nore depth iron: {}\n\
+         ore depth copper: {}\nore depth gold: {}\nore depth coal: {}\nore region: {}",
+        selected_z,
+        name(tile.bg),
+        name(tile.fg),
+        tile.fg_rotation * 90,
+        name(tile.fg2),
+        map.tile_source(x, y, selected_z),
+        sample.terrain_height,
+        sample.soil_thickness,
+        sample.vegetation,
+        sample.biome.name(),
+        sample.iron_ore_depth,
+        sample.copper_ore_depth,
+        sample.gold_ore_depth,
+        sample.coal_ore_depth,
+        sample.ore_region
+    );
+    let margin = 8.0;
+    text.set_string(&detail);
+    let detail_width = text.global_bounds().width;
+    text.set_string(&list);
+    let list_bounds = text.global_bounds();
+    let corner = Vector2f::new(
+        window.size().x as f32 - list_bounds.width - detail_width - 3.0 * margin,
+        margin,
+    );
+    text.set_position(corner);
+    let line_top = text.find_character_pos(selected_char);
+    let line_height = line_top.y - text.find_character_pos(0).y;
+    let line_height = line_height / (list[..selected_byte].matches('\n').count() as f32);
+    let highlight = [
+        (0., 0.),
+        (list_bounds.width, 0.),
+        (list_bounds.width, line_height),
+        (0., line_height),
+    ]
+    .map(|(dx, dy)| {
+        Vertex::with_pos_color(
+            Vector2f::new(corner.x + dx, line_top.y + dy),
+            Color::rgba(0, 160, 255, 120),
+        )
+    });
+    window.draw_primitives(&highlight, PrimitiveType::QUADS, rs);
+    window.draw_text(text, rs);
+    text.set_string(&detail);
+    text.set_position(corner + Vector2f::new(list_bounds.width + margin, line_top.y - corner.y));
+    window.draw_text(text, rs);
+}
+
+/// a horizontal and a vertical line through the center of the window
+fn draw_crosshair(window: &mut RenderWindow, rs: &RenderStates) {
+    let size = vu2f(window.size());
+    let center = size / 2.0;
+    let color = Color::rgba(255, 255, 255, 160);
+    let lines = [
+        Vertex::with_pos_color(Vector2f::new(0., center.y), color),
+        Vertex::with_pos_color(Vector2f::new(size.x, center.y), color),
+        Vertex::with_pos_color(Vector2f::new(center.x, 0.), color),
+        Vertex::with_pos_color(Vector2f::new(center.x, size.y), color),
+    ];
+    window.draw_primitives(&lines, PrimitiveType::LINES, rs);
+}
+
+/// Destructive commands have to be given twice within 3 seconds
+fn confirm(pending: &mut Option<(Key, bool, Clock)>, code: Key, shift: bool) -> bool {
+    let confirmed = pending
+        .take()
+        .is_some_and(|(pending_code, pending_shift, clock)| {
+            (pending_code, pending_shift) == (code, shift)
+                && clock.elapsed_time().as_seconds() < 3.0
+        });
+    if !confirmed {
+        *pending = Some((code, shift, Clock::start()));
+    }
+    confirmed
+}
+
+/// deterministic variant of an image for a world position
+fn pick_variant(
+    variants: &HashMap<ImageId, Vec<ImageId>>,
+    image_id: ImageId,
+    pos_x: i32,
+    pos_y: i32,
+) -> ImageId {
+    match variants.get(&image_id) {
+        Some(variants) => variants[tile_hash(pos_x, pos_y, 0) as usize % variants.len()],
+        None => image_id,
     }
 }
 
+/// "x,y" or "x,y,z" of the go-to command, spaces around the numbers are ignored
+fn parse_coordinates(text: &str) -> Option<(i32, i32, Option<i32>)> {
+    let values: Vec<i32> = text
+        .split(',')
+        .map(|value| value.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    match values[..] {
+        [x, y] => Some((x, y, None)),
+        [x, y, z] => Some((x, y, Some(z))),
+        _ => None,
+    }
+}
+
+fn numpad_key(code: Key) -> Option<usize> {
+    [
+        Key::NUMPAD0,
+        Key::NUMPAD1,
+        Key::NUMPAD2,
+        Key::NUMPAD3,
+        Key::NUMPAD4,
+        Key::NUMPAD5,
+        Key::NUMPAD6,
+        Key::NUMPAD7,
+        Key::NUMPAD8,
+        Key::NUMPAD9,
+    ]
+    .iter()
+    .position(|key| *key == code)
+}
+
+fn number_key(code: Key) -> Option<usize> {
+    [
+        Key::NUM0,
+        Key::NUM1,
+        Key::NUM2,
+        Key::NUM3,
+        Key::NUM4,
+        Key::NUM5,
+        Key::NUM6,
+        Key::NUM7,
+        Key::NUM8,
+        Key::NUM9,
+    ]
+    .iter()
+    .position(|key| *key == code)
+}
+
 fn cursor_size_decrease(cursor_size: &mut i32) {
     *cursor_size = match *cursor_size {
         1 => 1,
@@ -652,18 +3786,56 @@ fn cursor_size_increase(cursor_size: &mut i32) {
     };
 }
 
-fn make_matrix(scale: f32) -> (Vec<Object>, i32) {
+/// number of rows of the palette with the filter
+fn palette_rows(atlas: &TileAtlas, filter: Option<Category>) -> i32 {
+    let filtered = filter
+        .map(|category| atlas.image_ids(category))
+        .unwrap_or_default();
+    if filtered.is_empty() {
+        (IMAGES_CNT / IMAGES_X) as i32
+    } else {
+        (filtered.len() as i32 + IMAGES_USED_X as i32 - 1) / IMAGES_USED_X as i32
+    }
+}
+
+/// Builds the palette. With a filter, only the images of that category are shown, packed
+/// into the used palette area. Falls back to all images if the category has none.
+/// `scroll` rows at the top of the palette are hidden
+fn make_matrix(
+    scale: f32,
+    atlas: &TileAtlas,
+    filter: Option<Category>,
+    scroll: i32,
+) -> (Vec<Object>, i32) {
     // matrix of objects
     let mut matrix = Vec::new();
     let matrix_offset_y = 40 / (scale - 0.1).max(1.0) as i32;
-    for idx in 0..IMAGES_CNT {
-        let x: i32 = (idx % IMAGES_X) as i32;
-        let y: i32 = (idx / IMAGES_X) as i32 + matrix_offset_y;
-        let obj = Object {
-            position: Vector2i { x, y },
-            image_id: idx,
-        };
-        matrix.push(obj);
+    let filtered = filter
+        .map(|category| atlas.image_ids(category))
+        .unwrap_or_default();
+    if filtered.is_empty() {
+        for idx in scroll as ImageId * IMAGES_X..IMAGES_CNT {
+            let x: i32 = (idx % IMAGES_X) as i32;
+            let y: i32 = (idx / IMAGES_X) as i32 - scroll + matrix_offset_y;
+            let obj = Object {
+                position: Vector2i { x, y },
+                image_id: idx,
+            };
+            matrix.push(obj);
+        }
+    } else {
+        for (idx, image_id) in filtered.into_iter().enumerate() {
+            let x: i32 = (idx % IMAGES_USED_X as usize) as i32;
+            let y: i32 = (idx / IMAGES_USED_X as usize) as i32 - scroll + matrix_offset_y;
+            if y < matrix_offset_y {
+                continue;
+            }
+            let obj = Object {
+                position: Vector2i { x, y },
+                image_id,
+            };
+            matrix.push(obj);
+        }
     }
     (matrix, matrix_offset_y)
 }
@@ -677,8 +3849,6 @@ fn push_texture_coordinates(
     buf: &mut Vec<Vertex>,
 ) {
     let tilesize = TILESIZE as f32;
-    let tex_x = f32::from(image_id % IMAGES_X) * tilesize;
-    let tex_y = f32::from(image_id / IMAGES_X) * tilesize;
     let mut tf = Transform::default();
     let object_pos = grid_to_win(Vector2 { x: pos_x, y: pos_y }, scale);
     tf.translate(object_pos.x, object_pos.y);
@@ -688,7 +3858,82 @@ fn push_texture_coordinates(
         0. * scale * tilesize / 2.0,
         0. * scale * tilesize / 2.0,
     );
+    push_transformed_texture_coordinates(image_id, &tf, color, buf);
+}
+
+/// Like push_texture_coordinates(), but shrinks the image by up to 15% and moves it sideways
+/// depending on `hash`. The image stays inside its tile and on the ground.
+fn push_jittered_texture_coordinates(
+    image_id: ImageId,
+    pos_x: i32,
+    pos_y: i32,
+    hash: u32,
+    scale: f32,
+    color: Color,
+    buf: &mut Vec<Vertex>,
+) {
+    let tilesize = TILESIZE as f32;
+    let size = 0.85 + (hash % 16) as f32 / 100.0;
+    let shift = ((hash / 16 % 16) as f32 / 15.0 - 0.5) * (1.0 - size);
+    let mut tf = Transform::default();
+    let object_pos = grid_to_win(Vector2 { x: pos_x, y: pos_y }, scale);
+    tf.translate(
+        object_pos.x + ((1.0 - size) / 2.0 + shift) * tilesize * scale,
+        object_pos.y + (1.0 - size) * tilesize * scale,
+    );
+    tf.scale_with_center(scale * size, scale * size, 0., 0.);
+    push_transformed_texture_coordinates(image_id, &tf, color, buf);
+}
+
+/// Turn the image of the last pushed quad `rotation` quarter turns clockwise
+fn rotate_texture_coordinates(quad: &mut [Vertex], rotation: u8) {
+    let tex_coords: Vec<Vector2f> = quad.iter().map(|vertex| vertex.tex_coords).collect();
+    for (i, vertex) in quad.iter_mut().enumerate() {
+        vertex.tex_coords = tex_coords[(i + rotation as usize) % 4];
+    }
+}
+
+/// Move the texture coordinates of the quads `inset` pixels towards the middle of the image,
+/// so fractional scales do not sample the neighboring images of the atlas
+fn inset_texture_coordinates(buf: &mut [Vertex], inset: f32) {
+    for quad in buf.chunks_mut(4) {
+        // the corners of rotated images are in a different order
+        let min = Vector2f::new(
+            quad.iter().map(|v| v.tex_coords.x).fold(f32::MAX, f32::min),
+            quad.iter().map(|v| v.tex_coords.y).fold(f32::MAX, f32::min),
+        );
+        let max = Vector2f::new(
+            quad.iter().map(|v| v.tex_coords.x).fold(f32::MIN, f32::max),
+            quad.iter().map(|v| v.tex_coords.y).fold(f32::MIN, f32::max),
+        );
+        for vertex in quad {
+            vertex.tex_coords.x += if vertex.tex_coords.x == min.x {
+                inset
+            } else if vertex.tex_coords.x == max.x {
+                -inset
+            } else {
+                0.
+            };
+            vertex.tex_coords.y += if vertex.tex_coords.y == min.y {
+                inset
+            } else if vertex.tex_coords.y == max.y {
+                -inset
+            } else {
+                0.
+            };
+        }
+    }
+}
 
+fn push_transformed_texture_coordinates(
+    image_id: ImageId,
+    tf: &Transform,
+    color: Color,
+    buf: &mut Vec<Vertex>,
+) {
+    let tilesize = TILESIZE as f32;
+    let tex_x = f32::from(image_id % IMAGES_X) * tilesize;
+    let tex_y = f32::from(image_id / IMAGES_X) * tilesize;
     buf.push(Vertex {
         color,
         position: tf.transform_point(Vector2f::new(0., 0.)),
@@ -710,3 +3955,112 @@ fn push_texture_coordinates(
         tex_coords: Vector2f::new(tex_x + tilesize, tex_y),
     });
 }
+
+/// untextured square of `size` x `size` tiles
+fn push_color_coordinates(
+    pos_x: i32,
+    pos_y: i32,
+    size: i32,
+    scale: f32,
+    color: Color,
+    buf: &mut Vec<Vertex>,
+) {
+    let size = size as f32 * TILESIZE as f32 * scale;
+    let object_pos = grid_to_win(Vector2 { x: pos_x, y: pos_y }, scale);
+    for (x, y) in [(0., 0.), (0., size), (size, size), (size, 0.)] {
+        buf.push(Vertex {
+            color,
+            position: object_pos + Vector2f::new(x, y),
+            tex_coords: Vector2f::new(0., 0.),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::{PINE_1_1, STONE};
+
+    #[test]
+    fn view_grid_round_trips_in_every_rotation() {
+        for rotation in 0..4 {
+            let view = ViewGrid::new(94, -44, Vector2i::new(40, 30), rotation);
+            for y in -3..33 {
+                for x in -3..43 {
+                    let view_pos = Vector2i::new(x, y);
+                    assert_eq!(view.to_view(view.to_world(view_pos)), view_pos);
+                    let world_pos = Vector2i::new(x + 80, y - 50);
+                    assert_eq!(view.to_world(view.to_view(world_pos)), world_pos);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn click_lands_on_the_world_tile_in_every_rotation() {
+        let scale = 2.0;
+        // one tile right of the center of a 10x8 grid, in the middle of the tile
+        let click = Vector2f::new(6.5, 4.5) * (TILESIZE as f32 * scale);
+        let expected = [(106, 204), (105, 203), (104, 204), (105, 205)];
+        for (rotation, (x, y)) in expected.into_iter().enumerate() {
+            let view = ViewGrid::new(100, 200, Vector2i::new(10, 8), rotation as u8);
+            let world_pos = view.to_world(win_to_grid(click, scale));
+            assert_eq!(world_pos, Vector2i::new(x, y), "rotation {}", rotation);
+        }
+    }
+
+    #[test]
+    fn shipped_multi_images_are_the_built_in_ones() {
+        let shipped = MultiImage::from_file(example_res!("multi_images.txt")).unwrap();
+        let built_in = MultiImage::built_in();
+        assert_eq!(shipped.len(), built_in.len());
+        for (shipped, built_in) in shipped.iter().zip(&built_in) {
+            assert_eq!(shipped.name, built_in.name);
+            assert_eq!(shipped.image_ids, built_in.image_ids);
+        }
+    }
+
+    #[test]
+    fn shipped_tiles_are_the_built_in_ones() {
+        let shipped = TileAtlas::load(example_res!("tiles.txt")).unwrap();
+        let built_in = TileAtlas::new();
+        for image_id in 0..IMAGES_CNT {
+            assert_eq!(
+                shipped.name(image_id),
+                built_in.name(image_id),
+                "{}",
+                image_id
+            );
+            assert_eq!(
+                shipped.category(image_id),
+                built_in.category(image_id),
+                "{}",
+                image_id
+            );
+            // the file only lists named images, the others are unused palette slots
+            if built_in.name(image_id).is_some() {
+                assert_eq!(
+                    shipped.is_background(image_id),
+                    built_in.is_background(image_id),
+                    "{}",
+                    image_id
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rect_fill_uses_the_background_flags_like_painting() {
+        let atlas = TileAtlas::new();
+        let eraser = 3 * IMAGES_X + 3;
+        let tile = |image_id| rect_fill_tile(&MouseObject::ImageId(image_id), eraser, &atlas);
+        let stone = tile(STONE).unwrap();
+        assert_eq!((stone.bg, stone.fg), (Some(STONE), None));
+        let pine = tile(PINE_1_1).unwrap();
+        assert_eq!((pine.bg, pine.fg), (Some(GRASS), Some(PINE_1_1)));
+        let erased = tile(eraser).unwrap();
+        assert_eq!((erased.bg, erased.fg), (None, None));
+        let multi_image = MouseObject::MultiImage(MultiImage::built_in().remove(0));
+        assert!(rect_fill_tile(&multi_image, eraser, &atlas).is_none());
+    }
+}
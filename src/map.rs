@@ -1,12 +1,18 @@
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::time::{Duration, Instant};
 
-use rzdb::{Data, Db};
+use rzdb::{Data, Db, Row};
+use sfml::graphics::{Color, Image};
 
 use crate::chunk::Chunk;
+use crate::history::EditHistory;
 use crate::image::{
-    MultiImage, COPPER, DIRT, FLOWER1, FLOWER2, FLOWER3, GOLD, GRASS, IMAGES_X, IRON, OAK_1_1,
-    OAK_1_1_RED, OAK_1_1_SMALL, PINE_1_1, STONE, WATER,
+    ImageId, MultiImage, MultiImagePart, BERRY_BUSH, COAL, COPPER, DIRT, FLOWER1, FLOWER2, FLOWER3,
+    GOLD, GRASS, IMAGES_CNT, IMAGES_X, IRON, OAK_1_1, OAK_1_1_RED, OAK_1_1_SMALL, PINE_1_1, ROCK,
+    SAND, SNOW, STONE, WATER,
 };
+use crate::journal::{Journal, JournalEntry};
 use crate::tile::Tile;
 
 /// The first bit of the index is the sign of the coordinate - both x and y
@@ -36,17 +42,70 @@ fn u_to_i(idx: usize) -> i32 {
     }
 }
 
-fn chunkify(i: i32) -> (usize, usize) {
+/// Split a tile coordinate into the signed chunk coordinate and the offset inside the chunk
+fn chunkify(i: i32) -> (i32, usize) {
     let cs = Chunk::chunksize() as i32;
-    let (chunk, rest) = if i < 0 {
-        ((i - cs + 1) / cs, (i + 1) % cs + cs - 1)
-    } else {
-        (i / cs, i % cs)
-    };
-    (i_to_u(chunk), rest as usize)
+    (i.div_euclid(cs), i.rem_euclid(cs) as usize)
+}
+
+/// tile coordinates from signed chunk coordinates and the position inside the chunk
+fn tile_pos(chunk: (i32, i32, i32), rest: (usize, usize, usize)) -> (i32, i32, i32) {
+    let cs = Chunk::chunksize() as i32;
+    (
+        chunk.0 * cs + rest.0 as i32,
+        chunk.1 * cs + rest.1 as i32,
+        chunk.2 * cs + rest.2 as i32,
+    )
+}
+
+/// number of columns of a row, counted up to max+1
+fn column_count(row: &Row, max: usize) -> usize {
+    (0..=max)
+        .take_while(|&idx| row.select_at(idx).is_ok())
+        .count()
+}
+
+/// names of the columns of the map table in the layout of `format`, see MAP_FORMAT
+fn map_columns(format: i64) -> Vec<String> {
+    let mut columns: Vec<String> = ["chunk_x", "chunk_y", "chunk_z", "z", "y"]
+        .map(String::from)
+        .into();
+    if format >= 3 {
+        columns.push("encoding".to_string());
+    }
+    for i in 0..Chunk::chunksize() {
+        columns.push(format!("bg{i}"));
+        columns.push(format!("fg{i}"));
+        if format >= 2 {
+            columns.push(format!("fg2_{i}"));
+        }
+    }
+    columns
 }
 
+/// Default for Map::world_limit. Beyond it the tile and chunk coordinates would come close to
+/// overflowing i32.
+pub const WORLD_LIMIT: i32 = 100_000_000;
+/// flood fills of larger areas are refused, the terrain goes on forever
+pub const FLOOD_FILL_MAX_TILES: usize = 100_000;
+/// version of the layout of the map table, stored as map_format in the config
+/// 1: bg, fg per tile, 2: bg, fg, fg2 per tile, 3: encoding column, rows may be run-length
+/// encoded, 4: changed chunks are appended after a clear row
+pub const MAP_FORMAT: i64 = 4;
+/// file with the strokes since the last save, in the working directory
+pub const JOURNAL_PATH: &str = "w8_journal.txt";
+/// default for Map::max_resident_chunks, about 32 KB each
+pub const MAX_RESIDENT_CHUNKS: usize = 2048;
+/// generated chunks closer to the camera than this many chunks are never evicted
+const EVICT_DISTANCE: i32 = 8;
+/// largest width and height of Map::render_overview() in tiles, 64 MB as RGBA
+const OVERVIEW_MAX_SIZE: i64 = 4096;
+/// how long the time of an edit is kept, see Map::edit_age()
+pub const EDIT_AGE_MAX: Duration = Duration::from_secs(300);
+
+#[derive(Clone, Copy)]
 struct NoiseMeta {
+    name: &'static str, // identifies the noise in the stored parameters
     id: usize,
     frequency: f32,
     octaves: u8, // changes noise_min/noise_max
@@ -57,6 +116,21 @@ struct NoiseMeta {
     max_value: i16, // noise_min and noise_max
     seed: i32,
 }
+impl NoiseMeta {
+    /// Scale a noise value to min_value..max_value. Rounded down with `floor`, truncating
+    /// makes the value 0 twice as wide as the others, with a visible step where the noise
+    /// crosses it. Older worlds truncate, see NoiseConfig::floor.
+    fn value(&self, noise: f32, floor: bool) -> i16 {
+        let value = (noise - self.noise_min) / (self.noise_max - self.noise_min)
+            * (self.max_value - self.min_value) as f32
+            + self.min_value as f32;
+        if floor {
+            value.floor() as i16
+        } else {
+            value as i16
+        }
+    }
+}
 
 const NOISE_2_OCTAVES_MIN: f32 = -0.0911;
 const NOISE_2_OCTAVES_MAX: f32 = 0.0911;
@@ -64,6 +138,7 @@ const NOISE_5_OCTAVES_MIN: f32 = -0.66;
 const NOISE_5_OCTAVES_MAX: f32 = 0.66;
 
 const NOISE_TERRAIN_HEIGHT: NoiseMeta = NoiseMeta {
+    name: "terrain_height",
     id: 0,
     seed: 1,
     frequency: 0.04,
@@ -76,6 +151,7 @@ const NOISE_TERRAIN_HEIGHT: NoiseMeta = NoiseMeta {
 };
 
 const NOISE_SOIL_THICKNESS: NoiseMeta = NoiseMeta {
+    name: "soil_thickness",
     id: 1,
     seed: 0,
     frequency: 0.02,
@@ -88,6 +164,7 @@ const NOISE_SOIL_THICKNESS: NoiseMeta = NoiseMeta {
 };
 
 const NOISE_VEGETATION: NoiseMeta = NoiseMeta {
+    name: "vegetation",
     id: 2,
     seed: 2,
     frequency: 0.030,
@@ -99,9 +176,82 @@ const NOISE_VEGETATION: NoiseMeta = NoiseMeta {
     max_value: 300,
 };
 
-const NOISE_2D_COUNT: usize = 3;
+const NOISE_BIOME: NoiseMeta = NoiseMeta {
+    name: "biome",
+    id: 3,
+    seed: 7,
+    frequency: 0.006,
+    octaves: 2,
+    lacunarity: 0.4,
+    noise_min: NOISE_2_OCTAVES_MIN,
+    noise_max: NOISE_2_OCTAVES_MAX,
+    min_value: 0,
+    max_value: 3,
+};
+
+/// Rivers run where this noise crosses zero, folding it at zero makes narrow ridges of the
+/// broad noise, see Map::river_depth()
+const NOISE_RIVER: NoiseMeta = NoiseMeta {
+    name: "river",
+    id: 4,
+    seed: 8,
+    frequency: 0.008,
+    octaves: 2,
+    lacunarity: 0.4,
+    noise_min: NOISE_2_OCTAVES_MIN,
+    noise_max: NOISE_2_OCTAVES_MAX,
+    min_value: -100,
+    max_value: 100,
+};
+
+const NOISE_2D_COUNT: usize = 5;
+
+/// The kind of surface of a region, chosen by the biome noise
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Biome {
+    Desert,
+    Grassland,
+    Tundra,
+}
+impl Biome {
+    fn from_value(value: i16) -> Biome {
+        match value {
+            i16::MIN..=0 => Biome::Desert,
+            1 => Biome::Grassland,
+            _ => Biome::Tundra,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            Biome::Desert => "desert",
+            Biome::Grassland => "grassland",
+            Biome::Tundra => "tundra",
+        }
+    }
+}
+
+/// Noise: trees and flowers follow the smooth vegetation noise and grow in bands.
+/// Hash: every tile decides on its own, from a hash of its position.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VegetationMode {
+    Noise,
+    Hash,
+}
+
+/// deterministic pseudo random value of a world position
+pub fn tile_hash(x: i32, y: i32, seed: i32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (seed as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297a_2d39);
+    h ^ (h >> 15)
+}
 
 const NOISE_IRON_ORE: NoiseMeta = NoiseMeta {
+    name: "iron_ore",
     id: 0,
     seed: 3,
     frequency: 0.06,
@@ -114,6 +264,7 @@ const NOISE_IRON_ORE: NoiseMeta = NoiseMeta {
 };
 
 const NOISE_COPPER_ORE: NoiseMeta = NoiseMeta {
+    name: "copper_ore",
     id: 1,
     seed: 4,
     frequency: 0.06,
@@ -126,6 +277,7 @@ const NOISE_COPPER_ORE: NoiseMeta = NoiseMeta {
 };
 
 const NOISE_GOLD_ORE: NoiseMeta = NoiseMeta {
+    name: "gold_ore",
     id: 2,
     seed: 5,
     frequency: 0.16,
@@ -137,148 +289,585 @@ const NOISE_GOLD_ORE: NoiseMeta = NoiseMeta {
     max_value: 50,
 };
 
-const NOISE_3D_COUNT: usize = 3;
+/// coal is the most common ore, the larger part of its range is below ORE_DENSITY_THRESHOLD
+const NOISE_COAL_ORE: NoiseMeta = NoiseMeta {
+    name: "coal_ore",
+    id: 4,
+    seed: 10,
+    frequency: 0.08,
+    octaves: 2,
+    lacunarity: 0.4,
+    noise_min: NOISE_2_OCTAVES_MIN,
+    noise_max: NOISE_2_OCTAVES_MAX,
+    min_value: -8,
+    max_value: 12,
+};
+
+/// Ores only occur in the regions where this slow noise is above ORE_REGION_THRESHOLD, inside
+/// them the ore noises decide which tiles are ore, so the ores form veins
+const NOISE_ORE_REGION: NoiseMeta = NoiseMeta {
+    name: "ore_region",
+    id: 5,
+    seed: 11,
+    frequency: 0.02,
+    octaves: 2,
+    lacunarity: 0.4,
+    noise_min: NOISE_2_OCTAVES_MIN,
+    noise_max: NOISE_2_OCTAVES_MAX,
+    min_value: -100,
+    max_value: 100,
+};
+const ORE_REGION_THRESHOLD: i16 = 10;
+/// inside an ore region, tiles with an ore noise value below it are ore
+const ORE_DENSITY_THRESHOLD: i16 = 3;
+
+const NOISE_CAVE: NoiseMeta = NoiseMeta {
+    name: "cave",
+    id: 3,
+    seed: 9,
+    frequency: 0.05,
+    octaves: 2,
+    lacunarity: 0.4,
+    noise_min: NOISE_2_OCTAVES_MIN,
+    noise_max: NOISE_2_OCTAVES_MAX,
+    min_value: -100,
+    max_value: 100,
+};
+/// tiles below the soil with a cave noise value above it are empty, lower values give more caves
+const CAVE_THRESHOLD: i16 = 55;
+
+const NOISE_3D_COUNT: usize = 6;
+/// the noises of the worlds saved before the noise parameters were, the passes of the later
+/// ones (biomes, rivers, caves, coal and ore regions) are off in them
+const FIRST_NOISES: [&str; 6] = [
+    "terrain_height",
+    "soil_thickness",
+    "vegetation",
+    "iron_ore",
+    "copper_ore",
+    "gold_ore",
+];
+
+/// The parameters of all noises of a world, they can be changed while the world is open
+pub struct NoiseConfig {
+    params_2d: [NoiseMeta; NOISE_2D_COUNT],
+    params_3d: [NoiseMeta; NOISE_3D_COUNT],
+    floor: bool, // noise values are rounded down, worlds saved without parameters truncate
+    disabled: Vec<&'static str>, // noises the world was created without, their passes are off
+}
+impl NoiseConfig {
+    /// the compiled defaults
+    pub fn new() -> Self {
+        NoiseConfig {
+            params_2d: [
+                NOISE_TERRAIN_HEIGHT,
+                NOISE_SOIL_THICKNESS,
+                NOISE_VEGETATION,
+                NOISE_BIOME,
+                NOISE_RIVER,
+            ],
+            params_3d: [
+                NOISE_IRON_ORE,
+                NOISE_COPPER_ORE,
+                NOISE_GOLD_ORE,
+                NOISE_CAVE,
+                NOISE_COAL_ORE,
+                NOISE_ORE_REGION,
+            ],
+            floor: true,
+            disabled: vec![],
+        }
+    }
+    /// false for the noises the world was created without, see Map::parse_noise_params()
+    fn generates(&self, noise: &NoiseMeta) -> bool {
+        !self.disabled.contains(&noise.name)
+    }
+    /// a parameter of the terrain height noise as text
+    pub fn terrain_text(&self, param: NoiseParam) -> String {
+        let terrain = &self.params_2d[NOISE_TERRAIN_HEIGHT.id];
+        match param {
+            NoiseParam::Frequency => format!("{:.4}", terrain.frequency),
+            NoiseParam::Octaves => terrain.octaves.to_string(),
+            NoiseParam::Lacunarity => format!("{:.2}", terrain.lacunarity),
+        }
+    }
+    /// Change a parameter of the terrain height noise by `steps` steps, negative steps make it
+    /// smaller. noise_min and noise_max stay, so other octaves scale the height less exactly.
+    pub fn nudge_terrain(&mut self, param: NoiseParam, steps: i32) {
+        let terrain = &mut self.params_2d[NOISE_TERRAIN_HEIGHT.id];
+        match param {
+            NoiseParam::Frequency => {
+                terrain.frequency = (terrain.frequency * 1.1f32.powi(steps)).clamp(0.001, 1.0)
+            }
+            NoiseParam::Octaves => {
+                terrain.octaves = (terrain.octaves as i32 + steps).clamp(1, 8) as u8
+            }
+            NoiseParam::Lacunarity => {
+                terrain.lacunarity = (terrain.lacunarity + 0.05 * steps as f32).clamp(0.05, 4.0)
+            }
+        }
+    }
+}
+
+/// the parameters of a noise which can be changed in the noise panel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoiseParam {
+    Frequency,
+    Octaves,
+    Lacunarity,
+}
+impl NoiseParam {
+    pub const ALL: [NoiseParam; 3] = [
+        NoiseParam::Frequency,
+        NoiseParam::Octaves,
+        NoiseParam::Lacunarity,
+    ];
+    pub fn name(&self) -> &'static str {
+        match self {
+            NoiseParam::Frequency => "frequency",
+            NoiseParam::Octaves => "octaves",
+            NoiseParam::Lacunarity => "lacunarity",
+        }
+    }
+}
+
+/// seed of the hash which places berry bushes and rocks, see Map::surface_resource()
+const NOISE_SURFACE_RESOURCE_SEED: i32 = 6;
 
 struct Noise {
     data: Vec<i16>, // chunksize*chunksize values for 2d noise, chunksize*chunksize*chunksize values for 3d noise
 }
 
+/// chunk position and its 2d and 3d noise
+type ChunkNoise = ((usize, usize, usize), Vec<Noise>, Vec<Noise>);
+/// smallest and largest corner of a box of tiles
+pub type Bounds = ((i32, i32, i32), (i32, i32, i32));
+/// tiles of one chunk as (x, y, z, tile) relative to the chunk
+type ChunkTiles = Vec<(usize, usize, usize, Tile)>;
+
+/// The raw generation values of a tile, see Map::debug_sample()
+pub struct DebugSample {
+    pub terrain_height: i16,
+    pub soil_thickness: i16,
+    pub vegetation: i16,
+    pub biome: Biome,
+    pub iron_ore_depth: i16,
+    pub copper_ore_depth: i16,
+    pub gold_ore_depth: i16,
+    pub coal_ore_depth: i16,
+    pub ore_region: i16,
+}
+
 pub struct Map {
-    chunks_modified: Vec<Vec<Vec<Chunk>>>,
-    chunks_generated: Vec<Vec<Vec<Chunk>>>,
+    chunks_modified: HashMap<(i32, i32, i32), Chunk>, // key: signed chunk_x, chunk_y, chunk_z
+    // key: chunk_x, chunk_y, chunk_z by i_to_u(), value: the chunk and when get() used it last
+    chunks_generated: HashMap<(usize, usize, usize), (Chunk, u64)>,
     noise_min: f32,
     noise_max: f32,
     pub iron_ore_count: usize,
     pub copper_ore_count: usize,
     pub gold_ore_count: usize,
+    pub coal_ore_count: usize,
+    pub history: EditHistory,
+    pub journal: Journal, // changes since the last save, for crash recovery
+    pub vegetation_mode: VegetationMode,
+    pub vegetation_density: u32, // per mille of grass tiles with vegetation in hash mode
+    pub terrace_step: i16,       // terrain height is rounded to multiples of it, 0 = off
+    pub sea_level: i16,          // terrain up to it is under water, higher terrain is grass
+    pub seed: i32,               // added to the seed of every noise
+    pub resource_density: u32,   // per mille of surface tiles with berry bushes or rocks
+    pub river_width: i16,        // half width of the rivers in river noise values, 0 = off
+    debug_noise: Option<ChunkNoise>, // last sampled chunk for debug_sample()
+    // the noise parameters of this world, the compiled defaults unless stored ones are loaded
+    noise: NoiseConfig,
+    edit_times: HashMap<(i32, i32, i32), Instant>, // when tiles were set in this session
+    // when the edit_times older than EDIT_AGE_MAX were dropped last
+    edit_times_expired: Instant,
+    scratch: Option<HashMap<(i32, i32, i32), Tile>>, // edits which are not committed yet
+    pub world_limit: i32, // tiles beyond it in any direction are empty and can not be set
+    world_limit_warned: bool,
+    pub prefetch_pending: usize, // chunks prefetch() had no time for
+    prefetching: bool,
+    recently_generated: Vec<GeneratedChunk>,
+    uses: u64, // number of get() calls into generated chunks, the clock of the chunk uses
+    pub max_resident_chunks: usize,
+    removed_chunks: HashSet<(i32, i32, i32)>, // edited chunks discarded since the last store
+    stored_table: Option<String>,             // the table take_rows() can append to
+    appended_chunks: usize, // chunks appended to it since it was written from scratch
+}
+/// Rows of the map table taken by Map::take_rows(), so they can be written to the database
+/// on another thread
+pub struct MapRows {
+    table_name: String,
+    from_scratch: bool, // the table is created again before the rows are appended
+    rows: Vec<Vec<Data>>,
+}
+impl MapRows {
+    pub fn write(self, db: &mut Db) -> Result<(), Box<dyn Error>> {
+        let table_name = &self.table_name;
+        if self.from_scratch {
+            db.create_or_replace_table(table_name)?;
+            for column in map_columns(MAP_FORMAT) {
+                db.create_column(table_name, &column)?;
+            }
+        }
+        for row in self.rows {
+            db.insert_data(table_name, row)?;
+        }
+        Ok(())
+    }
+}
+/// a chunk which was generated recently, for showing the generation progress
+pub struct GeneratedChunk {
+    pub chunk: (i32, i32, i32), // signed chunk coordinates
+    pub time: Instant,
+    pub prefetched: bool, // false if it was generated when it was needed
 }
 impl Map {
     pub fn new() -> Self {
         Map {
-            chunks_modified: vec![],
-            chunks_generated: vec![],
+            chunks_modified: HashMap::new(),
+            chunks_generated: HashMap::new(),
             noise_min: NOISE_2_OCTAVES_MIN,
             noise_max: NOISE_2_OCTAVES_MAX,
             iron_ore_count: 0,
             copper_ore_count: 0,
             gold_ore_count: 0,
+            coal_ore_count: 0,
+            history: EditHistory::new(),
+            journal: Journal::new(JOURNAL_PATH),
+            vegetation_mode: VegetationMode::Noise,
+            vegetation_density: 40,
+            terrace_step: 0,
+            sea_level: 0,
+            seed: 0,
+            resource_density: 5,
+            river_width: 8,
+            debug_noise: None,
+            noise: NoiseConfig::new(),
+            edit_times: HashMap::new(),
+            edit_times_expired: Instant::now(),
+            scratch: None,
+            world_limit: WORLD_LIMIT,
+            world_limit_warned: false,
+            prefetch_pending: 0,
+            prefetching: false,
+            recently_generated: vec![],
+            uses: 0,
+            max_resident_chunks: MAX_RESIDENT_CHUNKS,
+            removed_chunks: HashSet::new(),
+            stored_table: None,
+            appended_chunks: 0,
         }
     }
+    /// true for coordinates beyond the world limit, the first one is logged
+    fn outside_world(&mut self, x: i32, y: i32, z: i32) -> bool {
+        let limit = self.world_limit.unsigned_abs();
+        let outside = [x, y, z].iter().any(|i| i.unsigned_abs() > limit);
+        if outside && !self.world_limit_warned {
+            println!(
+                "warning: {},{},{} is beyond the world limit of {} tiles, ignored",
+                x, y, z, limit
+            );
+            self.world_limit_warned = true;
+        }
+        outside
+    }
     pub fn get(&mut self, x: i32, y: i32, z: i32) -> Tile {
+        if let Some(tile) = self
+            .scratch
+            .as_ref()
+            .and_then(|scratch| scratch.get(&(x, y, z)))
+        {
+            return *tile;
+        }
+        if self.outside_world(x, y, z) {
+            return Tile {
+                bg: None,
+                fg: None,
+                fg_rotation: 0,
+                fg2: None,
+            };
+        }
         let (chunk_x, rest_x) = chunkify(x);
         let (chunk_y, rest_y) = chunkify(y);
         let (chunk_z, rest_z) = chunkify(z);
-        if chunk_z < self.chunks_modified.len()
-            && chunk_y < self.chunks_modified[chunk_z].len()
-            && chunk_x < self.chunks_modified[chunk_z][chunk_y].len()
-        {
-            let chunk = &self.chunks_modified[chunk_z][chunk_y][chunk_x];
+        if let Some(chunk) = self.chunks_modified.get(&(chunk_x, chunk_y, chunk_z)) {
             if let Some(tile) = chunk.get(rest_x, rest_y, rest_z) {
                 return tile;
             }
         }
-        if chunk_z < self.chunks_modified.len()
-            && chunk_y < self.chunks_modified[chunk_z].len()
-            && chunk_x < self.chunks_modified[chunk_z][chunk_y].len()
-        {
-            let chunk = &self.chunks_modified[chunk_z][chunk_y][chunk_x];
-            if let Some(tile) = chunk.get(rest_x, rest_y, rest_z) {
-                return tile;
+        let (chunk_x, chunk_y, chunk_z) = (i_to_u(chunk_x), i_to_u(chunk_y), i_to_u(chunk_z));
+        self.generate_noise(chunk_x, chunk_y, chunk_z);
+        self.uses += 1;
+        let (chunk, last_used) = self
+            .chunks_generated
+            .get_mut(&(chunk_x, chunk_y, chunk_z))
+            .unwrap();
+        *last_used = self.uses;
+        chunk.get(rest_x, rest_y, rest_z).unwrap()
+    }
+
+    /// z of the topmost tile with a background between z and z-depth+1, None if all are empty
+    pub fn surface_height(&mut self, x: i32, y: i32, z: i32, depth: i32) -> Option<i32> {
+        (z - depth + 1..=z)
+            .rev()
+            .find(|&pos_z| self.get(x, y, pos_z).bg.is_some())
+    }
+
+    /// Raise the surface between z and z-depth+1 of every column by one tile of `material`,
+    /// or lower it by removing the top tile if material is None. The surface does not rise
+    /// above z. Returns the number of changed columns.
+    pub fn sculpt(
+        &mut self,
+        columns: &[(i32, i32)],
+        z: i32,
+        depth: i32,
+        material: Option<ImageId>,
+    ) -> usize {
+        let mut tiles = vec![];
+        for &(x, y) in columns {
+            match (self.surface_height(x, y, z, depth), material) {
+                (Some(surface_z), Some(image_id)) if surface_z < z => tiles.push((
+                    x,
+                    y,
+                    surface_z + 1,
+                    Tile {
+                        bg: Some(image_id),
+                        fg: None,
+                        fg_rotation: 0,
+                        fg2: None,
+                    },
+                )),
+                (Some(surface_z), None) => tiles.push((
+                    x,
+                    y,
+                    surface_z,
+                    Tile {
+                        bg: None,
+                        fg: None,
+                        fg_rotation: 0,
+                        fg2: None,
+                    },
+                )),
+                _ => {}
             }
         }
-        self.generate_noise(chunk_x, chunk_y, chunk_z);
-        self.chunks_generated[chunk_z][chunk_y][chunk_x]
-            .get(rest_x, rest_y, rest_z)
-            .unwrap()
+        self.set_range(&tiles);
+        tiles.len()
     }
 
-    // TODO: We take the old encoding and encode into the new one. Switch everything to new encoding.
-    fn generate_noise(&mut self, chunk_x: usize, chunk_y: usize, chunk_z: usize) {
+    /// terrain height, soil thickness, vegetation, biome and river noise of a chunk column,
+    /// chunksize*chunksize values each
+    fn noise_2d(&mut self, chunk_x: usize, chunk_y: usize) -> Vec<Noise> {
         let chunksize = Chunk::chunksize();
-        let has_data = {
-            let chunk = self.get_chunk_generated_mut(chunk_x, chunk_y, chunk_z);
-            chunk.has_data()
-        };
-        if !has_data {
-            let mut noise_2d = vec![];
-            for _ in 0..NOISE_2D_COUNT {
-                noise_2d.push(Noise { data: vec![] });
+        let mut noise_2d = vec![];
+        for _ in 0..NOISE_2D_COUNT {
+            noise_2d.push(Noise { data: vec![] });
+        }
+        for (id, noise_struct) in self.noise.params_2d.into_iter().enumerate() {
+            let noise = &mut noise_2d[id];
+            if !self.noise.generates(&noise_struct) {
+                noise.data = vec![0; chunksize * chunksize];
+                continue;
             }
-            for (id, noise_struct) in [NOISE_TERRAIN_HEIGHT, NOISE_SOIL_THICKNESS, NOISE_VEGETATION]
-                .iter()
-                .enumerate()
-            {
-                let noise = &mut noise_2d[id];
-                let (data, min, max) = simdnoise::NoiseBuilder::fbm_2d_offset(
-                    (u_to_i(chunk_x) * chunksize as i32) as f32,
-                    chunksize,
-                    (u_to_i(chunk_y) * chunksize as i32) as f32,
-                    chunksize,
-                )
-                .with_freq(noise_struct.frequency)
-                .with_octaves(noise_struct.octaves)
-                .with_lacunarity(noise_struct.lacunarity)
-                .with_seed(noise_struct.seed)
-                .generate();
-                if min < noise_struct.noise_min && id > 0 && min < self.noise_min {
-                    self.noise_min = self.noise_min.min(min);
-                    println!("new noise_2d[{}] min: {}", id, min);
-                }
-                if max > noise_struct.noise_max && id > 0 && max > self.noise_max {
-                    self.noise_max = self.noise_max.max(max);
-                    println!("new noise_2d[{}] max: {}", id, max);
-                }
-                noise.data = data
-                    .iter()
-                    .map(|x| {
-                        ((x - noise_struct.noise_min)
-                            / (noise_struct.noise_max - noise_struct.noise_min)
-                            * (noise_struct.max_value - noise_struct.min_value) as f32
-                            + noise_struct.min_value as f32) as i16
-                    })
-                    .collect();
-            }
-
-            let mut noise_3d = vec![];
-            for _ in 0..NOISE_3D_COUNT {
-                noise_3d.push(Noise { data: vec![] });
-            }
-            for (id, noise_struct) in [NOISE_IRON_ORE, NOISE_COPPER_ORE, NOISE_GOLD_ORE]
+            // world coordinates chunk*chunksize..(chunk+1)*chunksize, so the edges of
+            // neighboring chunks are consecutive samples of the same noise
+            let (data, min, max) = simdnoise::NoiseBuilder::fbm_2d_offset(
+                (u_to_i(chunk_x) * chunksize as i32) as f32,
+                chunksize,
+                (u_to_i(chunk_y) * chunksize as i32) as f32,
+                chunksize,
+            )
+            .with_freq(noise_struct.frequency)
+            .with_octaves(noise_struct.octaves)
+            .with_lacunarity(noise_struct.lacunarity)
+            .with_seed(noise_struct.seed.wrapping_add(self.seed))
+            .generate();
+            if min < noise_struct.noise_min && id > 0 && min < self.noise_min {
+                self.noise_min = self.noise_min.min(min);
+                println!("new noise_2d[{}] min: {}", id, min);
+            }
+            if max > noise_struct.noise_max && id > 0 && max > self.noise_max {
+                self.noise_max = self.noise_max.max(max);
+                println!("new noise_2d[{}] max: {}", id, max);
+            }
+            noise.data = data
                 .iter()
-                .enumerate()
-            {
-                let noise = &mut noise_3d[id];
-                let (data, min, max) = simdnoise::NoiseBuilder::fbm_3d_offset(
-                    (u_to_i(chunk_x) * chunksize as i32) as f32,
-                    chunksize,
-                    (u_to_i(chunk_y) * chunksize as i32) as f32,
-                    chunksize,
-                    (u_to_i(chunk_z) * chunksize as i32) as f32,
-                    chunksize,
-                )
-                .with_freq(noise_struct.frequency)
-                .with_octaves(noise_struct.octaves)
-                .with_lacunarity(noise_struct.lacunarity)
-                .with_seed(noise_struct.seed)
-                .generate();
-                if min < noise_struct.noise_min && id > 0 && min < self.noise_min {
-                    self.noise_min = self.noise_min.min(min);
-                    println!("new noise_3d[{}] min: {}", id, min);
-                }
-                if max > noise_struct.noise_max && id > 0 && max > self.noise_max {
-                    self.noise_max = self.noise_max.max(max);
-                    println!("new noise_3d[{}] max: {}", id, max);
-                }
-                noise.data = data
-                    .iter()
-                    .map(|x| {
-                        ((x - noise_struct.noise_min)
-                            / (noise_struct.noise_max - noise_struct.noise_min)
-                            * (noise_struct.max_value - noise_struct.min_value) as f32
-                            + noise_struct.min_value as f32) as i16
-                    })
-                    .collect();
+                .map(|&x| noise_struct.value(x, self.noise.floor))
+                .collect();
+        }
+        for height in &mut noise_2d[NOISE_TERRAIN_HEIGHT.id].data {
+            *height = self.terrace(*height);
+        }
+        noise_2d
+    }
+    /// terrain height rounded to the terrace step
+    fn terrace(&self, height: i16) -> i16 {
+        if self.terrace_step > 0 {
+            let step = self.terrace_step;
+            (height as f32 / step as f32).round() as i16 * step
+        } else {
+            height
+        }
+    }
+
+    /// Terrain of the world with another seed, without generating any chunks: one pixel per
+    /// `step` tiles of the `size` tiles around the origin, water and grass shaded by height.
+    /// `colors` are the colors of the images, see image::image_colors().
+    pub fn preview_seed(&self, seed: i32, size: (i32, i32), step: i32, colors: &[Color]) -> Image {
+        let noise_struct = self.noise.params_2d[NOISE_TERRAIN_HEIGHT.id];
+        let (width, height) = ((size.0 / step) as usize, (size.1 / step) as usize);
+        // sampling every step tiles is the same as a step times higher frequency
+        let (data, _, _) = simdnoise::NoiseBuilder::fbm_2d_offset(
+            -(width as f32) / 2.0,
+            width,
+            -(height as f32) / 2.0,
+            height,
+        )
+        .with_freq(noise_struct.frequency * step as f32)
+        .with_octaves(noise_struct.octaves)
+        .with_lacunarity(noise_struct.lacunarity)
+        .with_seed(noise_struct.seed.wrapping_add(seed))
+        .generate();
+        let range = (noise_struct.max_value - noise_struct.min_value) as f32;
+        let mut image = Image::new(width as u32, height as u32);
+        for (idx, value) in data.iter().enumerate() {
+            let terrain_height = self.terrace(noise_struct.value(*value, self.noise.floor));
+            let base = if terrain_height < self.sea_level {
+                colors[WATER as usize]
+            } else {
+                colors[GRASS as usize]
+            };
+            let brightness = 0.5 + 0.5 * (terrain_height - noise_struct.min_value) as f32 / range;
+            let shade = |c: u8| (c as f32 * brightness.clamp(0., 1.)) as u8;
+            let color = Color::rgb(shade(base.r), shade(base.g), shade(base.b));
+            let (x, y) = ((idx % width) as u32, (idx / width) as u32);
+            // safe: x and y are inside the image
+            unsafe { image.set_pixel(x, y, color) };
+        }
+        image
+    }
+
+    /// How deep a river lowers the terrain, 0 outside of rivers. Rivers are deepest in their
+    /// middle and only cut into terrain above the sea level, the sea is there already.
+    fn river_depth(&self, river: i16, terrain_height: i16) -> i16 {
+        let closeness = self.river_width - river.abs();
+        if closeness <= 0 || terrain_height <= self.sea_level {
+            0
+        } else {
+            2 + closeness * 2 / self.river_width
+        }
+    }
+    /// Sparse harvestable objects on the surface: berry bushes on grass, rocks on dirt, stone
+    /// and sand
+    fn surface_resource(&self, x: i32, y: i32, bg: Option<ImageId>) -> Option<ImageId> {
+        let hash = tile_hash(x, y, NOISE_SURFACE_RESOURCE_SEED.wrapping_add(self.seed));
+        if hash % 1000 >= self.resource_density {
+            return None;
+        }
+        match bg {
+            Some(GRASS) => Some(BERRY_BUSH),
+            Some(DIRT) | Some(STONE) | Some(SAND) => Some(ROCK),
+            _ => None,
+        }
+    }
+    /// iron, copper, gold and coal ore depth, ore regions and caves of a chunk,
+    /// chunksize*chunksize*chunksize values each
+    fn noise_3d(&mut self, chunk_x: usize, chunk_y: usize, chunk_z: usize) -> Vec<Noise> {
+        let chunksize = Chunk::chunksize();
+        let mut noise_3d = vec![];
+        for _ in 0..NOISE_3D_COUNT {
+            noise_3d.push(Noise { data: vec![] });
+        }
+        for (id, noise_struct) in self.noise.params_3d.into_iter().enumerate() {
+            let noise = &mut noise_3d[id];
+            if !self.noise.generates(&noise_struct) {
+                noise.data = vec![0; chunksize * chunksize * chunksize];
+                continue;
+            }
+            let (data, min, max) = simdnoise::NoiseBuilder::fbm_3d_offset(
+                (u_to_i(chunk_x) * chunksize as i32) as f32,
+                chunksize,
+                (u_to_i(chunk_y) * chunksize as i32) as f32,
+                chunksize,
+                (u_to_i(chunk_z) * chunksize as i32) as f32,
+                chunksize,
+            )
+            .with_freq(noise_struct.frequency)
+            .with_octaves(noise_struct.octaves)
+            .with_lacunarity(noise_struct.lacunarity)
+            .with_seed(noise_struct.seed.wrapping_add(self.seed))
+            .generate();
+            if min < noise_struct.noise_min && id > 0 && min < self.noise_min {
+                self.noise_min = self.noise_min.min(min);
+                println!("new noise_3d[{}] min: {}", id, min);
+            }
+            if max > noise_struct.noise_max && id > 0 && max > self.noise_max {
+                self.noise_max = self.noise_max.max(max);
+                println!("new noise_3d[{}] max: {}", id, max);
             }
+            noise.data = data
+                .iter()
+                .map(|&x| noise_struct.value(x, self.noise.floor))
+                .collect();
+        }
+        noise_3d
+    }
+
+    /// The noise values which generated the tile x,y,z. The noise of the last sampled chunk is
+    /// cached, so hovering inside a chunk is cheap.
+    pub fn debug_sample(&mut self, x: i32, y: i32, z: i32) -> DebugSample {
+        let chunksize = Chunk::chunksize();
+        let (chunk_x, rest_x) = chunkify(x);
+        let (chunk_y, rest_y) = chunkify(y);
+        let (chunk_z, rest_z) = chunkify(z);
+        let chunk_pos = (i_to_u(chunk_x), i_to_u(chunk_y), i_to_u(chunk_z));
+        if self.debug_noise.as_ref().map(|(pos, _, _)| *pos) != Some(chunk_pos) {
+            let noise_2d = self.noise_2d(chunk_pos.0, chunk_pos.1);
+            let noise_3d = self.noise_3d(chunk_pos.0, chunk_pos.1, chunk_pos.2);
+            self.debug_noise = Some((chunk_pos, noise_2d, noise_3d));
+        }
+        let (_, noise_2d, noise_3d) = self.debug_noise.as_ref().unwrap();
+        let idx_2d = rest_x + rest_y * chunksize;
+        let idx_3d = idx_2d + rest_z * chunksize * chunksize;
+        DebugSample {
+            terrain_height: noise_2d[NOISE_TERRAIN_HEIGHT.id].data[idx_2d],
+            soil_thickness: noise_2d[NOISE_SOIL_THICKNESS.id].data[idx_2d],
+            vegetation: noise_2d[NOISE_VEGETATION.id].data[idx_2d],
+            biome: if self.noise.generates(&NOISE_BIOME) {
+                Biome::from_value(noise_2d[NOISE_BIOME.id].data[idx_2d])
+            } else {
+                Biome::Grassland
+            },
+            iron_ore_depth: noise_3d[NOISE_IRON_ORE.id].data[idx_3d],
+            copper_ore_depth: noise_3d[NOISE_COPPER_ORE.id].data[idx_3d],
+            gold_ore_depth: noise_3d[NOISE_GOLD_ORE.id].data[idx_3d],
+            coal_ore_depth: noise_3d[NOISE_COAL_ORE.id].data[idx_3d],
+            ore_region: noise_3d[NOISE_ORE_REGION.id].data[idx_3d],
+        }
+    }
+
+    // TODO: We take the old encoding and encode into the new one. Switch everything to new encoding.
+    fn generate_noise(&mut self, chunk_x: usize, chunk_y: usize, chunk_z: usize) {
+        let chunksize = Chunk::chunksize();
+        if !self
+            .chunks_generated
+            .contains_key(&(chunk_x, chunk_y, chunk_z))
+        {
+            self.recently_generated.push(GeneratedChunk {
+                chunk: (u_to_i(chunk_x), u_to_i(chunk_y), u_to_i(chunk_z)),
+                time: Instant::now(),
+                prefetched: self.prefetching,
+            });
+            let noise_2d = self.noise_2d(chunk_x, chunk_y);
+            let noise_3d = self.noise_3d(chunk_x, chunk_y, chunk_z);
+            // older worlds are generated without the passes added after them
+            let biomes = self.noise.generates(&NOISE_BIOME);
+            let rivers = self.noise.generates(&NOISE_RIVER);
+            let caves = self.noise.generates(&NOISE_CAVE);
+            let coal = self.noise.generates(&NOISE_COAL_ORE);
+            let ore_regions = self.noise.generates(&NOISE_ORE_REGION);
 
             let mut tiles_z = vec![];
             for z in 0..chunksize {
@@ -291,89 +880,405 @@ impl Map {
                         let terrain_height = noise_2d[NOISE_TERRAIN_HEIGHT.id].data[idx_2d];
                         let soil_thickness = noise_2d[NOISE_SOIL_THICKNESS.id].data[idx_2d];
                         let vegetation = noise_2d[NOISE_VEGETATION.id].data[idx_2d];
+                        let biome = if biomes {
+                            Biome::from_value(noise_2d[NOISE_BIOME.id].data[idx_2d])
+                        } else {
+                            Biome::Grassland
+                        };
+                        let river_depth = if rivers {
+                            self.river_depth(noise_2d[NOISE_RIVER.id].data[idx_2d], terrain_height)
+                        } else {
+                            0
+                        };
 
                         let idx_3d = x + y * chunksize + z * chunksize * chunksize;
                         let iron_ore_depth = noise_3d[NOISE_IRON_ORE.id].data[idx_3d];
                         let copper_ore_depth = noise_3d[NOISE_COPPER_ORE.id].data[idx_3d];
                         let gold_ore_depth = noise_3d[NOISE_GOLD_ORE.id].data[idx_3d];
+                        let coal_ore_depth = noise_3d[NOISE_COAL_ORE.id].data[idx_3d];
+                        let ore_region = noise_3d[NOISE_ORE_REGION.id].data[idx_3d];
+                        let cave = noise_3d[NOISE_CAVE.id].data[idx_3d];
 
                         let mut ore_kind = STONE;
                         let mut chooser = |value, ore_type| {
-                            if value < 0 {
+                            // without ore regions every tile below 0 is ore
+                            let is_ore = if ore_regions {
+                                ore_region > ORE_REGION_THRESHOLD && value < ORE_DENSITY_THRESHOLD
+                            } else {
+                                value < 0
+                            };
+                            if is_ore {
                                 ore_kind = ore_type;
                             }
                         };
-                        // latter overwrites former
+                        // latter overwrites former, the common coal gives way to the others
+                        if coal {
+                            chooser(coal_ore_depth, COAL);
+                        }
                         chooser(copper_ore_depth, COPPER);
                         chooser(gold_ore_depth, GOLD);
                         chooser(iron_ore_depth, IRON);
-                        match ore_kind {
-                            IRON => self.iron_ore_count += 1,
-                            COPPER => self.copper_ore_count += 1,
-                            GOLD => self.gold_ore_count += 1,
-                            _ => (),
-                        }
 
-                        let z_level = u_to_i(chunk_z) as i16 * chunksize as i16 + z as i16;
-                        let distance = z_level as i16 - terrain_height;
+                        // i32, chunks far above or below the terrain are beyond i16
+                        let z_level = u_to_i(chunk_z) * chunksize as i32 + z as i32;
+                        // a river bed is below the terrain, its water one tile below the banks
+                        let distance = z_level - (terrain_height - river_depth) as i32;
                         let bg = if distance > 0 {
-                            if terrain_height <= 0 && z_level <= 0 {
+                            if terrain_height <= self.sea_level && z_level <= self.sea_level as i32
+                                || river_depth > 0 && z_level < terrain_height as i32
+                            {
                                 Some(WATER)
                             } else {
                                 None
                             }
                         } else if distance == 0 {
-                            if terrain_height >= 0 {
+                            if river_depth > 0 {
+                                Some(DIRT)
+                            } else if terrain_height >= self.sea_level {
                                 Some(GRASS)
                             } else {
                                 Some(DIRT)
                             }
-                        } else if distance < 0 && distance >= -soil_thickness {
+                        } else if distance < 0 && distance >= -(soil_thickness as i32) {
                             Some(DIRT)
+                        } else if caves && cave > CAVE_THRESHOLD {
+                            // the soil above keeps caves from opening at the surface
+                            None
                         } else {
                             Some(ore_kind)
                         };
-                        let fg = if bg == Some(GRASS) {
-                            if vegetation < 150 {
-                                match vegetation % 30 {
-                                    1 | 3 | 5 | 7 | 9 | 11 | 13 | 15 => Some(PINE_1_1),
-                                    20 | 23 => Some(OAK_1_1),
-                                    26 => Some(OAK_1_1_RED),
-                                    29 => Some(OAK_1_1_SMALL),
-                                    _ => None,
+                        // only the ore which is placed, not the ore in soil, caves or the air
+                        match bg {
+                            Some(IRON) => self.iron_ore_count += 1,
+                            Some(COPPER) => self.copper_ore_count += 1,
+                            Some(GOLD) => self.gold_ore_count += 1,
+                            Some(COAL) => self.coal_ore_count += 1,
+                            _ => (),
+                        }
+                        let fg =
+                            if bg == Some(GRASS) && self.vegetation_mode == VegetationMode::Hash {
+                                let hash = tile_hash(
+                                    u_to_i(chunk_x) * chunksize as i32 + x as i32,
+                                    u_to_i(chunk_y) * chunksize as i32 + y as i32,
+                                    self.noise.params_2d[NOISE_VEGETATION.id]
+                                        .seed
+                                        .wrapping_add(self.seed),
+                                );
+                                if hash % 1000 < self.vegetation_density {
+                                    // same mix as in the noise mode
+                                    match hash / 1000 % 15 {
+                                        0..=7 => Some(PINE_1_1),
+                                        8 | 9 => Some(OAK_1_1),
+                                        10 => Some(OAK_1_1_RED),
+                                        11 => Some(OAK_1_1_SMALL),
+                                        12 => Some(FLOWER1),
+                                        13 => Some(FLOWER2),
+                                        _ => Some(FLOWER3),
+                                    }
+                                } else {
+                                    None
                                 }
-                            } else {
-                                match vegetation - 150 {
-                                    1 => Some(FLOWER1),
-                                    5 => Some(FLOWER2),
-                                    10 => Some(FLOWER3),
-                                    _ => None,
+                            } else if bg == Some(GRASS) {
+                                if vegetation < 150 {
+                                    match vegetation % 30 {
+                                        1 | 3 | 5 | 7 | 9 | 11 | 13 | 15 => Some(PINE_1_1),
+                                        20 | 23 => Some(OAK_1_1),
+                                        26 => Some(OAK_1_1_RED),
+                                        29 => Some(OAK_1_1_SMALL),
+                                        _ => None,
+                                    }
+                                } else {
+                                    match vegetation - 150 {
+                                        1 => Some(FLOWER1),
+                                        5 => Some(FLOWER2),
+                                        10 => Some(FLOWER3),
+                                        _ => None,
+                                    }
                                 }
+                            } else {
+                                None
+                            };
+                        // deserts have sand without plants, in the tundra only pines grow
+                        let (bg, fg) = match (bg, biome) {
+                            (Some(GRASS), Biome::Desert) => (Some(SAND), None),
+                            (Some(GRASS), Biome::Tundra) => {
+                                (Some(SNOW), fg.filter(|&image_id| image_id == PINE_1_1))
                             }
+                            _ => (bg, fg),
+                        };
+                        let fg = if fg.is_none() && distance == 0 && river_depth == 0 {
+                            self.surface_resource(
+                                u_to_i(chunk_x) * chunksize as i32 + x as i32,
+                                u_to_i(chunk_y) * chunksize as i32 + y as i32,
+                                bg,
+                            )
                         } else {
-                            None
+                            fg
                         };
-                        tiles_x.push(Some(Tile { bg, fg }));
+                        tiles_x.push(Some(Tile {
+                            bg,
+                            fg,
+                            fg_rotation: 0,
+                            fg2: None,
+                        }));
                     }
                     tiles_y.push(tiles_x);
                 }
                 tiles_z.push(tiles_y);
             }
-            let mut chunk = self.get_chunk_generated_mut(chunk_x, chunk_y, chunk_z);
+            let mut chunk = Chunk::new();
             chunk.tiles = tiles_z;
+            self.chunks_generated
+                .insert((chunk_x, chunk_y, chunk_z), (chunk, self.uses));
         }
     }
 
+    // used by the tests and benches/set_range.rs, the editor sets tiles in bulk
+    #[allow(dead_code)]
     pub fn set(&mut self, x: i32, y: i32, z: i32, tile: Tile) {
+        self.set_range(&[(x, y, z, tile)]);
+    }
+    /// Set many tiles at once, every chunk is looked up only once. Returns the number of tiles
+    /// which changed, setting the same tile again does not count.
+    pub fn set_range(&mut self, tiles: &[(i32, i32, i32, Tile)]) -> usize {
+        let mut changed = 0;
+        if let Some(scratch) = &mut self.scratch {
+            for &(x, y, z, tile) in tiles {
+                if scratch.insert((x, y, z), tile) != Some(tile) {
+                    changed += 1;
+                }
+            }
+            return changed;
+        }
+        let mut by_chunk: HashMap<(i32, i32, i32), ChunkTiles> = HashMap::new();
+        for &(x, y, z, tile) in tiles {
+            if self.outside_world(x, y, z) {
+                continue;
+            }
+            let (chunk_x, rest_x) = chunkify(x);
+            let (chunk_y, rest_y) = chunkify(y);
+            let (chunk_z, rest_z) = chunkify(z);
+            by_chunk
+                .entry((chunk_x, chunk_y, chunk_z))
+                .or_default()
+                .push((rest_x, rest_y, rest_z, tile));
+        }
+        let now = Instant::now();
+        for (chunk_pos, chunk_tiles) in by_chunk {
+            let chunk = self
+                .chunks_modified
+                .entry(chunk_pos)
+                .or_insert_with(Chunk::new);
+            for (rest_x, rest_y, rest_z, tile) in chunk_tiles {
+                let pos = tile_pos(chunk_pos, (rest_x, rest_y, rest_z));
+                let old = chunk.get(rest_x, rest_y, rest_z);
+                // a held mouse button sets the same tiles every frame
+                if old == Some(tile) {
+                    continue;
+                }
+                changed += 1;
+                self.history.record(pos.0, pos.1, pos.2, old, Some(tile));
+                self.journal.record(pos.0, pos.1, pos.2, Some(tile));
+                chunk.set(rest_x, rest_y, rest_z, tile);
+                self.edit_times.insert(pos, now);
+            }
+        }
+        if now.duration_since(self.edit_times_expired) > EDIT_AGE_MAX {
+            self.edit_times
+                .retain(|_, time| now.duration_since(*time) < EDIT_AGE_MAX);
+            self.edit_times_expired = now;
+        }
+        changed
+    }
+    /// From now on set_range() only changes the scratch layer, until it is committed or discarded
+    pub fn scratch_begin(&mut self) {
+        if self.scratch.is_none() {
+            self.scratch = Some(HashMap::new());
+        }
+    }
+    /// Apply the scratch layer to the map as one undo step, returns the number of tiles
+    pub fn scratch_commit(&mut self) -> usize {
+        let scratch = self.scratch.take().unwrap_or_default();
+        let tiles: Vec<_> = scratch
+            .iter()
+            .map(|(&(x, y, z), &tile)| (x, y, z, tile))
+            .collect();
+        self.history.begin();
+        self.set_range(&tiles);
+        self.history.end();
+        scratch.len()
+    }
+    /// Forget the scratch layer, returns the number of tiles
+    pub fn scratch_discard(&mut self) -> usize {
+        self.scratch.take().map_or(0, |scratch| scratch.len())
+    }
+    /// Number of tiles in the scratch layer, None if it is not active
+    pub fn scratch_len(&self) -> Option<usize> {
+        self.scratch.as_ref().map(|scratch| scratch.len())
+    }
+    pub fn is_scratch(&self, x: i32, y: i32, z: i32) -> bool {
+        self.scratch
+            .as_ref()
+            .is_some_and(|scratch| scratch.contains_key(&(x, y, z)))
+    }
+
+    /// smallest and largest coordinates of all edited tiles, None if nothing is edited
+    pub fn edit_bounds(&self) -> Option<Bounds> {
+        let mut bounds: Option<Bounds> = None;
+        for (&chunk_pos, chunk) in &self.chunks_modified {
+            for (x, y, z, _) in chunk.iter() {
+                let (x, y, z) = tile_pos(chunk_pos, (x, y, z));
+                bounds = Some(match bounds {
+                    None => ((x, y, z), (x, y, z)),
+                    Some((min, max)) => (
+                        (min.0.min(x), min.1.min(y), min.2.min(z)),
+                        (max.0.max(x), max.1.max(y), max.2.max(z)),
+                    ),
+                });
+            }
+        }
+        bounds
+    }
+    /// time since the tile was set, None if it was not set in the last EDIT_AGE_MAX
+    pub fn edit_age(&self, x: i32, y: i32, z: i32) -> Option<Duration> {
+        self.edit_times
+            .get(&(x, y, z))
+            .map(|time| time.elapsed())
+            .filter(|&age| age < EDIT_AGE_MAX)
+    }
+    /// where get() takes the tile from
+    pub fn tile_source(&mut self, x: i32, y: i32, z: i32) -> &'static str {
+        if self
+            .scratch
+            .as_ref()
+            .is_some_and(|scratch| scratch.contains_key(&(x, y, z)))
+        {
+            "scratch"
+        } else if self.outside_world(x, y, z) {
+            "outside the world"
+        } else if self.get_modified(x, y, z).is_some() {
+            "edited"
+        } else {
+            "generated"
+        }
+    }
+    /// the edited tile, None if the tile is generated
+    fn get_modified(&self, x: i32, y: i32, z: i32) -> Option<Tile> {
+        let (chunk_x, rest_x) = chunkify(x);
+        let (chunk_y, rest_y) = chunkify(y);
+        let (chunk_z, rest_z) = chunkify(z);
+        self.chunks_modified
+            .get(&(chunk_x, chunk_y, chunk_z))
+            .and_then(|chunk| chunk.get(rest_x, rest_y, rest_z))
+    }
+    /// tile == None removes the edit, so the generated tile is shown again
+    fn set_modified(&mut self, x: i32, y: i32, z: i32, tile: Option<Tile>) {
         let (chunk_x, rest_x) = chunkify(x);
         let (chunk_y, rest_y) = chunkify(y);
         let (chunk_z, rest_z) = chunkify(z);
-        self.get_chunk_modified_mut(chunk_x, chunk_y, chunk_z)
-            .set(rest_x, rest_y, rest_z, tile);
+        let chunk = self
+            .chunks_modified
+            .entry((chunk_x, chunk_y, chunk_z))
+            .or_insert_with(Chunk::new);
+        if let Some(tile) = tile {
+            chunk.set(rest_x, rest_y, rest_z, tile);
+        } else {
+            chunk.clear(rest_x, rest_y, rest_z);
+        }
+    }
+    /// Revert the last stroke. Returns false if there is nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        let Some(stroke) = self.history.undo() else {
+            return false;
+        };
+        for &(x, y, z, old, _) in stroke.iter().rev() {
+            self.restore(x, y, z, old);
+        }
+        true
+    }
+    /// Apply the last undone stroke again. Returns false if there is nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        let Some(stroke) = self.history.redo() else {
+            return false;
+        };
+        for &(x, y, z, _, new) in &stroke {
+            self.restore(x, y, z, new);
+        }
+        true
+    }
+    /// set a tile of the history, tile == None removes the edit
+    fn restore(&mut self, x: i32, y: i32, z: i32, tile: Option<Tile>) {
+        self.set_modified(x, y, z, tile);
+        self.journal.record(x, y, z, tile);
     }
-    pub fn set_multi_fg(&mut self, x: i32, y: i32, z: i32, multi_image: MultiImage) {
+    /// Apply strokes from the journal of a crashed session, each one is an undo step
+    pub fn replay(&mut self, strokes: &[Vec<JournalEntry>]) {
+        for stroke in strokes {
+            self.history.begin();
+            for &(x, y, z, tile) in stroke {
+                if self.outside_world(x, y, z) {
+                    continue;
+                }
+                let old = self.get_modified(x, y, z);
+                self.history.record(x, y, z, old, tile);
+                self.restore(x, y, z, tile);
+            }
+            self.history.end();
+        }
+    }
+    /// returns the number of changed tiles, see set_range()
+    pub fn set_multi_fg(&mut self, x: i32, y: i32, z: i32, multi_image: MultiImage) -> usize {
+        let tiles = Self::multi_fg_tiles(x, y, z, &multi_image);
+        self.set_range(&tiles)
+    }
+    /// true if a cell of the multi-image already has another foreground object
+    pub fn multi_fg_blocked(&mut self, x: i32, y: i32, z: i32, multi_image: &MultiImage) -> bool {
+        Self::multi_fg_tiles(x, y, z, multi_image)
+            .into_iter()
+            .any(|(x, y, z, tile)| {
+                let fg = self.get(x, y, z).fg;
+                fg.is_some() && fg != tile.fg
+            })
+    }
+    /// The cells of the placed multi-image which the foreground of x,y,z is a part of, found
+    /// from the position of the image in its multi-image, see generate_multi_reverse_map().
+    /// Cells with another foreground do not belong to it. Empty if the foreground is not part
+    /// of a multi-image.
+    pub fn multi_fg_footprint(
+        &mut self,
+        x: i32,
+        y: i32,
+        z: i32,
+        multi_ids: &[Option<MultiImagePart>],
+        multi_images: &[MultiImage],
+    ) -> Vec<(i32, i32, i32)> {
+        let Some(part) = self.get(x, y, z).fg.and_then(|fg| multi_ids[fg as usize]) else {
+            return vec![];
+        };
+        let multi_image = &multi_images[part.image_id as usize];
+        let (origin_x, origin_y) = (x - part.dx, y - part.dy);
+        let mut cells = vec![];
+        for &image_id in &multi_image.image_ids {
+            let (image_x, image_y) = (image_id % IMAGES_X, image_id / IMAGES_X);
+            let (x, y) = (
+                origin_x + image_x as i32 - multi_image.min_x as i32,
+                origin_y + image_y as i32 - multi_image.min_y as i32,
+            );
+            if self.get(x, y, z).fg == Some(image_id) {
+                cells.push((x, y, z));
+            }
+        }
+        cells
+    }
+    fn multi_fg_tiles(
+        x: i32,
+        y: i32,
+        z: i32,
+        multi_image: &MultiImage,
+    ) -> Vec<(i32, i32, i32, Tile)> {
         let (dx, dy) = (multi_image.size_x as i32 / 2, multi_image.size_y as i32 / 2);
-        for image_id in multi_image.image_ids {
+        let mut tiles = vec![];
+        for &image_id in &multi_image.image_ids {
             let (image_x, image_y) = (image_id % IMAGES_X, image_id / IMAGES_X);
             let (x, y) = (
                 x - dx + image_x as i32 - multi_image.min_x as i32,
@@ -382,92 +1287,636 @@ impl Map {
             let tile = Tile {
                 bg: Some(GRASS),
                 fg: Some(image_id),
+                fg_rotation: 0,
+                fg2: None,
             };
-            self.set(x, y, z, tile);
+            tiles.push((x, y, z, tile));
         }
+        tiles
     }
 
-    fn get_chunk_modified_mut(
+    /// Generate the chunks up to `distance` chunks beyond the view in `direction` (-1, 0 or 1
+    /// for x and y), so they are ready before they get visible. view_min and view_max are
+    /// tile coordinates. Stops when `budget` is used up. Returns the number of generated chunks.
+    pub fn prefetch(
         &mut self,
-        chunk_x: usize,
-        chunk_y: usize,
-        chunk_z: usize,
-    ) -> &mut Chunk {
-        while self.chunks_modified.len() < chunk_z + 1 {
-            self.chunks_modified.push(vec![]);
+        view_min: (i32, i32, i32),
+        view_max: (i32, i32, i32),
+        direction: (i32, i32),
+        distance: i32,
+        budget: Duration,
+    ) -> usize {
+        let start = Instant::now();
+        let ahead = distance * Chunk::chunksize() as i32;
+        let limit = self.world_limit;
+        let clamp = |i: i32, direction: i32| {
+            i.saturating_add(direction.saturating_mul(ahead))
+                .clamp(-limit, limit)
+        };
+        let (min_x, _) = chunkify(clamp(view_min.0, direction.0.min(0)));
+        let (max_x, _) = chunkify(clamp(view_max.0, direction.0.max(0)));
+        let (min_y, _) = chunkify(clamp(view_min.1, direction.1.min(0)));
+        let (max_y, _) = chunkify(clamp(view_max.1, direction.1.max(0)));
+        let (min_z, _) = chunkify(clamp(view_min.2, 0));
+        let (max_z, _) = chunkify(clamp(view_max.2, 0));
+        let mut generated = 0;
+        let mut pending = 0;
+        self.prefetching = true;
+        for chunk_z in min_z..=max_z {
+            for chunk_y in min_y..=max_y {
+                for chunk_x in min_x..=max_x {
+                    let (chunk_x, chunk_y, chunk_z) =
+                        (i_to_u(chunk_x), i_to_u(chunk_y), i_to_u(chunk_z));
+                    if !self
+                        .chunks_generated
+                        .contains_key(&(chunk_x, chunk_y, chunk_z))
+                    {
+                        // out of time, the rest is only counted
+                        if start.elapsed() >= budget {
+                            pending += 1;
+                        } else {
+                            self.generate_noise(chunk_x, chunk_y, chunk_z);
+                            generated += 1;
+                        }
+                    }
+                }
+            }
+        }
+        self.prefetching = false;
+        self.prefetch_pending = pending;
+        generated
+    }
+
+    /// Replace `from` by `to` in all edited tiles. With include_generated the generated chunks
+    /// in memory are baked into edits as well. Returns the number of replaced tiles.
+    pub fn replace_all(&mut self, from: ImageId, to: ImageId, include_generated: bool) -> usize {
+        let replace = |tile: Tile| {
+            let swap =
+                |image_id: Option<ImageId>| image_id.map(|id| if id == from { to } else { id });
+            if tile.bg == Some(from) || tile.fg == Some(from) || tile.fg2 == Some(from) {
+                Some(Tile {
+                    bg: swap(tile.bg),
+                    fg: swap(tile.fg),
+                    fg_rotation: tile.fg_rotation,
+                    fg2: swap(tile.fg2),
+                })
+            } else {
+                None
+            }
+        };
+        let mut replaced = vec![];
+        for (&chunk_pos, chunk) in &self.chunks_modified {
+            for (x, y, z, tile) in chunk.iter() {
+                if let Some(tile) = replace(tile) {
+                    let (x, y, z) = tile_pos(chunk_pos, (x, y, z));
+                    replaced.push((x, y, z, tile));
+                }
+            }
+        }
+        if include_generated {
+            for (&(chunk_x, chunk_y, chunk_z), (chunk, _)) in &self.chunks_generated {
+                let chunk_pos = (u_to_i(chunk_x), u_to_i(chunk_y), u_to_i(chunk_z));
+                for (x, y, z, tile) in chunk.iter() {
+                    let pos = tile_pos(chunk_pos, (x, y, z));
+                    if self.get_modified(pos.0, pos.1, pos.2).is_none() {
+                        if let Some(tile) = replace(tile) {
+                            replaced.push((pos.0, pos.1, pos.2, tile));
+                        }
+                    }
+                }
+            }
         }
-        while self.chunks_modified[chunk_z].len() < chunk_y + 1 {
-            self.chunks_modified[chunk_z].push(vec![]);
+        self.set_range(&replaced);
+        replaced.len()
+    }
+
+    /// Check the edited chunks for anomalies, returns one message per issue
+    pub fn validate(&self) -> Vec<String> {
+        let cs = Chunk::chunksize();
+        let mut issues = vec![];
+        let mut keys: Vec<_> = self.chunks_modified.keys().copied().collect();
+        keys.sort_unstable_by_key(|&(x, y, z)| (z, y, x));
+        for chunk_pos in keys {
+            let chunk = &self.chunks_modified[&chunk_pos];
+            let (chunk_x, chunk_y, chunk_z) = chunk_pos;
+            if chunk.tiles.len() > cs {
+                issues.push(format!(
+                    "chunk {},{},{}: {} z levels, expected at most {}",
+                    chunk_x,
+                    chunk_y,
+                    chunk_z,
+                    chunk.tiles.len(),
+                    cs
+                ));
+            }
+            for (z, tiles_y) in chunk.tiles.iter().enumerate() {
+                if tiles_y.len() > cs {
+                    issues.push(format!(
+                        "chunk {},{},{} z {}: {} rows, expected at most {}",
+                        chunk_x,
+                        chunk_y,
+                        chunk_z,
+                        z,
+                        tiles_y.len(),
+                        cs
+                    ));
+                }
+                for (y, tiles_x) in tiles_y.iter().enumerate() {
+                    if tiles_x.len() > cs {
+                        issues.push(format!(
+                            "chunk {},{},{} z {} y {}: {} columns, expected at most {}",
+                            chunk_x,
+                            chunk_y,
+                            chunk_z,
+                            z,
+                            y,
+                            tiles_x.len(),
+                            cs
+                        ));
+                    }
+                }
+            }
+            for (x, y, z, tile) in chunk.iter() {
+                let (x, y, z) = tile_pos(chunk_pos, (x, y, z));
+                if tile.bg.is_none() && (tile.fg.is_some() || tile.fg2.is_some()) {
+                    issues.push(format!(
+                        "tile {},{},{}: foreground without background",
+                        x, y, z
+                    ));
+                }
+                for image_id in [tile.bg, tile.fg, tile.fg2].into_iter().flatten() {
+                    if image_id >= IMAGES_CNT {
+                        issues.push(format!(
+                            "tile {},{},{}: invalid image id {}",
+                            x, y, z, image_id
+                        ));
+                    }
+                }
+            }
         }
-        while self.chunks_modified[chunk_z][chunk_y].len() < chunk_x + 1 {
-            self.chunks_modified[chunk_z][chunk_y].push(Chunk::new());
+        issues
+    }
+
+    /// true if the chunk (signed chunk coordinates) contains edits
+    pub fn is_chunk_modified(&self, chunk_x: i32, chunk_y: i32, chunk_z: i32) -> bool {
+        self.chunks_modified
+            .get(&(chunk_x, chunk_y, chunk_z))
+            .is_some_and(|chunk| chunk.has_data())
+    }
+
+    /// chunks generated within `max_age`, older ones are forgotten
+    pub fn recently_generated(&mut self, max_age: Duration) -> &[GeneratedChunk] {
+        self.recently_generated
+            .retain(|generated| generated.time.elapsed() < max_age);
+        &self.recently_generated
+    }
+
+    /// Forget all generated chunks, e.g. after changing generation settings
+    pub fn clear_generated(&mut self) {
+        self.chunks_generated.clear();
+        self.debug_noise = None;
+    }
+
+    pub fn noise(&self) -> &NoiseConfig {
+        &self.noise
+    }
+    /// Change a parameter of the terrain height noise and generate the world again with it,
+    /// the edits stay
+    pub fn nudge_terrain_noise(&mut self, param: NoiseParam, steps: i32) {
+        self.noise.nudge_terrain(param, steps);
+        self.clear_generated();
+    }
+
+    /// Signed coordinates of the chunk containing the tile x,y,z
+    pub fn chunk_coords(x: i32, y: i32, z: i32) -> (i32, i32, i32) {
+        let (chunk_x, _) = chunkify(x);
+        let (chunk_y, _) = chunkify(y);
+        let (chunk_z, _) = chunkify(z);
+        (chunk_x, chunk_y, chunk_z)
+    }
+
+    /// Discard the generated data of the chunk containing the tile x,y,z, so the next get()
+    /// generates it again. With clear_modified the edits in that chunk are discarded as well.
+    pub fn regenerate_chunk(&mut self, x: i32, y: i32, z: i32, clear_modified: bool) {
+        let (chunk_x, _) = chunkify(x);
+        let (chunk_y, _) = chunkify(y);
+        let (chunk_z, _) = chunkify(z);
+        if clear_modified {
+            if let Some(chunk) = self.chunks_modified.remove(&(chunk_x, chunk_y, chunk_z)) {
+                self.removed_chunks.insert((chunk_x, chunk_y, chunk_z));
+                for (x, y, z, tile) in chunk.iter() {
+                    let (x, y, z) = tile_pos((chunk_x, chunk_y, chunk_z), (x, y, z));
+                    self.history.record(x, y, z, Some(tile), None);
+                }
+            }
+        }
+        let (chunk_x, chunk_y, chunk_z) = (i_to_u(chunk_x), i_to_u(chunk_y), i_to_u(chunk_z));
+        self.chunks_generated.remove(&(chunk_x, chunk_y, chunk_z));
+    }
+
+    /// Free generated chunks once there are more than max_resident_chunks, the least recently
+    /// used first. Chunks near the camera tile `center` are kept. They are generated again when
+    /// needed, the edits in chunks_modified are never evicted. Returns the number of evicted
+    /// chunks.
+    pub fn evict_chunks(&mut self, center: (i32, i32, i32)) -> usize {
+        if self.chunks_generated.len() <= self.max_resident_chunks {
+            return 0;
+        }
+        let center = Map::chunk_coords(center.0, center.1, center.2);
+        let near = |&(chunk_x, chunk_y, chunk_z): &(usize, usize, usize)| {
+            (u_to_i(chunk_x) - center.0).abs() <= EVICT_DISTANCE
+                && (u_to_i(chunk_y) - center.1).abs() <= EVICT_DISTANCE
+                && (u_to_i(chunk_z) - center.2).abs() <= EVICT_DISTANCE
+        };
+        let mut far: Vec<_> = self
+            .chunks_generated
+            .iter()
+            .filter(|(chunk_pos, _)| !near(chunk_pos))
+            .map(|(&chunk_pos, &(_, last_used))| (last_used, chunk_pos))
+            .collect();
+        far.sort_unstable();
+        far.truncate(self.chunks_generated.len() - self.max_resident_chunks);
+        for (_, chunk_pos) in &far {
+            self.chunks_generated.remove(chunk_pos);
+        }
+        far.len()
+    }
+
+    /// Write the table from scratch with all edited chunks
+    pub fn store_all(&mut self, db: &mut Db, table_name: &str) -> Result<(), Box<dyn Error>> {
+        self.take_all_rows(table_name).write(db)
+    }
+
+    /// The rows of the chunks which changed since the last save. They are appended to the
+    /// table, each chunk after a clear row which discards its older rows. The table is written
+    /// from scratch if it is another one than the last time, or once the appended chunks
+    /// outnumber the edited chunks.
+    /// Data format:
+    /// chunk_x,chunk_y,chunk_z,z,y,encoding,x0,x1,x2...xn where n is Chunk::chunksize()-1,
+    /// or runs of tiles instead of x0...xn, see also Chunk::parse_row()
+    pub fn take_rows(&mut self, table_name: &str) -> MapRows {
+        if self.stored_table.as_deref() != Some(table_name)
+            || self.appended_chunks > self.chunks_modified.len()
+        {
+            return self.take_all_rows(table_name);
+        }
+        let mut keys: Vec<_> = self
+            .chunks_modified
+            .iter()
+            .filter(|(_, chunk)| chunk.dirty)
+            .map(|(&chunk_pos, _)| chunk_pos)
+            .chain(self.removed_chunks.drain())
+            .collect();
+        keys.sort_unstable_by_key(|&(x, y, z)| (z, y, x));
+        keys.dedup();
+        let mut rows = vec![];
+        for (x, y, z) in keys {
+            rows.push(Chunk::clear_row(x, y, z));
+            if let Some(chunk) = self.chunks_modified.get_mut(&(x, y, z)) {
+                rows.extend(chunk.rows(x, y, z));
+                chunk.dirty = false;
+            }
+            self.appended_chunks += 1;
+        }
+        MapRows {
+            table_name: table_name.to_string(),
+            from_scratch: false,
+            rows,
+        }
+    }
+
+    fn take_all_rows(&mut self, table_name: &str) -> MapRows {
+        // sorted, so that saving the same map always gives the same table
+        let mut keys: Vec<_> = self.chunks_modified.keys().copied().collect();
+        keys.sort_unstable_by_key(|&(x, y, z)| (z, y, x));
+        let rows = keys
+            .into_iter()
+            .flat_map(|(x, y, z)| self.chunks_modified[&(x, y, z)].rows(x, y, z))
+            .collect();
+        for chunk in self.chunks_modified.values_mut() {
+            chunk.dirty = false;
+        }
+        self.removed_chunks.clear();
+        self.stored_table = Some(table_name.to_string());
+        self.appended_chunks = 0;
+        MapRows {
+            table_name: table_name.to_string(),
+            from_scratch: true,
+            rows,
+        }
+    }
+
+    /// Forget chunks whose edits were all reverted and rewrite the table from scratch, the
+    /// result is the same as store_all(). Returns the number of rows before and after.
+    pub fn compact(
+        &mut self,
+        db: &mut Db,
+        table_name: &str,
+    ) -> Result<(usize, usize), Box<dyn Error>> {
+        let rows_before = db.select_from(table_name).map_or(0, |rows| rows.len());
+        self.chunks_modified
+            .retain(|_, chunk| chunk.iter().next().is_some());
+        self.store_all(db, table_name)?;
+        let rows_after = db.select_from(table_name)?.len();
+        Ok((rows_before, rows_after))
+    }
+
+    /// Write the background images of the z-slice between min and max (x, y, inclusive) as
+    /// CSV: one line per y, one image id per x, empty for tiles without background.
+    /// Returns the number of tiles with a background.
+    pub fn export_csv_slice(
+        &mut self,
+        path: &str,
+        min: (i32, i32),
+        max: (i32, i32),
+        z: i32,
+    ) -> Result<usize, Box<dyn Error>> {
+        let mut csv = String::new();
+        let mut count = 0;
+        for y in min.1..=max.1 {
+            let fields: Vec<String> = (min.0..=max.0)
+                .map(|x| match self.get(x, y, z).bg {
+                    Some(image_id) => {
+                        count += 1;
+                        image_id.to_string()
+                    }
+                    None => String::new(),
+                })
+                .collect();
+            csv += &fields.join(",");
+            csv += "\n";
+        }
+        std::fs::write(path, csv)?;
+        Ok(count)
+    }
+    /// Set the backgrounds of a CSV written by export_csv_slice(), the first field is put at
+    /// origin. Empty fields leave the tile unchanged, rows may have different lengths, the
+    /// foreground is kept. Returns the number of set tiles.
+    pub fn import_csv_slice(
+        &mut self,
+        path: &str,
+        origin_x: i32,
+        origin_y: i32,
+        z: i32,
+    ) -> Result<usize, Box<dyn Error>> {
+        let csv = std::fs::read_to_string(path)?;
+        let mut tiles = vec![];
+        for (row, line) in csv.lines().enumerate() {
+            for (column, field) in line.split(',').enumerate() {
+                let field = field.trim();
+                if field.is_empty() {
+                    continue;
+                }
+                let image_id = match field.parse::<ImageId>() {
+                    Ok(image_id) if image_id < IMAGES_CNT => image_id,
+                    _ => {
+                        return Err(Box::new(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("{}:{}: invalid image id {}", path, row + 1, field),
+                        )))
+                    }
+                };
+                let (x, y) = (origin_x + column as i32, origin_y + row as i32);
+                let tile = self.get(x, y, z);
+                tiles.push((
+                    x,
+                    y,
+                    z,
+                    Tile {
+                        bg: Some(image_id),
+                        ..tile
+                    },
+                ));
+            }
         }
-        &mut self.chunks_modified[chunk_z][chunk_y][chunk_x]
+        self.history.begin();
+        self.set_range(&tiles);
+        self.history.end();
+        Ok(tiles.len())
     }
 
-    fn get_chunk_generated_mut(
+    /// Copy the tiles inside `bounds` (min and max corner, inclusive) into a new database as
+    /// a world of its own. x and y are shifted so that the min corner becomes 0,0, z is kept.
+    /// Generated tiles are copied as well, as the new world would generate different ones.
+    /// Returns the number of copied tiles.
+    pub fn export_region_as_world(
         &mut self,
-        chunk_x: usize,
-        chunk_y: usize,
-        chunk_z: usize,
-    ) -> &mut Chunk {
-        while self.chunks_generated.len() < chunk_z + 1 {
-            self.chunks_generated.push(vec![]);
+        db_name: &str,
+        db_dir: &str,
+        table_name: &str,
+        bounds: ((i32, i32, i32), (i32, i32, i32)),
+    ) -> Result<usize, Box<dyn Error>> {
+        let (min, max) = bounds;
+        let mut tiles = vec![];
+        for z in min.2..=max.2 {
+            for y in min.1..=max.1 {
+                for x in min.0..=max.0 {
+                    tiles.push((x - min.0, y - min.1, z, self.get(x, y, z)));
+                }
+            }
         }
-        while self.chunks_generated[chunk_z].len() < chunk_y + 1 {
-            self.chunks_generated[chunk_z].push(vec![]);
+        let mut region = Map::new();
+        region.set_range(&tiles);
+        let mut db = Db::create(db_name, db_dir)?;
+        region.store_all(&mut db, table_name)?;
+        db.save()?;
+        Ok(tiles.len())
+    }
+
+    /// Replace the area of tiles connected to x,y at height z, in the 4 directions, by
+    /// `image_id`. A background replaces the backgrounds of the tiles with the same background,
+    /// an object the objects of the tiles with the same background and object. Returns the
+    /// number of replaced tiles, None if the area is larger than FLOOD_FILL_MAX_TILES and
+    /// nothing was changed. `is_bg` tells if the image is a background, see TileAtlas. The fill
+    /// is one undo step.
+    pub fn flood_fill(
+        &mut self,
+        x: i32,
+        y: i32,
+        z: i32,
+        image_id: ImageId,
+        is_bg: bool,
+    ) -> Option<usize> {
+        let start = self.get(x, y, z);
+        let same = |tile: Tile| tile.bg == start.bg && (is_bg || tile.fg == start.fg);
+        let mut visited = HashSet::from([(x, y)]);
+        let mut todo = vec![(x, y)];
+        let mut tiles = vec![];
+        while let Some((x, y)) = todo.pop() {
+            if tiles.len() >= FLOOD_FILL_MAX_TILES {
+                return None;
+            }
+            let tile = self.get(x, y, z);
+            tiles.push((
+                x,
+                y,
+                z,
+                if is_bg {
+                    Tile {
+                        bg: Some(image_id),
+                        ..tile
+                    }
+                } else {
+                    Tile {
+                        fg: Some(image_id),
+                        ..tile
+                    }
+                },
+            ));
+            for next in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)] {
+                if !visited.contains(&next) && same(self.get(next.0, next.1, z)) {
+                    visited.insert(next);
+                    todo.push(next);
+                }
+            }
         }
-        while self.chunks_generated[chunk_z][chunk_y].len() < chunk_x + 1 {
-            self.chunks_generated[chunk_z][chunk_y].push(Chunk::new());
+        self.history.begin();
+        self.set_range(&tiles);
+        self.history.end();
+        Some(tiles.len())
+    }
+
+    /// Number of tiles per background image inside `bounds` (min and max corner, inclusive),
+    /// empty tiles are not counted
+    pub fn count_tiles(
+        &mut self,
+        bounds: ((i32, i32, i32), (i32, i32, i32)),
+    ) -> HashMap<ImageId, usize> {
+        let (min, max) = bounds;
+        let mut counts = HashMap::new();
+        for z in min.2..=max.2 {
+            for y in min.1..=max.1 {
+                for x in min.0..=max.0 {
+                    if let Some(image_id) = self.get(x, y, z).bg {
+                        *counts.entry(image_id).or_insert(0) += 1;
+                    }
+                }
+            }
         }
-        &mut self.chunks_generated[chunk_z][chunk_y][chunk_x]
+        counts
     }
 
-    /// Store the map in the database.
-    /// Data format:
-    /// chunk_x,chunk_y,chunk_z,z,y,x0,x1,x2...xn where n is Chunk::chunksize()-1
-    /// see also chunk::store()
-    pub fn store(&self, db: &mut Db, table_name: &str) -> Result<(), Box<dyn Error>> {
-        db.create_or_replace_table(table_name)?;
-        db.create_column(table_name, "chunk_x")?;
-        db.create_column(table_name, "chunk_y")?;
-        db.create_column(table_name, "chunk_z")?;
-        db.create_column(table_name, "z")?;
-        db.create_column(table_name, "y")?;
-        for i in 0..Chunk::chunksize() {
-            db.create_column(table_name, &format!("bg{i}"))?;
-            db.create_column(table_name, &format!("fg{i}"))?;
-        }
-
-        for (z, chunk_z) in self.chunks_modified.iter().enumerate() {
-            for (y, chunk_y) in chunk_z.iter().enumerate() {
-                for (x, chunk_x) in chunk_y.iter().enumerate() {
-                    let (x, y, z) = (u_to_i(x), u_to_i(y), u_to_i(z));
-                    chunk_x.store(db, table_name, x, y, z)?;
+    /// One pixel per tile with the color of the topmost tile, for all chunks stored in the
+    /// table. `colors` are the colors of the images, see image::image_colors().
+    /// Returns the image and the tile coordinates of its upper left corner, or an error if
+    /// the stored chunks span more than OVERVIEW_MAX_SIZE tiles in x or y.
+    pub fn render_overview(
+        &mut self,
+        db: &mut Db,
+        table_name: &str,
+        colors: &[Color],
+    ) -> Result<(Image, (i32, i32)), Box<dyn Error>> {
+        let rows = db.select_from(table_name)?;
+        let mut min = (i32::MAX, i32::MAX, i32::MAX);
+        let mut max = (i32::MIN, i32::MIN, i32::MIN);
+        for row in &rows {
+            if let (Data::Int(chunk_x), Data::Int(chunk_y), Data::Int(chunk_z)) =
+                (row.select_at(0)?, row.select_at(1)?, row.select_at(2)?)
+            {
+                let (chunk_x, chunk_y, chunk_z) = (chunk_x as i32, chunk_y as i32, chunk_z as i32);
+                min = (min.0.min(chunk_x), min.1.min(chunk_y), min.2.min(chunk_z));
+                max = (max.0.max(chunk_x), max.1.max(chunk_y), max.2.max(chunk_z));
+            }
+        }
+        if min.0 > max.0 {
+            return Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "no stored chunks",
+            )));
+        }
+        let cs = Chunk::chunksize() as i32;
+        // in i64, chunks near the world limit are too far apart for i32
+        let span = |min: i32, max: i32| (max as i64 - min as i64 + 1) * cs as i64;
+        let (width, height) = (span(min.0, max.0), span(min.1, max.1));
+        if width > OVERVIEW_MAX_SIZE || height > OVERVIEW_MAX_SIZE {
+            return Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "{}x{} tiles, larger than {}x{}",
+                    width, height, OVERVIEW_MAX_SIZE, OVERVIEW_MAX_SIZE
+                ),
+            )));
+        }
+        let (width, height) = (width as i32, height as i32);
+        let (min_x, min_y) = (min.0 * cs, min.1 * cs);
+        // the generated terrain is between min_value and max_value, edits can be anywhere
+        let terrain_height = self.noise.params_2d[NOISE_TERRAIN_HEIGHT.id];
+        let top_z = ((max.2 + 1) * cs - 1).max(terrain_height.max_value as i32);
+        let bottom_z = (min.2 * cs).min(terrain_height.min_value as i32);
+        let mut image = Image::new(width as u32, height as u32);
+        for y in 0..height {
+            for x in 0..width {
+                for z in (bottom_z..=top_z).rev() {
+                    let tile = self.get(min_x + x, min_y + y, z);
+                    if let Some(image_id) = tile.fg.or(tile.bg) {
+                        // safe: x and y are inside the image
+                        unsafe { image.set_pixel(x as u32, y as u32, colors[image_id as usize]) };
+                        break;
+                    }
                 }
             }
         }
-        Ok(())
+        Ok((image, (min_x, min_y)))
+    }
+
+    /// Check that the table was stored in a layout this version reads, before any row is
+    /// parsed: a newer format or other columns would give a garbled map.
+    /// `format` is the stored map_format, see MAP_FORMAT.
+    pub fn check_schema(db: &mut Db, table_name: &str, format: i64) -> Result<(), Box<dyn Error>> {
+        let incompatible = |reason: String| -> Result<(), Box<dyn Error>> {
+            Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("table {} {}; incompatible format", table_name, reason),
+            )))
+        };
+        if format > MAP_FORMAT {
+            return incompatible(format!(
+                "has map format {}, expected {} or older",
+                format, MAP_FORMAT
+            ));
+        }
+        // worlds from before map_format was stored have one of the older layouts
+        let columns = db.get_column_names(table_name)?;
+        if (1..=MAP_FORMAT).any(|format| columns == map_columns(format)) {
+            return Ok(());
+        }
+        let expected = map_columns(format.max(1));
+        let mismatch = columns
+            .iter()
+            .zip(&expected)
+            .find(|(column, name)| column != name);
+        if let Some((column, name)) = mismatch {
+            return incompatible(format!("has a column {}, expected {}", column, name));
+        }
+        incompatible(format!(
+            "has {} columns, expected {}",
+            columns.len(),
+            expected.len()
+        ))
     }
     pub fn parse_table(&mut self, db: &mut Db, table_name: &str) -> Result<(), Box<dyn Error>> {
         let rows = db.select_from(table_name)?;
+        // older formats have fewer columns, see check_schema()
+        let columns = rows
+            .first()
+            .map_or(0, |row| column_count(row, 6 + 3 * Chunk::chunksize()));
         let make_error = |s: &str| -> Result<(), Box<dyn Error>> {
             Err(Box::new(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
                 s,
             )))
         };
+        let mut appended_chunks = 0;
         for row in &rows {
             if let Data::Int(chunk_x) = row.select_at(0)? {
                 if let Data::Int(chunk_y) = row.select_at(1)? {
                     if let Data::Int(chunk_z) = row.select_at(2)? {
-                        let (chunk_x, chunk_y, chunk_z) = (
-                            i_to_u(chunk_x as i32),
-                            i_to_u(chunk_y as i32),
-                            i_to_u(chunk_z as i32),
-                        );
-                        let chunk = self.get_chunk_modified_mut(chunk_x, chunk_y, chunk_z);
-                        chunk.parse_row(row)?;
+                        let key = (chunk_x as i32, chunk_y as i32, chunk_z as i32);
+                        let chunk = self.chunks_modified.entry(key).or_insert_with(Chunk::new);
+                        chunk.parse_row(row, columns)?;
+                        if Chunk::is_clear_row(row, columns) {
+                            appended_chunks += 1;
+                        }
                     } else {
                         return make_error("chunk_z is not an int");
                     }
@@ -478,6 +1927,686 @@ impl Map {
                 return make_error("chunk_x is not an int");
             }
         }
+        for chunk in self.chunks_modified.values_mut() {
+            chunk.dirty = false;
+        }
+        // tables of older formats are rewritten by the next take_rows()
+        if columns == 6 + 3 * Chunk::chunksize() {
+            self.stored_table = Some(table_name.to_string());
+            self.appended_chunks = appended_chunks;
+        }
+        Ok(())
+    }
+
+    /// Store the noise parameters of the world, so it is generated the same way after the
+    /// compiled defaults changed. Floats are stored as text, which reads back exactly.
+    /// Data format:
+    /// name,seed,frequency,octaves,lacunarity,noise_min,noise_max,min_value,max_value,rounding
+    /// rounding is "floor" or "truncate", see NoiseConfig::floor
+    pub fn store_noise_params(&self, db: &mut Db, table_name: &str) -> Result<(), Box<dyn Error>> {
+        db.create_or_replace_table(table_name)?;
+        for column in [
+            "name",
+            "seed",
+            "frequency",
+            "octaves",
+            "lacunarity",
+            "noise_min",
+            "noise_max",
+            "min_value",
+            "max_value",
+            "rounding",
+        ] {
+            db.create_column(table_name, column)?;
+        }
+        let rounding = if self.noise.floor {
+            "floor"
+        } else {
+            "truncate"
+        };
+        for params in self
+            .noise
+            .params_2d
+            .iter()
+            .chain(&self.noise.params_3d)
+            .filter(|params| self.noise.generates(params))
+        {
+            db.insert_data(
+                table_name,
+                vec![
+                    Data::String(params.name.to_string()),
+                    Data::Int(params.seed as i64),
+                    Data::String(params.frequency.to_string()),
+                    Data::Int(params.octaves as i64),
+                    Data::String(params.lacunarity.to_string()),
+                    Data::String(params.noise_min.to_string()),
+                    Data::String(params.noise_max.to_string()),
+                    Data::Int(params.min_value as i64),
+                    Data::Int(params.max_value as i64),
+                    Data::String(rounding.to_string()),
+                ],
+            )?;
+        }
         Ok(())
     }
+    /// Use the stored noise parameters instead of the compiled defaults. Noises which are not
+    /// stored were added after the world was created, their passes stay off so the world does
+    /// not change. Worlds saved before the parameters were stored have no table, they only
+    /// have the FIRST_NOISES and truncate their noise values, as they did then.
+    pub fn parse_noise_params(
+        &mut self,
+        db: &mut Db,
+        table_name: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let all_noises = self.noise.params_2d.iter().chain(&self.noise.params_3d);
+        let names: Vec<&'static str> = all_noises.map(|params| params.name).collect();
+        let Ok(rows) = db.select_from(table_name) else {
+            self.noise.floor = false;
+            self.noise.disabled = names
+                .into_iter()
+                .filter(|name| !FIRST_NOISES.contains(name))
+                .collect();
+            return Ok(());
+        };
+        let mut disabled = names;
+        let invalid = |name: &str| -> Box<dyn Error> {
+            Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid noise parameters: {}", name),
+            ))
+        };
+        for row in &rows {
+            let Data::String(name) = row.select_at(0)? else {
+                return Err(invalid("name is not a string"));
+            };
+            let int = |idx: usize| match row.select_at(idx) {
+                Ok(Data::Int(value)) => Ok(value),
+                _ => Err(invalid(&name)),
+            };
+            let float = |idx: usize| match row.select_at(idx) {
+                Ok(Data::String(value)) => value.parse::<f32>().map_err(|_| invalid(&name)),
+                _ => Err(invalid(&name)),
+            };
+            let Some(params) = self
+                .noise
+                .params_2d
+                .iter_mut()
+                .chain(&mut self.noise.params_3d)
+                .find(|params| params.name == name)
+            else {
+                println!("unknown noise {} in {}, ignored", name, table_name);
+                continue;
+            };
+            disabled.retain(|&disabled_name| disabled_name != name);
+            params.seed = int(1)? as i32;
+            params.frequency = float(2)?;
+            params.octaves = int(3)? as u8;
+            params.lacunarity = float(4)?;
+            params.noise_min = float(5)?;
+            params.noise_max = float(6)?;
+            params.min_value = int(7)? as i16;
+            params.max_value = int(8)? as i16;
+            // stored before the rounding was, which was rounded down then
+            self.noise.floor = match row.select_at(9) {
+                Ok(Data::String(rounding)) => rounding != "truncate",
+                _ => true,
+            };
+        }
+        self.noise.disabled = disabled;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn test_db() -> Db {
+        Db::create("w8_test", &std::env::temp_dir().to_string_lossy()).unwrap()
+    }
+
+    fn tile(n: usize) -> Tile {
+        Tile {
+            bg: Some(STONE),
+            fg: Some(n as ImageId),
+            fg_rotation: (n % 4) as u8,
+            fg2: None,
+        }
+    }
+
+    /// tiles on both sides of the chunk borders at -1/0 and -chunksize, and far out in the
+    /// negative range, each with another tile
+    fn negative_tiles() -> BTreeMap<(i32, i32, i32), Tile> {
+        let cs = Chunk::chunksize() as i32;
+        let mut tiles = BTreeMap::new();
+        for i in [-1000, -cs - 1, -cs, -1, 0, cs - 1, cs] {
+            for pos in [(i, -1, 0), (-1, i, -1), (0, -1, i), (i, i, i)] {
+                let n = tiles.len();
+                tiles.insert(pos, tile(n));
+            }
+        }
+        tiles
+    }
+
+    #[test]
+    fn chunkify_splits_negative_coordinates() {
+        let cs = Chunk::chunksize() as i32;
+        assert_eq!(chunkify(0), (0, 0));
+        assert_eq!(chunkify(-1), (-1, cs as usize - 1));
+        assert_eq!(chunkify(-cs), (-1, 0));
+        assert_eq!(chunkify(-cs - 1), (-2, cs as usize - 1));
+        for i in [-cs - 1, -cs, -1, 0, cs - 1, cs] {
+            let (chunk, rest) = chunkify(i);
+            assert_eq!(
+                tile_pos((chunk, chunk, chunk), (rest, rest, rest)),
+                (i, i, i)
+            );
+            assert_eq!(u_to_i(i_to_u(chunk)), chunk);
+        }
+    }
+
+    #[test]
+    fn chunkify_at_the_i32_limits() {
+        let cs = Chunk::chunksize() as i32;
+        assert_eq!(chunkify(i32::MAX), (i32::MAX / cs, cs as usize - 1));
+        assert_eq!(chunkify(i32::MIN), (i32::MIN / cs, 0));
+        assert_eq!(chunkify(i32::MIN + 1), (i32::MIN / cs, 1));
+        for i in [i32::MAX, i32::MAX - 1, i32::MIN, i32::MIN + 1] {
+            let (chunk, rest) = chunkify(i);
+            assert_eq!(tile_pos((chunk, 0, 0), (rest, 0, 0)).0, i);
+            assert_eq!(u_to_i(i_to_u(chunk)), chunk);
+        }
+    }
+
+    #[test]
+    fn coordinates_near_the_i32_limits_are_outside_the_world() {
+        let mut map = Map::new();
+        let empty = Tile {
+            bg: None,
+            fg: None,
+            fg_rotation: 0,
+            fg2: None,
+        };
+        for i in [i32::MAX, i32::MAX - 1, i32::MIN, i32::MIN + 1] {
+            for (x, y, z) in [(i, 0, 0), (0, i, 0), (0, 0, i), (i, i, i)] {
+                assert!(map.outside_world(x, y, z));
+                assert_eq!(map.get(x, y, z), empty);
+                map.set(x, y, z, tile(1));
+            }
+        }
+        // nothing was allocated for them
+        assert!(map.chunks_modified.is_empty());
+        assert!(map.chunks_generated.is_empty());
+
+        // just inside the limit only the chunk of the tile is generated
+        let inside = WORLD_LIMIT - 1;
+        map.get(inside, inside, 0);
+        map.get(-inside, 0, inside);
+        assert_eq!(map.chunks_generated.len(), 2);
+
+        // even without a limit i32::MIN is outside, its absolute value is larger
+        map.world_limit = i32::MAX;
+        assert!(!map.outside_world(i32::MAX, i32::MIN + 1, 0));
+        assert!(map.outside_world(i32::MIN, 0, 0));
+    }
+
+    #[test]
+    fn get_returns_set_tiles_at_negative_coordinates() {
+        let mut map = Map::new();
+        let tiles = negative_tiles();
+        for (&(x, y, z), &tile) in &tiles {
+            map.set(x, y, z, tile);
+        }
+        for (&(x, y, z), &tile) in &tiles {
+            assert_eq!(map.get(x, y, z), tile, "at {},{},{}", x, y, z);
+        }
+        // the neighbors across the -1/0 border are not touched
+        assert_eq!(map.tile_source(-2, -1, 0), "generated");
+        assert_eq!(map.tile_source(1, -1, 0), "generated");
+    }
+
+    #[test]
+    fn set_range_counts_only_changed_tiles() {
+        let mut map = Map::new();
+        let tiles: Vec<_> = (0..10).map(|x| (x, 0, 0, tile(1))).collect();
+        assert_eq!(map.set_range(&tiles), 10);
+        // a held mouse button sets the same tiles every frame
+        assert_eq!(map.set_range(&tiles), 0);
+        assert_eq!(map.set_range(&[(0, 0, 0, tile(2)), (1, 0, 0, tile(1))]), 1);
+    }
+
+    #[test]
+    fn setting_the_same_tile_again_is_not_an_edit() {
+        let mut map = Map::new();
+        let tiles: Vec<_> = (0..10).map(|x| (x, 0, 0, tile(1))).collect();
+        map.history.begin();
+        map.set_range(&tiles);
+        // as if the chunk was saved
+        map.chunks_modified.get_mut(&(0, 0, 0)).unwrap().dirty = false;
+        for _ in 0..3 {
+            map.set_range(&tiles);
+        }
+        assert!(!map.chunks_modified[&(0, 0, 0)].dirty);
+        assert_eq!(map.history.undo().unwrap().len(), 10);
+    }
+
+    #[test]
+    fn flood_fill_is_one_undo_step() {
+        let mut map = Map::new();
+        // a 4x3 area of dirt inside a ring of stone, high above the terrain
+        let z = 100;
+        let dirt = Tile {
+            bg: Some(DIRT),
+            fg: None,
+            fg_rotation: 0,
+            fg2: None,
+        };
+        let stone = Tile {
+            bg: Some(STONE),
+            ..dirt
+        };
+        let mut tiles = vec![];
+        for y in -1..=3 {
+            for x in -1..=4 {
+                let ring = x == -1 || x == 4 || y == -1 || y == 3;
+                tiles.push((x, y, z, if ring { stone } else { dirt }));
+            }
+        }
+        map.set_range(&tiles);
+        let undo_steps = map.history.len();
+
+        let filled = map.flood_fill(0, 0, z, GRASS, true);
+        assert_eq!(filled, Some(12));
+        assert_eq!(map.history.len(), undo_steps + 1);
+        for y in 0..3 {
+            for x in 0..4 {
+                assert_eq!(map.get(x, y, z).bg, Some(GRASS));
+            }
+        }
+
+        assert!(map.undo());
+        for &(x, y, z, tile) in &tiles {
+            assert_eq!(map.get(x, y, z), tile, "at {},{},{}", x, y, z);
+        }
+        assert_eq!(map.history.len(), undo_steps);
+    }
+
+    /// all values of all rows of a table
+    fn table_data(db: &Db, table_name: &str) -> Vec<Vec<Data>> {
+        let columns = 6 + 3 * Chunk::chunksize();
+        db.select_from(table_name)
+            .unwrap()
+            .iter()
+            .map(|row| {
+                (0..columns)
+                    .map(|idx| row.select_at(idx).unwrap())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compacted_table_parses_to_the_same_map_as_a_full_store() {
+        let mut db = test_db();
+        let mut map = Map::new();
+        let mut edited = vec![];
+        // many strokes with a save after each one, some of them undone again
+        for stroke in 0..20 {
+            map.history.begin();
+            for i in 0..10 {
+                let pos = (stroke * 7 - 70, i * 5 - 20, i - 5);
+                map.set(pos.0, pos.1, pos.2, tile(stroke as usize));
+                edited.push(pos);
+            }
+            map.history.end();
+            if stroke % 3 == 2 {
+                map.undo();
+            }
+            map.take_rows("appended").write(&mut db).unwrap();
+        }
+        let (rows_before, rows_after) = map.compact(&mut db, "appended").unwrap();
+        assert!(rows_after < rows_before);
+
+        let mut full = Map::new();
+        full.parse_table(&mut db, "appended").unwrap();
+        full.store_all(&mut db, "full").unwrap();
+        assert_eq!(table_data(&db, "appended"), table_data(&db, "full"));
+
+        let mut compacted = Map::new();
+        compacted.parse_table(&mut db, "appended").unwrap();
+        for &(x, y, z) in &edited {
+            assert_eq!(
+                compacted.get(x, y, z),
+                map.get(x, y, z),
+                "at {},{},{}",
+                x,
+                y,
+                z
+            );
+            assert_eq!(compacted.tile_source(x, y, z), map.tile_source(x, y, z));
+        }
+    }
+
+    #[test]
+    fn overview_of_a_too_large_world_is_an_error() {
+        let mut db = test_db();
+        let mut map = Map::new();
+        map.set(-1_000_000, 0, 0, tile(1));
+        map.set(1_000_000, 0, 0, tile(1));
+        map.store_all(&mut db, "map").unwrap();
+        let result = map.render_overview(&mut db, "map", &[]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn store_and_parse_table_round_trip_at_negative_coordinates() {
+        let mut db = test_db();
+        let mut map = Map::new();
+        let tiles = negative_tiles();
+        for (&(x, y, z), &tile) in &tiles {
+            map.set(x, y, z, tile);
+        }
+        map.store_all(&mut db, "map").unwrap();
+
+        let mut loaded = Map::new();
+        loaded.parse_table(&mut db, "map").unwrap();
+        for (&(x, y, z), &tile) in &tiles {
+            assert_eq!(loaded.get(x, y, z), tile, "at {},{},{}", x, y, z);
+        }
+        assert_eq!(loaded.tile_source(-2, -1, 0), "generated");
+    }
+
+    #[test]
+    fn appended_rows_round_trip_at_negative_coordinates() {
+        let mut db = test_db();
+        let mut map = Map::new();
+        let tiles = negative_tiles();
+        for (&(x, y, z), &tile) in &tiles {
+            map.set(x, y, z, tile);
+        }
+        map.take_rows("map").write(&mut db).unwrap();
+        // change the tiles on one side of the border, the other side keeps its rows
+        let changed = tile(999);
+        map.set(-1, -1, 0, changed);
+        map.set(-1, -1, -1, changed);
+        map.take_rows("map").write(&mut db).unwrap();
+
+        let mut loaded = Map::new();
+        loaded.parse_table(&mut db, "map").unwrap();
+        for (&(x, y, z), &tile) in &tiles {
+            let expected = if (x, y) == (-1, -1) && (z == 0 || z == -1) {
+                changed
+            } else {
+                tile
+            };
+            assert_eq!(loaded.get(x, y, z), expected, "at {},{},{}", x, y, z);
+        }
+    }
+
+    #[test]
+    fn terrain_height_continues_across_chunk_borders() {
+        let cs = Chunk::chunksize();
+        let mut map = Map::new();
+        let terrain = map.noise.params_2d[NOISE_TERRAIN_HEIGHT.id];
+        for chunk_x in [-2, -1, 0, 5] {
+            let left = map.noise_2d(i_to_u(chunk_x), 0);
+            let right = map.noise_2d(i_to_u(chunk_x + 1), 0);
+            // one sample spanning both chunks
+            let (data, _, _) = simdnoise::NoiseBuilder::fbm_2d_offset(
+                (chunk_x * cs as i32) as f32,
+                2 * cs,
+                0.0,
+                cs,
+            )
+            .with_freq(terrain.frequency)
+            .with_octaves(terrain.octaves)
+            .with_lacunarity(terrain.lacunarity)
+            .with_seed(terrain.seed.wrapping_add(map.seed))
+            .generate();
+            let height =
+                |x: usize, y: usize| map.terrace(terrain.value(data[x + y * 2 * cs], true));
+            for y in 0..cs {
+                let last = left[NOISE_TERRAIN_HEIGHT.id].data[cs - 1 + y * cs];
+                let first = right[NOISE_TERRAIN_HEIGHT.id].data[y * cs];
+                assert_eq!(last, height(cs - 1, y), "chunk {} row {}", chunk_x, y);
+                assert_eq!(first, height(cs, y), "chunk {} row {}", chunk_x + 1, y);
+            }
+        }
+    }
+
+    #[test]
+    fn worlds_saved_without_noise_parameters_truncate() {
+        // scaled to -0.5 and 0.5, which truncate to 0 both
+        let noise = NOISE_TERRAIN_HEIGHT;
+        let range = noise.noise_max - noise.noise_min;
+        let steps = (noise.max_value - noise.min_value) as f32;
+        let at = |value: f32| noise.noise_min + (value - noise.min_value as f32) / steps * range;
+        assert_eq!(noise.value(at(-0.5), true), -1);
+        assert_eq!(noise.value(at(-0.5), false), 0);
+        assert_eq!(noise.value(at(0.5), true), 0);
+        assert_eq!(noise.value(at(0.5), false), 0);
+
+        let mut db = test_db();
+        let mut map = Map::new();
+        map.parse_noise_params(&mut db, "noise").unwrap();
+        assert!(!map.noise.floor);
+        // and keep truncating once the parameters are stored
+        map.store_noise_params(&mut db, "noise").unwrap();
+        let mut reloaded = Map::new();
+        reloaded.parse_noise_params(&mut db, "noise").unwrap();
+        assert!(!reloaded.noise.floor);
+    }
+
+    /// generated tiles of a box around the origin which spans a few chunks in each direction
+    fn generated_tiles(map: &mut Map) -> Vec<Tile> {
+        let mut tiles = vec![];
+        for z in -12..20 {
+            for y in (-40..40).step_by(3) {
+                for x in (-40..40).step_by(3) {
+                    tiles.push(map.get(x, y, z));
+                }
+            }
+        }
+        tiles
+    }
+
+    #[test]
+    fn stored_noise_parameters_outlast_changed_defaults() {
+        let mut db = test_db();
+        let mut map = Map::new();
+        map.store_noise_params(&mut db, "noise").unwrap();
+        let expected = generated_tiles(&mut map);
+
+        // a later version with other compiled defaults
+        let changed_defaults = || {
+            let mut map = Map::new();
+            map.noise.params_2d[NOISE_TERRAIN_HEIGHT.id].frequency *= 1.5;
+            map.noise.params_2d[NOISE_SOIL_THICKNESS.id].max_value += 2;
+            map.noise.params_3d[NOISE_IRON_ORE.id].seed += 1;
+            map
+        };
+        assert_ne!(generated_tiles(&mut changed_defaults()), expected);
+        let mut reloaded = changed_defaults();
+        reloaded.parse_noise_params(&mut db, "noise").unwrap();
+        assert_eq!(generated_tiles(&mut reloaded), expected);
+    }
+
+    #[test]
+    fn worlds_saved_without_noise_parameters_get_no_later_passes() {
+        let mut db = test_db();
+        let mut map = Map::new();
+        map.parse_noise_params(&mut db, "noise").unwrap();
+        let tiles = generated_tiles(&mut map);
+        // no biomes, caves or coal
+        for tile in &tiles {
+            assert!(!matches!(tile.bg, Some(SAND) | Some(SNOW) | Some(COAL)));
+        }
+        // which a new world has
+        let mut new_map = Map::new();
+        assert_ne!(generated_tiles(&mut new_map), tiles);
+        // and the passes stay off once the parameters are stored
+        map.store_noise_params(&mut db, "noise").unwrap();
+        let mut reloaded = Map::new();
+        reloaded.parse_noise_params(&mut db, "noise").unwrap();
+        assert_eq!(generated_tiles(&mut reloaded), tiles);
+    }
+
+    #[test]
+    fn noises_missing_from_the_stored_parameters_are_off() {
+        let mut db = test_db();
+        let mut map = Map::new();
+        map.noise.disabled = vec!["cave", "coal_ore"];
+        map.store_noise_params(&mut db, "noise").unwrap();
+        let mut reloaded = Map::new();
+        reloaded.parse_noise_params(&mut db, "noise").unwrap();
+        assert_eq!(reloaded.noise.disabled, vec!["cave", "coal_ore"]);
+        assert_eq!(generated_tiles(&mut reloaded), generated_tiles(&mut map));
+    }
+
+    #[test]
+    fn tables_with_other_columns_fail_the_schema_check() {
+        let mut db = test_db();
+        Map::new().store_all(&mut db, "map").unwrap();
+        Map::check_schema(&mut db, "map", MAP_FORMAT).unwrap();
+        // the layouts of older formats are read as well
+        for format in 1..MAP_FORMAT {
+            let table_name = format!("format{}", format);
+            db.create_or_replace_table(&table_name).unwrap();
+            for column in map_columns(format) {
+                db.create_column(&table_name, &column).unwrap();
+            }
+            Map::check_schema(&mut db, &table_name, format).unwrap();
+        }
+
+        // as many columns as a map table, with other names
+        db.create_or_replace_table("other").unwrap();
+        for column in map_columns(MAP_FORMAT) {
+            db.create_column("other", &column.replace("fg", "x"))
+                .unwrap();
+        }
+        let err = Map::check_schema(&mut db, "other", MAP_FORMAT).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "table other has a column x0, expected fg0; incompatible format"
+        );
+        db.create_or_replace_table("short").unwrap();
+        for column in ["chunk_x", "chunk_y", "chunk_z"] {
+            db.create_column("short", column).unwrap();
+        }
+        let err = Map::check_schema(&mut db, "short", MAP_FORMAT).unwrap_err();
+        assert!(err.to_string().contains("has 3 columns"), "{}", err);
+    }
+
+    #[test]
+    fn undo_and_redo_restore_every_tile_of_a_stroke() {
+        let mut map = Map::new();
+        map.set(1, 2, 3, tile(1));
+        let generated = map.get(5, 2, 3);
+        // a stroke which paints some tiles twice
+        map.history.begin();
+        for x in 0..10 {
+            map.set(x, 2, 3, tile(2));
+        }
+        for x in 0..10 {
+            map.set(x, 2, 3, tile(3));
+        }
+        map.history.end();
+        // tiles set outside of begin() and end() are not recorded
+        assert_eq!(map.history.len(), 1);
+
+        assert!(map.undo());
+        assert_eq!(map.get(1, 2, 3), tile(1));
+        assert_eq!(map.get(5, 2, 3), generated);
+        assert_eq!(map.tile_source(5, 2, 3), "generated");
+        assert!(map.redo());
+        for x in 0..10 {
+            assert_eq!(map.get(x, 2, 3), tile(3));
+        }
+        assert!(!map.redo());
+        // the history ends at max_strokes
+        map.history.max_strokes = 2;
+        for n in 4..8 {
+            map.history.begin();
+            map.set(0, 0, 0, tile(n));
+            map.history.end();
+        }
+        assert_eq!(map.history.len(), 2);
+        assert!(map.undo() && map.undo() && !map.undo());
+        assert_eq!(map.get(0, 0, 0), tile(5));
+    }
+
+    #[test]
+    fn eviction_frees_the_least_recently_used_chunks_away_from_the_camera() {
+        let cs = Chunk::chunksize() as i32;
+        let mut map = Map::new();
+        for chunk_x in [100, 101, 0, 102, 1, 103] {
+            map.get(chunk_x * cs, 0, 0);
+        }
+        // coming back to a chunk keeps it
+        map.get(100 * cs, 0, 0);
+        let resident = |map: &Map| -> Vec<i32> {
+            let mut chunks: Vec<_> = map
+                .chunks_generated
+                .keys()
+                .map(|&(x, _, _)| u_to_i(x))
+                .collect();
+            chunks.sort();
+            chunks
+        };
+        map.max_resident_chunks = 4;
+        assert_eq!(map.evict_chunks((0, 0, 0)), 2);
+        assert_eq!(resident(&map), [0, 1, 100, 103]);
+        map.max_resident_chunks = 3;
+        assert_eq!(map.evict_chunks((0, 0, 0)), 1);
+        assert_eq!(resident(&map), [0, 1, 100]);
+        // chunks near the camera stay, even above the limit
+        map.max_resident_chunks = 1;
+        assert_eq!(map.evict_chunks((0, 0, 0)), 1);
+        assert_eq!(resident(&map), [0, 1]);
+    }
+
+    #[test]
+    fn malformed_chunks_are_reported_with_their_coordinates() {
+        let cs = Chunk::chunksize();
+        let mut map = Map::new();
+        let insert = |map: &mut Map, chunk_pos, tiles| {
+            let mut chunk = Chunk::new();
+            chunk.tiles = tiles;
+            map.chunks_modified.insert(chunk_pos, chunk);
+        };
+        insert(&mut map, (1, 0, 0), vec![vec![]; cs + 1]);
+        insert(&mut map, (2, 0, 0), vec![vec![vec![]; cs + 1]]);
+        insert(&mut map, (3, 0, 0), vec![vec![vec![None; cs + 1]]]);
+        let no_bg = Tile {
+            bg: None,
+            fg: Some(FLOWER1),
+            fg_rotation: 0,
+            fg2: None,
+        };
+        let invalid_id = Tile {
+            bg: Some(IMAGES_CNT),
+            fg: None,
+            ..no_bg
+        };
+        map.set(-14, 3, 4, no_bg);
+        map.set(5, -20, 6, invalid_id);
+        // a valid edit is no issue
+        map.set(0, 0, 0, tile(1));
+        assert_eq!(
+            map.validate(),
+            [
+                format!("tile 5,-20,6: invalid image id {}", IMAGES_CNT),
+                "tile -14,3,4: foreground without background".to_string(),
+                format!("chunk 1,0,0: {} z levels, expected at most {}", cs + 1, cs),
+                format!("chunk 2,0,0 z 0: {} rows, expected at most {}", cs + 1, cs),
+                format!(
+                    "chunk 3,0,0 z 0 y 0: {} columns, expected at most {}",
+                    cs + 1,
+                    cs
+                ),
+            ]
+        );
+    }
 }
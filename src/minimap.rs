@@ -0,0 +1,106 @@
+use sfml::graphics::{
+    Color, Image, PrimitiveType, RenderStates, RenderTarget, RenderWindow, Texture, Vertex,
+};
+use sfml::system::Vector2f;
+use sfml::SfBox;
+
+use crate::map::Map;
+
+/// width and height of the area around the camera in tiles
+const MINIMAP_TILES: i32 = 128;
+/// size of a tile on the screen in pixels
+const MINIMAP_TILE_PIXELS: f32 = 2.0;
+/// the colors are sampled again every this many frames
+const MINIMAP_UPDATE_FRAMES: u32 = 10;
+
+/// Overview of the area around the camera in the upper right corner, one pixel of a texture
+/// per tile in the color of its surface. Clicking it moves the camera.
+pub struct Minimap {
+    pub visible: bool,
+    texture: Option<SfBox<Texture>>,
+    origin: Vector2f,   // upper left corner in window coordinates
+    center: (i32, i32), // world position shown in the middle
+    frames: u32,        // frames since the last update
+}
+impl Minimap {
+    pub fn new() -> Self {
+        Minimap {
+            visible: false,
+            texture: None,
+            origin: Vector2f::new(0., 0.),
+            center: (0, 0),
+            frames: MINIMAP_UPDATE_FRAMES,
+        }
+    }
+
+    /// Sample the background of the topmost tile of every column between z and z-depth+1
+    /// around `center`, `colors` are the colors of the images by ImageId.
+    /// Does nothing for most frames, as sampling is expensive.
+    pub fn update(
+        &mut self,
+        map: &mut Map,
+        center: (i32, i32, i32),
+        depth: i32,
+        colors: &[Color],
+        window_size: Vector2f,
+    ) {
+        self.frames = self.frames.saturating_add(1);
+        if !self.visible || self.frames < MINIMAP_UPDATE_FRAMES {
+            return;
+        }
+        self.frames = 0;
+        let size = MINIMAP_TILES as f32 * MINIMAP_TILE_PIXELS;
+        self.origin = Vector2f::new(window_size.x - size - MINIMAP_TILE_PIXELS * 4.0, 0.);
+        self.center = (center.0, center.1);
+        let mut image = Image::new(MINIMAP_TILES as u32, MINIMAP_TILES as u32);
+        let half = MINIMAP_TILES / 2;
+        for y in 0..MINIMAP_TILES {
+            for x in 0..MINIMAP_TILES {
+                let (pos_x, pos_y) = (center.0 - half + x, center.1 - half + y);
+                let color = if (x, y) == (half, half) {
+                    Color::RED
+                } else {
+                    map.surface_height(pos_x, pos_y, center.2, depth)
+                        .and_then(|pos_z| map.get(pos_x, pos_y, pos_z).bg)
+                        .map_or(Color::BLACK, |image_id| colors[image_id as usize])
+                };
+                // safe: x and y are inside the image
+                unsafe { image.set_pixel(x as u32, y as u32, color) };
+            }
+        }
+        self.texture = Texture::from_image(&image);
+    }
+
+    pub fn draw(&self, window: &mut RenderWindow) {
+        let Some(texture) = self.texture.as_ref().filter(|_| self.visible) else {
+            return;
+        };
+        let size = MINIMAP_TILES as f32;
+        let quad: Vec<_> = [(0., 0.), (1., 0.), (1., 1.), (0., 1.)]
+            .iter()
+            .map(|&(x, y)| Vertex {
+                position: self.origin + Vector2f::new(x, y) * size * MINIMAP_TILE_PIXELS,
+                color: Color::WHITE,
+                tex_coords: Vector2f::new(x, y) * size,
+            })
+            .collect();
+        let mut rs = RenderStates::default();
+        rs.set_texture(Some(texture));
+        window.draw_primitives(&quad, PrimitiveType::QUADS, &rs);
+    }
+
+    /// world position of a window position on the minimap, None if it is outside
+    pub fn world_pos(&self, win_pos: Vector2f) -> Option<(i32, i32)> {
+        if !self.visible || self.texture.is_none() {
+            return None;
+        }
+        let offset = (win_pos - self.origin) / MINIMAP_TILE_PIXELS;
+        let (x, y) = (offset.x.floor() as i32, offset.y.floor() as i32);
+        if (0..MINIMAP_TILES).contains(&x) && (0..MINIMAP_TILES).contains(&y) {
+            let half = MINIMAP_TILES / 2;
+            Some((self.center.0 - half + x, self.center.1 - half + y))
+        } else {
+            None
+        }
+    }
+}
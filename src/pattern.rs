@@ -0,0 +1,42 @@
+use crate::tile::Tile;
+
+/// A small block of tiles which is repeated over a region, e.g. a 2x2 checker of two tiles
+pub struct Pattern {
+    pub width: i32,
+    pub height: i32,
+    tiles: Vec<Option<Tile>>, // row by row, None leaves the cell unchanged
+}
+impl Pattern {
+    pub fn new(width: i32, height: i32, tiles: Vec<Option<Tile>>) -> Self {
+        assert_eq!(tiles.len(), (width * height) as usize);
+        Pattern {
+            width,
+            height,
+            tiles,
+        }
+    }
+    /// tile at a world position, the pattern starts at `origin` and repeats in all directions
+    pub fn tile_at(&self, x: i32, y: i32, origin: (i32, i32)) -> Option<Tile> {
+        let px = (x - origin.0).rem_euclid(self.width);
+        let py = (y - origin.1).rem_euclid(self.height);
+        self.tiles[(px + py * self.width) as usize]
+    }
+    /// the tiles covering the rectangle between min and max at height z
+    pub fn fill(
+        &self,
+        min: (i32, i32),
+        max: (i32, i32),
+        z: i32,
+        origin: (i32, i32),
+    ) -> Vec<(i32, i32, i32, Tile)> {
+        let mut tiles = vec![];
+        for y in min.1..=max.1 {
+            for x in min.0..=max.0 {
+                if let Some(tile) = self.tile_at(x, y, origin) {
+                    tiles.push((x, y, z, tile));
+                }
+            }
+        }
+        tiles
+    }
+}
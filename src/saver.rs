@@ -0,0 +1,99 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use rzdb::Db;
+
+use crate::map::MapRows;
+
+/// Writes the map rows and saves the database on a thread of its own, so the main loop does
+/// not wait for the disk. The database is handed to the thread for each save and comes back
+/// when the save is done, only one save is in flight at a time.
+pub struct Saver {
+    db: Option<Db>, // None while the thread saves it
+    requests: Option<Sender<(Db, MapRows)>>,
+    results: Receiver<(Db, Result<(), String>)>,
+    thread: Option<JoinHandle<()>>,
+    finished: bool, // a save finished since the last call of finished()
+}
+impl Saver {
+    pub fn new(db: Db) -> Self {
+        let (requests, thread_requests) = mpsc::channel::<(Db, MapRows)>();
+        let (thread_results, results) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            for (mut db, rows) in thread_requests {
+                // errors are not Send, they are passed on as text
+                let result = rows
+                    .write(&mut db)
+                    .and_then(|()| db.save())
+                    .map_err(|err| err.to_string());
+                if thread_results.send((db, result)).is_err() {
+                    break;
+                }
+            }
+        });
+        Saver {
+            db: Some(db),
+            requests: Some(requests),
+            results,
+            thread: Some(thread),
+            finished: false,
+        }
+    }
+    /// true while a save is in flight, checks whether it is done
+    pub fn is_saving(&mut self) -> bool {
+        if self.db.is_none() {
+            if let Ok(result) = self.results.try_recv() {
+                self.receive(result);
+            }
+        }
+        self.db.is_none()
+    }
+    /// true once after each save which finished
+    pub fn finished(&mut self) -> bool {
+        _ = self.is_saving();
+        std::mem::take(&mut self.finished)
+    }
+    /// The database, waits for the save in flight
+    pub fn db(&mut self) -> &mut Db {
+        if self.db.is_none() {
+            let result = self.results.recv().expect("the saving thread stopped");
+            self.receive(result);
+        }
+        self.db.as_mut().unwrap()
+    }
+    /// Write the rows to the database and save it on the thread. The other tables have to be
+    /// stored into db() before.
+    pub fn save(&mut self, rows: MapRows) {
+        _ = self.db();
+        let db = self.db.take().unwrap();
+        if let Some(requests) = &self.requests {
+            requests
+                .send((db, rows))
+                .expect("the saving thread stopped");
+        }
+    }
+    fn receive(&mut self, (db, result): (Db, Result<(), String>)) {
+        if let Err(err) = result {
+            panic!(" {}", err);
+        }
+        self.db = Some(db);
+        self.finished = true;
+    }
+}
+impl Drop for Saver {
+    /// finish the save in flight before the program ends
+    fn drop(&mut self) {
+        if self.db.is_none() {
+            if let Ok((db, result)) = self.results.recv() {
+                if let Err(err) = result {
+                    println!("{}", err);
+                }
+                self.db = Some(db);
+            }
+        }
+        self.requests = None;
+        if let Some(thread) = self.thread.take() {
+            _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rzdb::Db;
+use sfml::graphics::{Color, Image, Texture};
+use sfml::SfBox;
+
+use crate::config::Config;
+use crate::map::Map;
+
+pub const SLOT_COUNT: u8 = 5;
+/// the world without --map, its tables have the plain names
+pub const DEFAULT_WORLD: &str = "generated_map";
+/// table with the slot of the last session
+const TABLE_SLOTS: &str = "slots";
+/// table with the names of all worlds and the time of their last save
+const TABLE_WORLDS: &str = "worlds";
+/// width and height of the thumbnails in tiles, one pixel per tile
+const THUMBNAIL_TILES: i32 = 64;
+const THUMBNAIL_PATH: &str = "w8_thumbnail.png";
+/// the tables of a world besides the map, see table_name()
+const TABLE_BASES: [&str; 8] = [
+    "stats",
+    "brushes",
+    "bookmarks",
+    "labels",
+    "config",
+    "map_meta",
+    TABLE_SLOTS,
+    TABLE_WORLDS,
+];
+
+/// A saved world as listed on the slot screen
+pub struct SlotInfo {
+    pub slot: u8,
+    pub saved_at: Option<u64>, // seconds since the epoch, None for an empty slot
+    pub thumbnail: Option<SfBox<Texture>>,
+}
+
+/// Name of a table of a world in a slot. The map table is named like the world, the other
+/// tables of worlds besides the default one start with the world name. Slot 1 uses the plain
+/// names, so worlds saved before slots existed are in slot 1.
+pub fn table_name(world: &str, base: &str, slot: u8) -> String {
+    let name = match (world, base) {
+        (_, "map") => world.to_string(),
+        (DEFAULT_WORLD, _) => base.to_string(),
+        _ => format!("{}_{}", world, base),
+    };
+    match slot {
+        1 => name,
+        _ => format!("{}_slot{}", name, slot),
+    }
+}
+
+/// Check a world name given with --map. The map table of a world is named like it, so it must
+/// not be the name of a table of the default world. The other tables start with the name and
+/// "_", names with "_" could take the tables of another world.
+pub fn check_world_name(world: &str) -> Result<(), String> {
+    if world == DEFAULT_WORLD {
+        Ok(())
+    } else if world.is_empty() || !world.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Err(format!(
+            "invalid world name \"{}\", use only letters and digits",
+            world
+        ))
+    } else if TABLE_BASES.contains(&world) {
+        Err(format!(
+            "invalid world name \"{}\", the default world has a table of that name",
+            world
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Name of a file of a world in a slot in the working directory, see table_name()
+pub fn file_path(world: &str, base: &str, slot: u8) -> String {
+    let base = match world {
+        DEFAULT_WORLD => base.to_string(),
+        _ => format!("{}_{}", world, base),
+    };
+    match (slot, base.rsplit_once('.')) {
+        (1, _) => base,
+        (_, Some((name, extension))) => format!("{}_slot{}.{}", name, slot, extension),
+        (_, None) => format!("{}_slot{}", base, slot),
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+/// the slot of the last session of a world, 1 if there is none
+pub fn last_slot(db: &mut Db, world: &str) -> u8 {
+    let mut slots = Config::new();
+    _ = slots.parse_table(db, &table_name(world, TABLE_SLOTS, 1));
+    slots.get("last_slot", 1).clamp(1, SLOT_COUNT as i64) as u8
+}
+/// Remember the slot for the next session and the world in the list of worlds
+pub fn store_last_slot(
+    db: &mut Db,
+    world: &str,
+    slot: u8,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut slots = Config::new();
+    slots.set("last_slot", slot as i64);
+    slots.store(db, &table_name(world, TABLE_SLOTS, 1))?;
+    let mut worlds = Config::new();
+    _ = worlds.parse_table(db, TABLE_WORLDS);
+    worlds.set(world, now() as i64);
+    worlds.store(db, TABLE_WORLDS)
+}
+/// names of the saved worlds, the most recently saved first
+pub fn worlds(db: &mut Db) -> Vec<String> {
+    let mut worlds = Config::new();
+    _ = worlds.parse_table(db, TABLE_WORLDS);
+    let mut names: Vec<_> = worlds.values().collect();
+    names.sort_unstable_by_key(|&(_, saved_at)| std::cmp::Reverse(saved_at));
+    names
+        .into_iter()
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+/// All slots with the time of their last save, taken from their config tables. Slots without
+/// a map table are empty.
+pub fn list(db: &mut Db, world: &str) -> Vec<SlotInfo> {
+    (1..=SLOT_COUNT)
+        .map(|slot| {
+            let saved_at = db
+                .select_from(&table_name(world, "map", slot))
+                .ok()
+                .map(|_| {
+                    let mut config = Config::new();
+                    _ = config.parse_table(db, &table_name(world, "config", slot));
+                    config.get("saved_at", 0) as u64
+                });
+            SlotInfo {
+                slot,
+                saved_at,
+                thumbnail: saved_at
+                    .and_then(|_| Texture::from_file(&file_path(world, THUMBNAIL_PATH, slot))),
+            }
+        })
+        .collect()
+}
+
+/// Save the topmost tiles between z and z-depth+1 around `center` as the thumbnail of a slot.
+/// `colors` are the colors of the images, see image::image_colors().
+pub fn save_thumbnail(
+    map: &mut Map,
+    center: (i32, i32, i32),
+    depth: i32,
+    colors: &[Color],
+    world: &str,
+    slot: u8,
+) {
+    let mut image = Image::new(THUMBNAIL_TILES as u32, THUMBNAIL_TILES as u32);
+    let half = THUMBNAIL_TILES / 2;
+    for y in 0..THUMBNAIL_TILES {
+        for x in 0..THUMBNAIL_TILES {
+            let mut color = Color::BLACK;
+            for z in (center.2 - depth + 1..=center.2).rev() {
+                let tile = map.get(center.0 - half + x, center.1 - half + y, z);
+                if let Some(image_id) = tile.fg.or(tile.bg) {
+                    color = colors[image_id as usize];
+                    break;
+                }
+            }
+            // safe: x and y are inside the image
+            unsafe { image.set_pixel(x as u32, y as u32, color) };
+        }
+    }
+    if !image.save_to_file(&file_path(world, THUMBNAIL_PATH, slot)) {
+        println!("could not save the thumbnail of slot {}", slot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables_of_valid_world_names_do_not_collide() {
+        let worlds = [DEFAULT_WORLD, "island", "Map2", "generated", "slot2", "map"];
+        // the list of worlds is shared by all of them
+        let bases = TABLE_BASES.iter().filter(|&&base| base != TABLE_WORLDS);
+        let mut names = std::collections::HashSet::new();
+        for world in worlds {
+            check_world_name(world).unwrap();
+            for slot in 1..=SLOT_COUNT {
+                for base in bases.clone().chain(&["map"]) {
+                    let name = table_name(world, base, slot);
+                    assert!(names.insert(name.clone()), "{}", name);
+                }
+            }
+        }
+        assert!(!names.contains(TABLE_WORLDS));
+        for world in [
+            "",
+            "stats",
+            "map_meta",
+            "worlds",
+            "island_stats",
+            "a b",
+            "x/y",
+        ] {
+            assert!(check_world_name(world).is_err(), "{}", world);
+        }
+    }
+}
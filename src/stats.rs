@@ -0,0 +1,78 @@
+use std::error::Error;
+
+use rzdb::{Data, Db};
+
+/// Cumulative statistics of a world, accumulated over all sessions
+pub struct Stats {
+    pub tiles_painted: i64,
+    pub tiles_erased: i64,
+    pub sessions: i64,
+    pub edit_time_ms: i64,
+}
+impl Stats {
+    pub fn new() -> Self {
+        Stats {
+            tiles_painted: 0,
+            tiles_erased: 0,
+            sessions: 0,
+            edit_time_ms: 0,
+        }
+    }
+
+    /// Store the statistics in the database.
+    /// Data format:
+    /// name,value
+    pub fn store(&self, db: &mut Db, table_name: &str) -> Result<(), Box<dyn Error>> {
+        db.create_or_replace_table(table_name)?;
+        db.create_column(table_name, "name")?;
+        db.create_column(table_name, "value")?;
+        for (name, value) in [
+            ("tiles_painted", self.tiles_painted),
+            ("tiles_erased", self.tiles_erased),
+            ("sessions", self.sessions),
+            ("edit_time_ms", self.edit_time_ms),
+        ] {
+            db.insert_data(
+                table_name,
+                vec![Data::String(name.to_string()), Data::Int(value)],
+            )?;
+        }
+        Ok(())
+    }
+    pub fn parse_table(&mut self, db: &mut Db, table_name: &str) -> Result<(), Box<dyn Error>> {
+        let rows = db.select_from(table_name)?;
+        let make_error = |s: &str| -> Result<(), Box<dyn Error>> {
+            Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                s,
+            )))
+        };
+        for row in &rows {
+            if let (Data::String(name), Data::Int(value)) = (row.select_at(0)?, row.select_at(1)?) {
+                match name.as_str() {
+                    "tiles_painted" => self.tiles_painted = value,
+                    "tiles_erased" => self.tiles_erased = value,
+                    "sessions" => self.sessions = value,
+                    "edit_time_ms" => self.edit_time_ms = value,
+                    _ => return make_error(&format!("unknown stat: {}", name)),
+                }
+            } else {
+                return make_error("stats row is not name,int");
+            }
+        }
+        Ok(())
+    }
+
+    pub fn message(&self) -> String {
+        let seconds = self.edit_time_ms / 1000;
+        format!(
+            "painted: {}, erased: {}, sessions: {}, edit time: {}:{:02}:{:02}",
+            self.tiles_painted,
+            self.tiles_erased,
+            self.sessions,
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    }
+}
@@ -2,4 +2,6 @@
 pub struct Tile {
     pub bg: Option<u16>, // background image id, e.g. grass, dirt, stone, water, floor, etc.
     pub fg: Option<u16>, // foreground image id, e.g. tree, flower, etc.
+    pub fg_rotation: u8, // quarter turns clockwise of the foreground image
+    pub fg2: Option<u16>, // second foreground image id drawn above fg, e.g. a flower on a path
 }